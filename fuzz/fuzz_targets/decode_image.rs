@@ -0,0 +1,12 @@
+#![no_main]
+
+use dvr::image::Image;
+use libfuzzer_sys::fuzz_target;
+
+// Run with `cargo fuzz run decode_image` from the repository root
+fuzz_target!(|data: &[u8]| {
+	if let Ok(image) = Image::decode(data) {
+		let (width, height) = image.get_size();
+		assert_eq!(image.pixels().len(), width as usize * height as usize * 4);
+	}
+});
@@ -0,0 +1,96 @@
+use crate::effects::{EffectQuad, TextEffects};
+
+/// A quad of a batch (see Dvr::create_quad_batch()), which is drawn from a texture
+/// rectangle like Dvr::draw_with_effects() draws it.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) struct BatchQuad {
+	/// The centre of the quad, relative to where the batch is drawn.
+	pub pos: (f32, f32),
+	pub size: (f32, f32),
+	/// The texture rectangle, in texture pixels.
+	pub tex_rect: ((f32, f32), (f32, f32)),
+	/// Everything outside this part of the texture is treated as empty.
+	pub clamp_rect: ((f32, f32), (f32, f32)),
+}
+
+impl BatchQuad {
+	/// A quad for a texture rectangle that is grown by the margin of the effects, so that
+	/// there is room for them.
+	pub fn new(pos: (f32, f32), size: (f32, f32), tex_rect: ((f32, f32), (f32, f32)), effects: &TextEffects) -> BatchQuad {
+		let quad = EffectQuad::new(effects, size, tex_rect);
+		BatchQuad {
+			pos,
+			size: quad.size,
+			tex_rect: quad.tex_rect,
+			clamp_rect: quad.clamp_rect,
+		}
+	}
+}
+
+/// The number of floats in a vertex: the position, the texture coordinates and the clamp
+/// rectangle (min and max), where the last two are in texture pixels.
+pub(crate) const VERTEX_FLOATS: usize = 8;
+
+/// The vertices of the quads, as two clockwise triangles per quad.
+pub(crate) fn batch_vertices(quads: &[BatchQuad]) -> Vec<f32> {
+	let mut vertices = Vec::with_capacity(quads.len() * 6 * VERTEX_FLOATS);
+	for quad in quads {
+		let ((x, y), (w, h)) = (quad.pos, quad.size);
+		let ((tx, ty), (tw, th)) = quad.tex_rect;
+		let ((cx, cy), (cw, ch)) = quad.clamp_rect;
+		// Dvr coordinates go up but texture coordinates go down
+		let top_left = [x - 0.5 * w, y + 0.5 * h, tx, ty];
+		let top_right = [x + 0.5 * w, y + 0.5 * h, tx + tw, ty];
+		let bottom_right = [x + 0.5 * w, y - 0.5 * h, tx + tw, ty + th];
+		let bottom_left = [x - 0.5 * w, y - 0.5 * h, tx, ty + th];
+		for corner in [top_left, top_right, bottom_right, top_left, bottom_right, bottom_left] {
+			vertices.extend_from_slice(&corner);
+			vertices.extend_from_slice(&[cx, cy, cx + cw, cy + ch]);
+		}
+	}
+	vertices
+}
+
+/// How many Dvr units a texture pixel of the quads covers, which the effects need. The
+/// quads of a batch are expected to be drawn at the same scale (e.g. glyphs of one size).
+pub(crate) fn batch_scale(quads: &[BatchQuad]) -> f32 {
+	quads
+		.iter()
+		.find(|quad| quad.tex_rect.1.0 > 0.0 && quad.tex_rect.1.1 > 0.0)
+		.map(|quad| 0.5 * (quad.size.0 / quad.tex_rect.1.0 + quad.size.1 / quad.tex_rect.1.1))
+		.unwrap_or(1.0)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn vertices_of_quads() {
+		let quad = BatchQuad::new((10.0, 20.0), (4.0, 6.0), ((1.0, 2.0), (2.0, 3.0)), &TextEffects::default());
+		let vertices = batch_vertices(&[quad, quad]);
+		assert_eq!(vertices.len(), 2 * 6 * VERTEX_FLOATS);
+		let corner = |i: usize| &vertices[i * VERTEX_FLOATS..(i + 1) * VERTEX_FLOATS];
+		assert_eq!(corner(0), &[8.0, 23.0, 1.0, 2.0, 1.0, 2.0, 3.0, 5.0]);
+		assert_eq!(corner(2), &[12.0, 17.0, 3.0, 5.0, 1.0, 2.0, 3.0, 5.0]);
+		assert_eq!(corner(5), &[8.0, 17.0, 1.0, 5.0, 1.0, 2.0, 3.0, 5.0]);
+		assert_eq!(&vertices[..6 * VERTEX_FLOATS], &vertices[6 * VERTEX_FLOATS..]);
+		assert_eq!(batch_scale(&[quad]), 2.0);
+	}
+
+	#[test]
+	fn quads_grow_for_effects() {
+		let effects = TextEffects {
+			outline_width: 2.0,
+			..TextEffects::default()
+		};
+		let quad = BatchQuad::new((0.0, 0.0), (8.0, 4.0), ((10.0, 10.0), (4.0, 2.0)), &effects);
+		// The quad keeps its centre and scale, and only the glyph is sampled
+		assert_eq!(quad.pos, (0.0, 0.0));
+		assert_eq!(quad.size, (16.0, 12.0));
+		assert_eq!(quad.tex_rect, ((8.0, 8.0), (8.0, 6.0)));
+		assert_eq!(quad.clamp_rect, ((10.0, 10.0), (4.0, 2.0)));
+		assert_eq!(batch_scale(&[quad]), 2.0);
+		assert_eq!(batch_scale(&[]), 1.0);
+	}
+}
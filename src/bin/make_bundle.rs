@@ -0,0 +1,46 @@
+use std::path::Path;
+use dvr::bundle::{AssetKind, BundleBuilder};
+
+/// Packs all files in a directory (including subdirectories) into an asset bundle.
+/// The assets are named by their path relative to the directory, using '/' as separator.
+fn main() -> Result<(), String> {
+	let args: Vec<String> = std::env::args().skip(1).collect();
+	let compress = args.iter().any(|arg| arg == "--compress");
+	let paths: Vec<&String> = args.iter().filter(|arg| !arg.starts_with("--")).collect();
+	let [input, output] = paths[..] else {
+		return Err("Usage: make_bundle [--compress] <input directory> <output file>".to_string());
+	};
+
+	let mut files = Vec::new();
+	collect_files(Path::new(input), "", &mut files)?;
+	files.sort();
+
+	let mut builder = BundleBuilder::new();
+	for (name, path) in &files {
+		let data = std::fs::read(path)
+			.map_err(|e| format!("Failed to read \"{}\" ({})", path, e))?;
+		let kind = AssetKind::from_file_name(name)?;
+		builder.add(name, kind, &data, compress)?;
+		println!("{} ({:?}, {} bytes)", name, kind, data.len());
+	}
+	std::fs::write(output, builder.to_bytes())
+		.map_err(|e| format!("Failed to write \"{}\" ({})", output, e))?;
+	println!("Wrote {} assets to {}", files.len(), output);
+	Ok(())
+}
+
+fn collect_files(dir: &Path, prefix: &str, files: &mut Vec<(String, String)>) -> Result<(), String> {
+	let entries = std::fs::read_dir(dir)
+		.map_err(|e| format!("Failed to read directory \"{}\" ({})", dir.display(), e))?;
+	for entry in entries {
+		let entry = entry.map_err(|e| format!("Failed to read directory \"{}\" ({})", dir.display(), e))?;
+		let path = entry.path();
+		let name = prefix.to_string() + &entry.file_name().to_string_lossy();
+		if path.is_dir() {
+			collect_files(&path, &(name + "/"), files)?;
+		} else {
+			files.push((name, path.display().to_string()));
+		}
+	}
+	Ok(())
+}
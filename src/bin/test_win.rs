@@ -1,10 +1,13 @@
+#[cfg(target_os = "windows")]
 use dvr::{interface::*, state::{LogicStatus, State, StateHandler}, *};
 
+#[cfg(target_os = "windows")]
 struct TestState {
 	angle: f32,
 	tex: Texture,
 }
 
+#[cfg(target_os = "windows")]
 impl TestState {
 	fn new(dvr: &Dvr) -> Result<TestState, String> {
 		let tex = dvr.load_texture("pluto.png")?;
@@ -15,6 +18,7 @@ impl TestState {
 	}
 }
 
+#[cfg(target_os = "windows")]
 impl State<()> for TestState {
 	fn logic(&mut self, _glob: &mut ()) -> Result<LogicStatus<()>, String> {
 		self.angle += 0.01;
@@ -27,10 +31,15 @@ impl State<()> for TestState {
 	}
 }
 
+#[cfg(target_os = "windows")]
 fn main() -> Result<(), String> {
 	let interface = Interface::new("testy", 500, 250, true)?;
 	let dvr = Dvr::new(interface.get_ctx())?;
 	let test_state = TestState::new(&dvr)?;
 	StateHandler::run(dvr, Box::new(test_state), (), &interface)?;
 	Ok(())
-}
\ No newline at end of file
+}
+
+// The test program only has a Windows version
+#[cfg(not(target_os = "windows"))]
+fn main() {}
@@ -0,0 +1,546 @@
+use std::collections::HashMap;
+
+/// A font descriptor in the AngelCode BMFont format, which is exported by e.g. BMFont,
+/// Hiero and many other bitmap font tools. The text, XML and binary (version 3) variants
+/// are supported. Use font::Font::from_bmfont() to create a Font from it.
+///
+/// All sizes are in pixels of the page textures.
+#[derive(Default, Debug, PartialEq)]
+pub struct BmFont {
+	pub face: String,
+	/// The size the font was rendered at. This is negative if the size refers to the
+	/// height of the characters rather than the height of the cells.
+	pub size: i32,
+	/// The distance between two lines.
+	pub line_height: u32,
+	/// The distance from the top of a line to the baseline.
+	pub base: u32,
+	/// The file names of the page textures, relative to the descriptor.
+	pub pages: Vec<String>,
+	pub chars: Vec<BmChar>,
+	pub kernings: Vec<BmKerning>,
+	/// Set if the pages are distance fields, as exported by e.g. msdf-bmfont-xml.
+	pub distance_field: Option<BmDistanceField>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BmChar {
+	pub id: u32,
+	pub x: u32,
+	pub y: u32,
+	pub width: u32,
+	pub height: u32,
+	/// Where the top left corner of the glyph is drawn relative to the cursor and the top of the line.
+	pub x_offset: i32,
+	pub y_offset: i32,
+	/// How far the cursor moves after the character.
+	pub x_advance: i32,
+	pub page: u32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BmDistanceField {
+	/// "sdf", "psdf", "msdf" or "mtsdf".
+	pub field_type: String,
+	/// The distance range that the fields were generated with, in pixels.
+	pub distance_range: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BmKerning {
+	pub first: u32,
+	pub second: u32,
+	/// How much the cursor is moved after the first character when it is followed by
+	/// the second character. This is usually negative.
+	pub amount: i32,
+}
+
+impl BmFont {
+	/// Parses a descriptor, detecting which variant of the format it uses.
+	pub fn parse(data: &[u8]) -> Result<BmFont, String> {
+		if data.starts_with(b"BMF") {
+			return Self::parse_binary(data);
+		}
+		let text = std::str::from_utf8(data)
+			.map_err(|_| "BMFont descriptor is not valid UTF-8")?;
+		let text = text.trim_start_matches('\u{feff}').trim_start();
+		if text.starts_with('<') {
+			Self::parse_xml(text)
+		} else {
+			Self::parse_text(text)
+		}
+	}
+
+	/// Parses the text variant, where each line is a tag followed by key=value pairs.
+	pub fn parse_text(text: &str) -> Result<BmFont, String> {
+		let mut builder = Builder::default();
+		for line in text.lines() {
+			let mut tokens = Tokens { rest: line };
+			let Some(tag) = tokens.next_word() else {
+				continue;
+			};
+			let mut attributes = HashMap::new();
+			while let Some((key, value)) = tokens.next_attribute()? {
+				attributes.insert(key, value);
+			}
+			builder.add(tag, &Attributes(attributes))?;
+		}
+		builder.finish()
+	}
+
+	pub fn parse_xml(text: &str) -> Result<BmFont, String> {
+		let document = roxmltree::Document::parse(text)
+			.map_err(|e| format!("Failed to parse BMFont XML ({})", e))?;
+		let mut builder = Builder::default();
+		for node in document.descendants().filter(|node| node.is_element()) {
+			let attributes = node
+				.attributes()
+				.map(|attribute| (attribute.name().to_string(), attribute.value().to_string()))
+				.collect();
+			builder.add(node.tag_name().name(), &Attributes(attributes))?;
+		}
+		builder.finish()
+	}
+
+	/// Parses the binary variant. Only version 3 is supported.
+	pub fn parse_binary(data: &[u8]) -> Result<BmFont, String> {
+		let mut reader = ByteReader { bytes: data, pos: 0 };
+		if reader.read(3)? != b"BMF" {
+			return Err("Data is not a binary BMFont descriptor".to_string());
+		}
+		let version = reader.read_u8()?;
+		if version != 3 {
+			return Err(format!("Unsupported binary BMFont version {}", version));
+		}
+		let mut font = BmFont::default();
+		let mut packed = false;
+		while reader.pos < data.len() {
+			let block_type = reader.read_u8()?;
+			let block_size = reader.read_u32()? as usize;
+			let mut block = ByteReader { bytes: reader.read(block_size)?, pos: 0 };
+			match block_type {
+				1 => {
+					font.size = block.read_u16()? as i16 as i32;
+					// Bit field, char set, stretch, antialiasing, padding, spacing and outline
+					block.read(12)?;
+					font.face = block.read_str()?;
+				},
+				2 => {
+					font.line_height = block.read_u16()? as u32;
+					font.base = block.read_u16()? as u32;
+					// Scale and number of pages
+					block.read(6)?;
+					packed = block.read_u8()? & 1 != 0;
+				},
+				3 => {
+					while block.pos < block.bytes.len() {
+						font.pages.push(block.read_str()?);
+					}
+				},
+				4 => {
+					while block.pos < block.bytes.len() {
+						font.chars.push(BmChar {
+							id: block.read_u32()?,
+							x: block.read_u16()? as u32,
+							y: block.read_u16()? as u32,
+							width: block.read_u16()? as u32,
+							height: block.read_u16()? as u32,
+							x_offset: block.read_u16()? as i16 as i32,
+							y_offset: block.read_u16()? as i16 as i32,
+							x_advance: block.read_u16()? as i16 as i32,
+							page: block.read_u8()? as u32,
+						});
+						// Channel
+						block.read_u8()?;
+					}
+				},
+				5 => {
+					while block.pos < block.bytes.len() {
+						font.kernings.push(BmKerning {
+							first: block.read_u32()?,
+							second: block.read_u32()?,
+							amount: block.read_u16()? as i16 as i32,
+						});
+					}
+				},
+				_ => {},
+			}
+		}
+		if packed {
+			return Err(PACKED_ERROR.to_string());
+		}
+		font.check()?;
+		Ok(font)
+	}
+
+	/// Returns the paths of the page textures, given the path (or URL) of the descriptor.
+	pub fn page_paths(&self, descriptor_path: &str) -> Vec<String> {
+		let dir = match descriptor_path.rfind(['/', '\\']) {
+			Some(i) => &descriptor_path[..=i],
+			None => "",
+		};
+		self.pages
+			.iter()
+			.map(|page| format!("{}{}", dir, page))
+			.collect()
+	}
+
+	fn check(&self) -> Result<(), String> {
+		if self.line_height == 0 {
+			return Err("BMFont descriptor has no line height".to_string());
+		}
+		if let Some(c) = self.chars.iter().find(|c| c.page as usize >= self.pages.len()) {
+			return Err(format!("Character {} is on page {}, which does not exist", c.id, c.page));
+		}
+		Ok(())
+	}
+}
+
+const PACKED_ERROR: &str = "Packed BMFonts (with glyphs in different colour channels) are not supported";
+
+/// Builds a BmFont from the tags of the text and XML variants, which have the same
+/// tags and attributes.
+#[derive(Default)]
+struct Builder {
+	font: BmFont,
+	pages: Vec<(u32, String)>,
+	packed: bool,
+}
+
+impl Builder {
+	fn add(&mut self, tag: &str, attributes: &Attributes) -> Result<(), String> {
+		match tag {
+			"info" => {
+				self.font.face = attributes.get("face").unwrap_or_default().to_string();
+				self.font.size = attributes.get_num("size").unwrap_or(Ok(0))?;
+			},
+			"common" => {
+				self.font.line_height = attributes.num("lineHeight")?;
+				self.font.base = attributes.num("base")?;
+				self.packed = attributes.get_num("packed").unwrap_or(Ok(0))? != 0;
+			},
+			"page" => {
+				self.pages.push((attributes.num("id")?, attributes.str("file")?.to_string()));
+			},
+			"char" => {
+				let id: i64 = attributes.num("id")?;
+				// Some tools use -1 for the glyph used for missing characters
+				if id < 0 {
+					return Ok(());
+				}
+				self.font.chars.push(BmChar {
+					id: id as u32,
+					x: attributes.num("x")?,
+					y: attributes.num("y")?,
+					width: attributes.num("width")?,
+					height: attributes.num("height")?,
+					x_offset: attributes.num("xoffset")?,
+					y_offset: attributes.num("yoffset")?,
+					x_advance: attributes.num("xadvance")?,
+					page: attributes.get_num("page").unwrap_or(Ok(0))?,
+				});
+			},
+			"distanceField" => {
+				self.font.distance_field = Some(BmDistanceField {
+					field_type: attributes.str("fieldType")?.to_string(),
+					distance_range: attributes.num("distanceRange")?,
+				});
+			},
+			"kerning" => {
+				self.font.kernings.push(BmKerning {
+					first: attributes.num("first")?,
+					second: attributes.num("second")?,
+					amount: attributes.num("amount")?,
+				});
+			},
+			_ => {},
+		}
+		Ok(())
+	}
+
+	fn finish(mut self) -> Result<BmFont, String> {
+		if self.packed {
+			return Err(PACKED_ERROR.to_string());
+		}
+		self.pages.sort_by_key(|(id, _)| *id);
+		for (i, (id, _)) in self.pages.iter().enumerate() {
+			if *id as usize != i {
+				return Err(format!("BMFont page {} is missing", i));
+			}
+		}
+		self.font.pages = self.pages.into_iter().map(|(_, file)| file).collect();
+		self.font.check()?;
+		Ok(self.font)
+	}
+}
+
+struct Attributes(HashMap<String, String>);
+
+impl Attributes {
+	fn get(&self, key: &str) -> Option<&str> {
+		self.0.get(key).map(|value| value.as_str())
+	}
+
+	fn str(&self, key: &str) -> Result<&str, String> {
+		self.get(key).ok_or(format!("BMFont attribute \"{}\" is missing", key))
+	}
+
+	fn get_num<T: std::str::FromStr>(&self, key: &str) -> Option<Result<T, String>> {
+		self.get(key).map(|value| value
+			.trim()
+			.parse()
+			.map_err(|_| format!("BMFont attribute \"{}\" is not a valid number", key)))
+	}
+
+	fn num<T: std::str::FromStr>(&self, key: &str) -> Result<T, String> {
+		self.get_num(key).ok_or(format!("BMFont attribute \"{}\" is missing", key))?
+	}
+}
+
+/// Splits a line of the text variant into words and key=value pairs, where the
+/// values may be quoted.
+struct Tokens<'a> {
+	rest: &'a str,
+}
+
+impl<'a> Tokens<'a> {
+	fn next_word(&mut self) -> Option<&'a str> {
+		self.rest = self.rest.trim_start();
+		if self.rest.is_empty() {
+			return None;
+		}
+		let end = self.rest.find(char::is_whitespace).unwrap_or(self.rest.len());
+		let (word, rest) = self.rest.split_at(end);
+		self.rest = rest;
+		Some(word)
+	}
+
+	fn next_attribute(&mut self) -> Result<Option<(String, String)>, String> {
+		self.rest = self.rest.trim_start();
+		if self.rest.is_empty() {
+			return Ok(None);
+		}
+		let (key, rest) = self.rest.split_once('=')
+			.ok_or(format!("Expected key=value in BMFont descriptor, found \"{}\"", self.rest))?;
+		let (value, rest) = match rest.strip_prefix('"') {
+			Some(quoted) => quoted.split_once('"')
+				.ok_or("Unterminated string in BMFont descriptor")?,
+			None => rest.split_at(rest.find(char::is_whitespace).unwrap_or(rest.len())),
+		};
+		self.rest = rest;
+		Ok(Some((key.trim().to_string(), value.to_string())))
+	}
+}
+
+struct ByteReader<'a> {
+	bytes: &'a [u8],
+	pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+	fn read(&mut self, n: usize) -> Result<&'a [u8], String> {
+		let end = self.pos.checked_add(n)
+			.filter(|end| *end <= self.bytes.len())
+			.ok_or("Unexpected end of BMFont descriptor")?;
+		let bytes = &self.bytes[self.pos..end];
+		self.pos = end;
+		Ok(bytes)
+	}
+
+	fn read_u8(&mut self) -> Result<u8, String> {
+		Ok(self.read(1)?[0])
+	}
+
+	fn read_u16(&mut self) -> Result<u16, String> {
+		Ok(u16::from_le_bytes(self.read(2)?.try_into().unwrap()))
+	}
+
+	fn read_u32(&mut self) -> Result<u32, String> {
+		Ok(u32::from_le_bytes(self.read(4)?.try_into().unwrap()))
+	}
+
+	/// Reads a null-terminated string.
+	fn read_str(&mut self) -> Result<String, String> {
+		let len = self.bytes[self.pos..]
+			.iter()
+			.position(|&b| b == 0)
+			.ok_or("Unterminated string in BMFont descriptor")?;
+		let s = String::from_utf8(self.read(len)?.to_vec())
+			.map_err(|_| "String in BMFont descriptor is not valid UTF-8")?;
+		self.read(1)?;
+		Ok(s)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const TEXT: &str = "\
+info face=\"Test Sans\" size=-32 bold=0 charset=\"\" padding=0,0,0,0
+common lineHeight=36 base=29 scaleW=256 scaleH=256 pages=2 packed=0
+page id=0 file=\"test_0.png\"
+page id=1 file=\"test 1.png\"
+distanceField fieldType=msdf distanceRange=4
+chars count=2
+char id=65   x=1    y=2    width=20   height=24   xoffset=-1   yoffset=5    xadvance=19   page=0  chnl=15
+char id=66   x=30   y=2    width=18   height=24   xoffset=1    yoffset=5    xadvance=20   page=1  chnl=15
+kernings count=1
+kerning first=65  second=66  amount=-2
+";
+
+	const XML: &str = r#"<?xml version="1.0"?>
+<font>
+	<info face="Test Sans" size="-32" bold="0" charset="" padding="0,0,0,0"/>
+	<common lineHeight="36" base="29" scaleW="256" scaleH="256" pages="2" packed="0"/>
+	<pages>
+		<page id="0" file="test_0.png"/>
+		<page id="1" file="test 1.png"/>
+	</pages>
+	<distanceField fieldType="msdf" distanceRange="4"/>
+	<chars count="2">
+		<char id="65" x="1" y="2" width="20" height="24" xoffset="-1" yoffset="5" xadvance="19" page="0" chnl="15"/>
+		<char id="66" x="30" y="2" width="18" height="24" xoffset="1" yoffset="5" xadvance="20" page="1" chnl="15"/>
+	</chars>
+	<kernings count="1">
+		<kerning first="65" second="66" amount="-2"/>
+	</kernings>
+</font>
+"#;
+
+	fn expected() -> BmFont {
+		BmFont {
+			face: "Test Sans".to_string(),
+			size: -32,
+			line_height: 36,
+			base: 29,
+			pages: vec!["test_0.png".to_string(), "test 1.png".to_string()],
+			chars: vec![
+				BmChar { id: 65, x: 1, y: 2, width: 20, height: 24, x_offset: -1, y_offset: 5, x_advance: 19, page: 0 },
+				BmChar { id: 66, x: 30, y: 2, width: 18, height: 24, x_offset: 1, y_offset: 5, x_advance: 20, page: 1 },
+			],
+			kernings: vec![BmKerning { first: 65, second: 66, amount: -2 }],
+			distance_field: Some(BmDistanceField { field_type: "msdf".to_string(), distance_range: 4 }),
+		}
+	}
+
+	fn push_block(out: &mut Vec<u8>, kind: u8, data: &[u8]) {
+		out.push(kind);
+		out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+		out.extend_from_slice(data);
+	}
+
+	/// The binary variant of TEXT, which has no distance field. Also returns where each
+	/// block ends.
+	fn encode_binary(common_bits: u8) -> (Vec<u8>, Vec<usize>) {
+		let mut out = b"BMF\x03".to_vec();
+		let mut ends = Vec::new();
+		let mut info = (-32i16).to_le_bytes().to_vec();
+		info.extend_from_slice(&[0; 12]);
+		info.extend_from_slice(b"Test Sans\0");
+		push_block(&mut out, 1, &info);
+		ends.push(out.len());
+		let mut common = Vec::new();
+		for value in [36u16, 29, 256, 256, 2] {
+			common.extend_from_slice(&value.to_le_bytes());
+		}
+		common.extend_from_slice(&[common_bits, 0, 0, 0, 0]);
+		push_block(&mut out, 2, &common);
+		ends.push(out.len());
+		push_block(&mut out, 3, b"test_0.png\0test 1.png\0");
+		ends.push(out.len());
+		let mut chars = Vec::new();
+		for c in &expected().chars {
+			chars.extend_from_slice(&c.id.to_le_bytes());
+			for value in [c.x as u16, c.y as u16, c.width as u16, c.height as u16] {
+				chars.extend_from_slice(&value.to_le_bytes());
+			}
+			for value in [c.x_offset as i16, c.y_offset as i16, c.x_advance as i16] {
+				chars.extend_from_slice(&value.to_le_bytes());
+			}
+			chars.extend_from_slice(&[c.page as u8, 15]);
+		}
+		push_block(&mut out, 4, &chars);
+		ends.push(out.len());
+		let mut kernings = 65u32.to_le_bytes().to_vec();
+		kernings.extend_from_slice(&66u32.to_le_bytes());
+		kernings.extend_from_slice(&(-2i16).to_le_bytes());
+		push_block(&mut out, 5, &kernings);
+		ends.push(out.len());
+		(out, ends)
+	}
+
+	#[test]
+	fn text_and_xml_are_the_same() {
+		assert_eq!(BmFont::parse(TEXT.as_bytes()).unwrap(), expected());
+		assert_eq!(BmFont::parse(XML.as_bytes()).unwrap(), expected());
+		let with_bom = format!("\u{feff}{}", XML);
+		assert_eq!(BmFont::parse(with_bom.as_bytes()).unwrap(), expected());
+	}
+
+	#[test]
+	fn binary() {
+		let mut font = expected();
+		font.distance_field = None;
+		assert_eq!(BmFont::parse(&encode_binary(0).0).unwrap(), font);
+		let mut version_2 = encode_binary(0).0;
+		version_2[3] = 2;
+		assert!(BmFont::parse(&version_2).is_err());
+	}
+
+	#[test]
+	fn quoted_values() {
+		let mut tokens = Tokens { rest: r#"char id=1 file="a b  c.png" empty="" face="Test Sans""# };
+		assert_eq!(tokens.next_word(), Some("char"));
+		let mut attributes = Vec::new();
+		while let Some(attribute) = tokens.next_attribute().unwrap() {
+			attributes.push(attribute);
+		}
+		let expected: Vec<(String, String)> = [("id", "1"), ("file", "a b  c.png"), ("empty", ""), ("face", "Test Sans")]
+			.iter()
+			.map(|(key, value)| (key.to_string(), value.to_string()))
+			.collect();
+		assert_eq!(attributes, expected);
+		assert!(BmFont::parse_text("page id=0 file=\"unterminated.png").is_err());
+		assert!(BmFont::parse_text("common lineHeight").is_err());
+	}
+
+	#[test]
+	fn pages() {
+		let common = "common lineHeight=10 base=8\n";
+		let out_of_order = format!("{}page id=1 file=b.png\npage id=0 file=a.png\n", common);
+		assert_eq!(BmFont::parse_text(&out_of_order).unwrap().pages, ["a.png", "b.png"]);
+		let missing = format!("{}page id=0 file=a.png\npage id=2 file=c.png\n", common);
+		assert!(BmFont::parse_text(&missing).is_err());
+		let no_page = format!("{}page id=0 file=a.png\nchar id=65 x=0 y=0 width=1 height=1 xoffset=0 yoffset=0 xadvance=1 page=1\n", common);
+		assert!(BmFont::parse_text(&no_page).is_err());
+		assert!(BmFont::parse_text("page id=0 file=a.png").is_err(), "No line height");
+	}
+
+	#[test]
+	fn page_paths() {
+		let font = BmFont::parse(TEXT.as_bytes()).unwrap();
+		assert_eq!(font.page_paths("fonts/test.fnt"), ["fonts/test_0.png", "fonts/test 1.png"]);
+		assert_eq!(font.page_paths("C:\\fonts\\test.fnt"), ["C:\\fonts\\test_0.png", "C:\\fonts\\test 1.png"]);
+		assert_eq!(font.page_paths("test.fnt"), ["test_0.png", "test 1.png"]);
+	}
+
+	#[test]
+	fn packed_is_an_error() {
+		assert_eq!(BmFont::parse(TEXT.replace("packed=0", "packed=1").as_bytes()).unwrap_err(), PACKED_ERROR);
+		assert_eq!(BmFont::parse(XML.replace("packed=\"0\"", "packed=\"1\"").as_bytes()).unwrap_err(), PACKED_ERROR);
+		assert_eq!(BmFont::parse(&encode_binary(1).0).unwrap_err(), PACKED_ERROR);
+	}
+
+	#[test]
+	fn truncated_binary_is_an_error() {
+		let (data, ends) = encode_binary(0);
+		for len in 0..data.len() {
+			// Cutting between two blocks only leaves out the later blocks
+			if let Ok(font) = BmFont::parse(&data[..len]) {
+				assert!(ends.contains(&len), "{} of {} bytes parsed", len, data.len());
+				assert_eq!(font.line_height, 36);
+			}
+		}
+	}
+}
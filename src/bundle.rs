@@ -0,0 +1,386 @@
+use std::{borrow::Cow, collections::HashMap};
+use crate::image::ImageFormat;
+
+/// A packed file containing many assets. The format is:
+///
+/// - the magic bytes "DVRB" and the format version (u32)
+/// - the number of entries (u32)
+/// - for each entry: the length of the name (u32), the name (UTF-8), the kind (u8),
+///   the compression (u8), the offset of the data from the start of the data section (u64),
+///   the number of stored bytes (u64) and the number of bytes after decompression (u64)
+/// - the data section, containing the data of all entries
+///
+/// All integers are little-endian. Bundles can be created with BundleBuilder or with the
+/// make_bundle program.
+pub struct Bundle {
+	names: Vec<String>,
+	entries: HashMap<String, Entry>,
+	bytes: Vec<u8>,
+	/// Where the data section starts in bytes.
+	data_start: usize,
+}
+
+struct Entry {
+	kind: AssetKind,
+	compression: Compression,
+	offset: usize,
+	stored_len: usize,
+	len: usize,
+}
+
+const MAGIC: &[u8; 4] = b"DVRB";
+const VERSION: u32 = 1;
+
+impl Bundle {
+	pub fn from_bytes(bytes: Vec<u8>) -> Result<Bundle, String> {
+		let mut reader = ByteReader { bytes: &bytes, pos: 0 };
+		if reader.read(4)? != MAGIC {
+			return Err("Data is not an asset bundle".to_string());
+		}
+		let version = reader.read_u32()?;
+		if version != VERSION {
+			return Err(format!("Unsupported asset bundle version {}", version));
+		}
+		let num_entries = reader.read_u32()?;
+		let mut names = Vec::new();
+		let mut entries = HashMap::new();
+		for _ in 0..num_entries {
+			let name_len = reader.read_u32()? as usize;
+			let name = String::from_utf8(reader.read(name_len)?.to_vec())
+				.map_err(|_| "Asset name is not valid UTF-8")?;
+			let entry = Entry {
+				kind: AssetKind::from_byte(reader.read_u8()?)?,
+				compression: Compression::from_byte(reader.read_u8()?)?,
+				offset: reader.read_u64()? as usize,
+				stored_len: reader.read_u64()? as usize,
+				len: reader.read_u64()? as usize,
+			};
+			if entries.contains_key(&name) {
+				return Err(format!("Asset \"{}\" appears more than once in the bundle", name));
+			}
+			names.push(name.clone());
+			entries.insert(name, entry);
+		}
+		let data_start = reader.pos;
+		let data_len = bytes.len() - data_start;
+		for (name, entry) in &entries {
+			if entry.offset.checked_add(entry.stored_len).is_none_or(|end| end > data_len) {
+				return Err(format!("The data of asset \"{}\" is outside the bundle", name));
+			}
+		}
+		Ok(Bundle { names, entries, bytes, data_start })
+	}
+
+	/// Reads a bundle from a file.
+	#[cfg(not(target_arch = "wasm32"))]
+	pub fn load(path: &str) -> Result<Bundle, String> {
+		let bytes = std::fs::read(path)
+			.map_err(|e| format!("Failed to read asset bundle \"{}\" ({})", path, e))?;
+		Self::from_bytes(bytes)
+	}
+
+	/// Downloads a bundle.
+	#[cfg(target_arch = "wasm32")]
+	pub async fn fetch(url: &str) -> Result<Bundle, String> {
+		Self::from_bytes(crate::wasm_utils::fetch_bytes(url).await?)
+	}
+
+	/// The names of all assets in the order they were added.
+	pub fn names(&self) -> impl Iterator<Item = &str> {
+		self.names.iter().map(|name| name.as_str())
+	}
+
+	/// The names of all assets of a kind in the order they were added.
+	pub fn names_of_kind(&self, kind: AssetKind) -> impl Iterator<Item = &str> {
+		self.names()
+			.filter(move |name| self.entries[*name].kind == kind)
+	}
+
+	pub fn contains(&self, name: &str) -> bool {
+		self.entries.contains_key(name)
+	}
+
+	pub fn kind(&self, name: &str) -> Option<AssetKind> {
+		self.entries.get(name).map(|entry| entry.kind)
+	}
+
+	/// Returns the (decompressed) data of an asset.
+	pub fn get(&self, name: &str) -> Result<Cow<'_, [u8]>, String> {
+		let entry = self.entries.get(name)
+			.ok_or(format!("Asset \"{}\" was not found", name))?;
+		let start = self.data_start + entry.offset;
+		let stored = &self.bytes[start..start + entry.stored_len];
+		match entry.compression {
+			Compression::None => Ok(Cow::Borrowed(stored)),
+			Compression::Deflate => {
+				let data = miniz_oxide::inflate::decompress_to_vec_with_limit(stored, entry.len)
+					.map_err(|_| format!("Failed to decompress asset \"{}\"", name))?;
+				if data.len() != entry.len {
+					return Err(format!("Asset \"{}\" has the wrong size", name));
+				}
+				Ok(Cow::Owned(data))
+			},
+		}
+	}
+}
+
+/// Creates the bytes of a Bundle.
+pub struct BundleBuilder {
+	names: Vec<String>,
+	entries: Vec<(Entry, Vec<u8>)>,
+}
+
+impl BundleBuilder {
+	pub fn new() -> BundleBuilder {
+		BundleBuilder {
+			names: Vec::new(),
+			entries: Vec::new(),
+		}
+	}
+
+	/// Adds an asset. If compress is true the data is compressed with deflate, unless
+	/// that would not make it smaller. Textures must be PNG or QOI images, because those
+	/// are the formats that every backend can decode (see Image::decode()).
+	pub fn add(&mut self, name: &str, kind: AssetKind, data: &[u8], compress: bool) -> Result<(), String> {
+		if self.names.iter().any(|n| n == name) {
+			return Err(format!("Asset \"{}\" has already been added", name));
+		}
+		if kind == AssetKind::Texture && ImageFormat::detect(data).is_none() {
+			return Err(format!("Texture \"{}\" is not a PNG or QOI image", name));
+		}
+		let compressed = match compress {
+			true => Some(miniz_oxide::deflate::compress_to_vec(data, 8))
+				.filter(|compressed| compressed.len() < data.len()),
+			false => None,
+		};
+		let (compression, stored) = match compressed {
+			Some(compressed) => (Compression::Deflate, compressed),
+			None => (Compression::None, data.to_vec()),
+		};
+		let offset = self.entries
+			.last()
+			.map(|(entry, _)| entry.offset + entry.stored_len)
+			.unwrap_or(0);
+		self.names.push(name.to_string());
+		self.entries.push((
+			Entry {
+				kind,
+				compression,
+				offset,
+				stored_len: stored.len(),
+				len: data.len(),
+			},
+			stored
+		));
+		Ok(())
+	}
+
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let mut bytes = Vec::new();
+		bytes.extend_from_slice(MAGIC);
+		bytes.extend_from_slice(&VERSION.to_le_bytes());
+		bytes.extend_from_slice(&(self.entries.len() as u32).to_le_bytes());
+		for (name, (entry, _)) in self.names.iter().zip(&self.entries) {
+			bytes.extend_from_slice(&(name.len() as u32).to_le_bytes());
+			bytes.extend_from_slice(name.as_bytes());
+			bytes.push(entry.kind.to_byte());
+			bytes.push(entry.compression.to_byte());
+			bytes.extend_from_slice(&(entry.offset as u64).to_le_bytes());
+			bytes.extend_from_slice(&(entry.stored_len as u64).to_le_bytes());
+			bytes.extend_from_slice(&(entry.len as u64).to_le_bytes());
+		}
+		for (_, stored) in &self.entries {
+			bytes.extend_from_slice(stored);
+		}
+		bytes
+	}
+}
+
+impl Default for BundleBuilder {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AssetKind {
+	Texture,
+	/// A font descriptor (e.g. a BMFont file) or a TrueType/OpenType font.
+	Font,
+	/// Anything else.
+	Data,
+}
+
+impl AssetKind {
+	/// Guesses the kind of an asset from the extension of its file name. Images in formats
+	/// that can't be decoded on all platforms (e.g. JPEG) are an error, so that a bundle
+	/// doesn't only work on some of them.
+	pub fn from_file_name(name: &str) -> Result<AssetKind, String> {
+		let extension = name.rsplit_once('.')
+			.map(|(_, extension)| extension.to_ascii_lowercase())
+			.unwrap_or_default();
+		match extension.as_str() {
+			"png" | "qoi" => Ok(AssetKind::Texture),
+			"jpg" | "jpeg" | "bmp" | "gif" => Err(format!("\"{}\" can't be decoded on the web, convert it to PNG or QOI", name)),
+			"fnt" | "ttf" | "otf" => Ok(AssetKind::Font),
+			_ => Ok(AssetKind::Data),
+		}
+	}
+
+	fn to_byte(self) -> u8 {
+		match self {
+			AssetKind::Texture => 0,
+			AssetKind::Font => 1,
+			AssetKind::Data => 2,
+		}
+	}
+
+	fn from_byte(b: u8) -> Result<AssetKind, String> {
+		match b {
+			0 => Ok(AssetKind::Texture),
+			1 => Ok(AssetKind::Font),
+			2 => Ok(AssetKind::Data),
+			_ => Err(format!("Unknown asset kind {}", b)),
+		}
+	}
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Compression {
+	None,
+	Deflate,
+}
+
+impl Compression {
+	fn to_byte(self) -> u8 {
+		match self {
+			Compression::None => 0,
+			Compression::Deflate => 1,
+		}
+	}
+
+	fn from_byte(b: u8) -> Result<Compression, String> {
+		match b {
+			0 => Ok(Compression::None),
+			1 => Ok(Compression::Deflate),
+			_ => Err(format!("Unknown compression {}", b)),
+		}
+	}
+}
+
+struct ByteReader<'a> {
+	bytes: &'a [u8],
+	pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+	fn read(&mut self, n: usize) -> Result<&'a [u8], String> {
+		let end = self.pos.checked_add(n)
+			.filter(|end| *end <= self.bytes.len())
+			.ok_or("Unexpected end of asset bundle")?;
+		let bytes = &self.bytes[self.pos..end];
+		self.pos = end;
+		Ok(bytes)
+	}
+
+	fn read_u8(&mut self) -> Result<u8, String> {
+		Ok(self.read(1)?[0])
+	}
+
+	fn read_u32(&mut self) -> Result<u32, String> {
+		Ok(u32::from_le_bytes(self.read(4)?.try_into().unwrap()))
+	}
+
+	fn read_u64(&mut self) -> Result<u64, String> {
+		Ok(u64::from_le_bytes(self.read(8)?.try_into().unwrap()))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const QOI_HEADER: &[u8] = b"qoif\0\0\0\x01\0\0\0\x01\x04\0";
+
+	fn test_bundle() -> Vec<u8> {
+		let mut builder = BundleBuilder::new();
+		builder.add("a.qoi", AssetKind::Texture, QOI_HEADER, false).unwrap();
+		builder.add("fonts/b.fnt", AssetKind::Font, &[b'x'; 1000], true).unwrap();
+		builder.add("c", AssetKind::Data, b"", true).unwrap();
+		// Too short to get smaller when compressed, so it's stored
+		builder.add("d.txt", AssetKind::Data, b"dvr", true).unwrap();
+		builder.to_bytes()
+	}
+
+	#[test]
+	fn round_trip() {
+		let bundle = Bundle::from_bytes(test_bundle()).unwrap();
+		assert_eq!(bundle.names().collect::<Vec<_>>(), ["a.qoi", "fonts/b.fnt", "c", "d.txt"]);
+		assert_eq!(bundle.names_of_kind(AssetKind::Data).collect::<Vec<_>>(), ["c", "d.txt"]);
+		assert_eq!(bundle.kind("fonts/b.fnt"), Some(AssetKind::Font));
+		assert!(bundle.contains("c"));
+		assert!(!bundle.contains("e"));
+		assert_eq!(&*bundle.get("a.qoi").unwrap(), QOI_HEADER);
+		assert_eq!(&*bundle.get("fonts/b.fnt").unwrap(), &[b'x'; 1000]);
+		assert_eq!(&*bundle.get("c").unwrap(), b"");
+		assert!(matches!(bundle.get("d.txt").unwrap(), Cow::Borrowed(b"dvr")));
+		assert!(bundle.get("e").is_err());
+	}
+
+	#[test]
+	fn compression() {
+		let bundle = Bundle::from_bytes(test_bundle()).unwrap();
+		assert_eq!(bundle.entries["fonts/b.fnt"].compression, Compression::Deflate);
+		assert!(bundle.entries["fonts/b.fnt"].stored_len < 1000);
+		assert_eq!(bundle.entries["d.txt"].compression, Compression::None);
+	}
+
+	#[test]
+	fn truncated_or_corrupt_bundles() {
+		let bytes = test_bundle();
+		for len in 0..bytes.len() {
+			assert!(Bundle::from_bytes(bytes[..len].to_vec()).is_err(), "{} bytes", len);
+		}
+		let mut bad_magic = bytes.clone();
+		bad_magic[0] = b'X';
+		assert!(Bundle::from_bytes(bad_magic).is_err());
+		let mut bad_version = bytes.clone();
+		bad_version[4] = 2;
+		assert!(Bundle::from_bytes(bad_version).is_err());
+		// The kind of the first entry, after the header and the name "a.qoi"
+		let mut bad_kind = bytes.clone();
+		bad_kind[12 + 4 + 5] = 9;
+		assert!(Bundle::from_bytes(bad_kind).is_err());
+		// Any corruption of the compressed data is an error, never a panic
+		let mut bundle = Bundle::from_bytes(bytes).unwrap();
+		let start = bundle.data_start + bundle.entries["fonts/b.fnt"].offset;
+		for i in 0..bundle.entries["fonts/b.fnt"].stored_len {
+			bundle.bytes[start + i] ^= 0x55;
+			if let Ok(data) = bundle.get("fonts/b.fnt") {
+				assert_eq!(data.len(), 1000);
+			}
+			bundle.bytes[start + i] ^= 0x55;
+		}
+	}
+
+	#[test]
+	fn builder_errors() {
+		let mut builder = BundleBuilder::new();
+		builder.add("a", AssetKind::Data, b"1", false).unwrap();
+		assert!(builder.add("a", AssetKind::Data, b"2", false).is_err());
+		assert!(builder.add("b.jpg", AssetKind::Texture, b"\xff\xd8\xff\xe0", false).is_err());
+	}
+
+	#[test]
+	fn kinds_from_file_names() {
+		assert_eq!(AssetKind::from_file_name("dir/image.PNG"), Ok(AssetKind::Texture));
+		assert_eq!(AssetKind::from_file_name("image.qoi"), Ok(AssetKind::Texture));
+		assert_eq!(AssetKind::from_file_name("font.fnt"), Ok(AssetKind::Font));
+		assert_eq!(AssetKind::from_file_name("font.ttf"), Ok(AssetKind::Font));
+		assert_eq!(AssetKind::from_file_name("level.json"), Ok(AssetKind::Data));
+		assert_eq!(AssetKind::from_file_name("README"), Ok(AssetKind::Data));
+		for name in ["photo.jpg", "photo.JPEG", "icon.bmp", "anim.gif"] {
+			assert!(AssetKind::from_file_name(name).is_err(), "{}", name);
+		}
+	}
+}
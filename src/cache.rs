@@ -0,0 +1,357 @@
+use std::{collections::HashMap, future::Future, ops::Deref, rc::Rc};
+
+/// Something that can be stored in an AssetCache.
+pub trait Asset {
+	/// An estimate of how much memory the asset uses in bytes. This is what the
+	/// memory budget of the cache is compared against.
+	fn memory(&self) -> usize;
+}
+
+impl Asset for Vec<u8> {
+	fn memory(&self) -> usize {
+		self.len()
+	}
+}
+
+impl Asset for crate::image::Image {
+	fn memory(&self) -> usize {
+		self.pixels().len()
+	}
+}
+
+/// A shared reference to an asset from an AssetCache. Cloning it is cheap. The asset
+/// is kept alive as long as there is a handle to it, even if it has been removed from
+/// the cache.
+pub struct Handle<T> {
+	asset: Rc<T>,
+}
+
+impl<T> Handle<T> {
+	/// Returns true if both handles refer to the same asset.
+	pub fn ptr_eq(a: &Handle<T>, b: &Handle<T>) -> bool {
+		Rc::ptr_eq(&a.asset, &b.asset)
+	}
+}
+
+impl<T> Clone for Handle<T> {
+	fn clone(&self) -> Self {
+		Handle {
+			asset: self.asset.clone(),
+		}
+	}
+}
+
+impl<T> Deref for Handle<T> {
+	type Target = T;
+
+	fn deref(&self) -> &T {
+		&self.asset
+	}
+}
+
+/// How an asset in an AssetCache is used.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct AssetUsage {
+	/// The number of handles to the asset that exist outside the cache. The asset can
+	/// only be evicted when this is 0.
+	pub handles: usize,
+	/// The number of times the asset has been fetched from the cache.
+	pub uses: u64,
+	pub memory: usize,
+}
+
+/// Hands out shared handles to assets, so that each asset is only loaded once no matter
+/// how many states use it. The assets are identified by a key, which is usually the path
+/// or URL that they were loaded from. One cache stores one kind of asset, e.g. an
+/// AssetCache<Texture> or an AssetCache<Font>.
+///
+/// If there is a memory budget, assets that have no handles outside the cache are evicted,
+/// least recently used first, whenever the cached assets use more memory than the budget.
+/// Assets that are in use are never evicted, so the budget can be exceeded.
+pub struct AssetCache<T: Asset> {
+	entries: HashMap<String, CacheEntry<T>>,
+	budget: Option<usize>,
+	// Increases every time an asset is used so that the least recently used one can be found
+	clock: u64,
+}
+
+struct CacheEntry<T> {
+	asset: Rc<T>,
+	uses: u64,
+	last_used: u64,
+}
+
+impl<T: Asset> AssetCache<T> {
+	/// Creates a cache without a memory budget. Nothing is evicted unless
+	/// evict_unused() or remove() is called.
+	pub fn new() -> AssetCache<T> {
+		AssetCache {
+			entries: HashMap::new(),
+			budget: None,
+			clock: 0,
+		}
+	}
+
+	/// Creates a cache with a memory budget in bytes.
+	pub fn with_budget(budget: usize) -> AssetCache<T> {
+		AssetCache {
+			budget: Some(budget),
+			..Self::new()
+		}
+	}
+
+	pub fn get_budget(&self) -> Option<usize> {
+		self.budget
+	}
+
+	/// Sets the memory budget in bytes and evicts assets if they do not fit.
+	/// None means that there is no budget.
+	pub fn set_budget(&mut self, budget: Option<usize>) {
+		self.budget = budget;
+		self.enforce_budget();
+	}
+
+	/// Returns a handle to an asset if it is in the cache.
+	pub fn get(&mut self, key: &str) -> Option<Handle<T>> {
+		self.clock += 1;
+		let entry = self.entries.get_mut(key)?;
+		entry.uses += 1;
+		entry.last_used = self.clock;
+		Some(Handle {
+			asset: entry.asset.clone(),
+		})
+	}
+
+	/// Returns a handle to an asset, calling load with the key to load it if it is not
+	/// in the cache.
+	pub fn get_or_load(&mut self, key: &str, load: impl FnOnce(&str) -> Result<T, String>) -> Result<Handle<T>, String> {
+		if let Some(handle) = self.get(key) {
+			return Ok(handle);
+		}
+		let asset = load(key)?;
+		Ok(self.insert(key, asset))
+	}
+
+	/// Like get_or_load(), but the asset is loaded by a future, e.g.
+	/// `cache.get_or_load_async(url, dvr.load_texture(url))`.
+	/// The future is only awaited if the asset is not in the cache.
+	pub async fn get_or_load_async(&mut self, key: &str, load: impl Future<Output = Result<T, String>>) -> Result<Handle<T>, String> {
+		if let Some(handle) = self.get(key) {
+			return Ok(handle);
+		}
+		let asset = load.await?;
+		Ok(self.insert(key, asset))
+	}
+
+	/// Adds an asset to the cache and returns a handle to it. If there already is an
+	/// asset with the same key it is replaced, but existing handles to it keep working.
+	pub fn insert(&mut self, key: &str, asset: T) -> Handle<T> {
+		self.clock += 1;
+		let asset = Rc::new(asset);
+		self.entries.insert(
+			key.to_string(),
+			CacheEntry {
+				asset: asset.clone(),
+				uses: 1,
+				last_used: self.clock,
+			}
+		);
+		let handle = Handle { asset };
+		self.enforce_budget();
+		handle
+	}
+
+	/// Removes an asset from the cache. Existing handles to it keep working.
+	pub fn remove(&mut self, key: &str) -> Option<Handle<T>> {
+		self.entries
+			.remove(key)
+			.map(|entry| Handle { asset: entry.asset })
+	}
+
+	pub fn contains(&self, key: &str) -> bool {
+		self.entries.contains_key(key)
+	}
+
+	pub fn keys(&self) -> impl Iterator<Item = &str> {
+		self.entries.keys().map(|key| key.as_str())
+	}
+
+	pub fn len(&self) -> usize {
+		self.entries.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.entries.is_empty()
+	}
+
+	pub fn get_usage(&self, key: &str) -> Option<AssetUsage> {
+		self.entries.get(key).map(|entry| AssetUsage {
+			handles: Rc::strong_count(&entry.asset) - 1,
+			uses: entry.uses,
+			memory: entry.asset.memory(),
+		})
+	}
+
+	/// The memory used by all assets in the cache in bytes.
+	pub fn get_memory(&self) -> usize {
+		self.entries
+			.values()
+			.map(|entry| entry.asset.memory())
+			.sum()
+	}
+
+	/// Removes all assets that have no handles outside the cache, regardless of the budget.
+	/// Returns the number of assets that were removed. This is useful e.g. after a level
+	/// has been unloaded.
+	pub fn evict_unused(&mut self) -> usize {
+		let len = self.entries.len();
+		self.entries.retain(|_, entry| Rc::strong_count(&entry.asset) > 1);
+		len - self.entries.len()
+	}
+
+	fn enforce_budget(&mut self) {
+		let Some(budget) = self.budget else {
+			return;
+		};
+		let mut memory = self.get_memory();
+		while memory > budget {
+			let least_recently_used = self.entries
+				.iter()
+				.filter(|(_, entry)| Rc::strong_count(&entry.asset) == 1)
+				.min_by_key(|(_, entry)| entry.last_used)
+				.map(|(key, _)| key.clone());
+			let Some(key) = least_recently_used else {
+				break;
+			};
+			if let Some(entry) = self.entries.remove(&key) {
+				memory -= entry.asset.memory();
+			}
+		}
+	}
+}
+
+impl<T: Asset> Default for AssetCache<T> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn sorted_keys(cache: &AssetCache<Vec<u8>>) -> Vec<&str> {
+		let mut keys: Vec<&str> = cache.keys().collect();
+		keys.sort();
+		keys
+	}
+
+	#[test]
+	fn least_recently_used_is_evicted_first() {
+		let mut cache = AssetCache::with_budget(30);
+		cache.insert("a", vec![0; 10]);
+		cache.insert("b", vec![0; 10]);
+		cache.insert("c", vec![0; 10]);
+		assert!(cache.get("a").is_some());
+		cache.insert("d", vec![0; 10]);
+		assert_eq!(sorted_keys(&cache), ["a", "c", "d"]);
+		cache.insert("e", vec![0; 15]);
+		// Both c and a had to go to fit 15 bytes
+		assert_eq!(sorted_keys(&cache), ["d", "e"]);
+		assert_eq!(cache.get_memory(), 25);
+	}
+
+	#[test]
+	fn handles_block_eviction() {
+		let mut cache = AssetCache::with_budget(20);
+		let a = cache.insert("a", vec![1; 10]);
+		cache.insert("b", vec![2; 10]);
+		cache.insert("c", vec![3; 10]);
+		assert_eq!(sorted_keys(&cache), ["a", "c"]);
+		let c = cache.get("c").unwrap();
+		// Nothing can be evicted, so the budget is exceeded
+		let d = cache.insert("d", vec![4; 10]);
+		assert_eq!(sorted_keys(&cache), ["a", "c", "d"]);
+		assert_eq!(cache.get_memory(), 30);
+		drop(a);
+		drop(c);
+		drop(d);
+		// Evicting happens when the cache changes, not when handles are dropped
+		assert_eq!(cache.len(), 3);
+		cache.set_budget(Some(20));
+		assert_eq!(sorted_keys(&cache), ["c", "d"]);
+	}
+
+	#[test]
+	fn usage() {
+		let mut cache = AssetCache::new();
+		let first = cache.insert("a", vec![0; 7]);
+		assert_eq!(cache.get_usage("a"), Some(AssetUsage { handles: 1, uses: 1, memory: 7 }));
+		let second = cache.get("a").unwrap();
+		let third = second.clone();
+		assert!(Handle::ptr_eq(&first, &third));
+		assert_eq!(cache.get_usage("a"), Some(AssetUsage { handles: 3, uses: 2, memory: 7 }));
+		drop((first, second, third));
+		assert_eq!(cache.get_usage("a").unwrap().handles, 0);
+		assert_eq!(cache.get_usage("b"), None);
+	}
+
+	#[test]
+	fn smaller_budget_evicts_immediately() {
+		let mut cache = AssetCache::new();
+		for key in ["a", "b", "c", "d"] {
+			cache.insert(key, vec![0; 10]);
+		}
+		assert_eq!(cache.get_budget(), None);
+		cache.set_budget(Some(100));
+		assert_eq!(cache.len(), 4);
+		cache.set_budget(Some(25));
+		assert_eq!(sorted_keys(&cache), ["c", "d"]);
+		cache.set_budget(Some(0));
+		assert!(cache.is_empty());
+	}
+
+	#[test]
+	fn evict_unused() {
+		let mut cache = AssetCache::new();
+		let a = cache.insert("a", vec![0; 10]);
+		cache.insert("b", vec![0; 10]);
+		cache.insert("c", vec![0; 10]);
+		assert_eq!(cache.evict_unused(), 2);
+		assert_eq!(sorted_keys(&cache), ["a"]);
+		drop(a);
+		assert_eq!(cache.evict_unused(), 1);
+		assert!(cache.is_empty());
+	}
+
+	#[test]
+	fn replaced_and_removed_assets_stay_valid() {
+		let mut cache = AssetCache::new();
+		let old = cache.insert("a", vec![1, 2, 3]);
+		let new = cache.insert("a", vec![4]);
+		assert_eq!(*old, [1, 2, 3]);
+		assert_eq!(*cache.get("a").unwrap(), [4]);
+		assert!(!Handle::ptr_eq(&old, &new));
+		assert_eq!(cache.get_memory(), 1);
+		let removed = cache.remove("a").unwrap();
+		assert!(Handle::ptr_eq(&removed, &new));
+		assert!(!cache.contains("a"));
+		assert_eq!(*new, [4]);
+	}
+
+	#[test]
+	fn load_only_once() {
+		let mut cache = AssetCache::new();
+		let mut loads = 0;
+		for _ in 0..3 {
+			let handle = cache.get_or_load("a", |key| {
+				loads += 1;
+				Ok(key.as_bytes().to_vec())
+			}).unwrap();
+			assert_eq!(*handle, b"a");
+		}
+		assert_eq!(loads, 1);
+		assert!(cache.get_or_load("b", |_| Err("Not found".to_string())).is_err());
+		assert!(!cache.contains("b"));
+	}
+}
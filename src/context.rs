@@ -0,0 +1,132 @@
+use std::{cell::Cell, rc::Rc};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum ContextStatus {
+	Ok,
+	Lost,
+	/// The context has been restored but the resources have not been recreated yet.
+	Restored,
+}
+
+/// Keeps track of whether a graphics context has been lost and restored. The event handlers
+/// and the Dvr share it, and it is kept apart from the calls that recreate the resources so
+/// that the restore logic does not need a context.
+#[derive(Clone)]
+pub(crate) struct ContextState {
+	status: Rc<Cell<ContextStatus>>,
+}
+
+impl ContextState {
+	pub fn new() -> ContextState {
+		ContextState {
+			status: Rc::new(Cell::new(ContextStatus::Ok)),
+		}
+	}
+
+	/// Called when the context is lost.
+	pub fn lost(&self) {
+		self.status.set(ContextStatus::Lost);
+	}
+
+	/// Called when the context is available again. The resources still have to be recreated.
+	pub fn restored(&self) {
+		if self.status.get() == ContextStatus::Lost {
+			self.status.set(ContextStatus::Restored);
+		}
+	}
+
+	/// Returns true if the context works and the resources have been created for it.
+	pub fn is_ok(&self) -> bool {
+		self.status.get() == ContextStatus::Ok
+	}
+
+	/// Calls recreate if the context has been restored and is alive, and returns whether it
+	/// was called. The context only counts as working again if recreate succeeds, so a failed
+	/// attempt is made again the next time.
+	pub fn restore_with(&self, context_alive: bool, recreate: impl FnOnce() -> Result<(), String>) -> Result<bool, String> {
+		if self.status.get() != ContextStatus::Restored || !context_alive {
+			return Ok(false);
+		}
+		recreate()?;
+		// The context may have been lost again while the resources were being recreated
+		if self.status.get() == ContextStatus::Restored {
+			self.status.set(ContextStatus::Ok);
+		}
+		Ok(true)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn starts_ok() {
+		let state = ContextState::new();
+		assert!(state.is_ok());
+		assert_eq!(state.restore_with(true, || panic!("nothing to restore")), Ok(false));
+	}
+
+	#[test]
+	fn lost_restored_recreated() {
+		let state = ContextState::new();
+		state.lost();
+		assert!(!state.is_ok());
+		// Nothing can be recreated before the context is back
+		assert_eq!(state.restore_with(true, || panic!("context is still lost")), Ok(false));
+
+		state.restored();
+		assert!(!state.is_ok());
+		let mut recreated = 0;
+		assert_eq!(state.restore_with(true, || { recreated += 1; Ok(()) }), Ok(true));
+		assert_eq!(recreated, 1);
+		assert!(state.is_ok());
+		// The resources are only recreated once
+		assert_eq!(state.restore_with(true, || panic!("already restored")), Ok(false));
+	}
+
+	#[test]
+	fn waits_for_context_to_be_alive() {
+		let state = ContextState::new();
+		state.lost();
+		state.restored();
+		assert_eq!(state.restore_with(false, || panic!("context is not alive")), Ok(false));
+		assert!(!state.is_ok());
+		assert_eq!(state.restore_with(true, || Ok(())), Ok(true));
+		assert!(state.is_ok());
+	}
+
+	#[test]
+	fn failed_restore_is_retried() {
+		let state = ContextState::new();
+		state.lost();
+		state.restored();
+		assert_eq!(state.restore_with(true, || Err("out of memory".to_string())), Err("out of memory".to_string()));
+		assert!(!state.is_ok());
+		assert_eq!(state.restore_with(true, || Ok(())), Ok(true));
+		assert!(state.is_ok());
+	}
+
+	#[test]
+	fn lost_again_while_recreating() {
+		let state = ContextState::new();
+		state.lost();
+		state.restored();
+		let events = state.clone();
+		assert_eq!(state.restore_with(true, || { events.lost(); Ok(()) }), Ok(true));
+		assert!(!state.is_ok());
+		// The restored event has to come again before the next attempt
+		assert_eq!(state.restore_with(true, || panic!("context is lost")), Ok(false));
+		state.restored();
+		assert_eq!(state.restore_with(true, || Ok(())), Ok(true));
+		assert!(state.is_ok());
+	}
+
+	#[test]
+	fn restored_without_loss_is_ignored() {
+		let state = ContextState::new();
+		state.restored();
+		assert!(state.is_ok());
+		assert_eq!(state.restore_with(true, || panic!("nothing was lost")), Ok(false));
+	}
+}
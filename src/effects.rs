@@ -0,0 +1,198 @@
+#[cfg(any(target_arch = "wasm32", target_os = "windows"))]
+use crate::sdf::{DistanceField, DistanceFieldKind};
+
+/// Effects that are drawn around the shapes in a texture, e.g. glyphs, in one draw call
+/// (see Dvr::draw_with_effects() and Dvr::draw_sdf()). Distances are in texture pixels, so
+/// they scale with what is drawn. The effects are drawn in the order outline, glow, shadow,
+/// where each one is behind the ones before it.
+///
+/// With distance fields, the effects can only reach as far from the shapes as the field
+/// does (half of its range). Other textures are sampled around each pixel, which works best
+/// for shapes with sharp edges and effects that are a few pixels wide.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TextEffects {
+	/// The colour that the texture is multiplied by (red, green, blue and alpha, from 0 to 1).
+	pub colour: (f32, f32, f32, f32),
+	pub outline_colour: (f32, f32, f32, f32),
+	/// The width of the outline around the shapes, or 0 for no outline.
+	pub outline_width: f32,
+	/// The colour of the shadow, which has no effect if the alpha is 0.
+	pub shadow_colour: (f32, f32, f32, f32),
+	/// How far the shadow is moved, where y goes up like in Dvr coordinates.
+	pub shadow_offset: (f32, f32),
+	/// How far the edge of the shadow is blurred.
+	pub shadow_blur: f32,
+	pub glow_colour: (f32, f32, f32, f32),
+	/// How far the glow reaches outside the shapes (and the outline), or 0 for no glow.
+	pub glow_width: f32,
+}
+
+impl Default for TextEffects {
+	fn default() -> Self {
+		TextEffects {
+			colour: (1.0, 1.0, 1.0, 1.0),
+			outline_colour: (0.0, 0.0, 0.0, 1.0),
+			outline_width: 0.0,
+			shadow_colour: (0.0, 0.0, 0.0, 0.0),
+			shadow_offset: (0.0, 0.0),
+			shadow_blur: 0.0,
+			glow_colour: (1.0, 1.0, 1.0, 1.0),
+			glow_width: 0.0,
+		}
+	}
+}
+
+impl TextEffects {
+	/// The effects with the colours multiplied by colour. The outline, shadow and glow only
+	/// get the alpha, so that e.g. coloured text keeps a black outline but still fades out.
+	pub fn tinted(&self, (r, g, b, a): (f32, f32, f32, f32)) -> TextEffects {
+		let fade = |(cr, cg, cb, ca): (f32, f32, f32, f32)| (cr, cg, cb, ca * a);
+		TextEffects {
+			colour: (self.colour.0 * r, self.colour.1 * g, self.colour.2 * b, self.colour.3 * a),
+			outline_colour: fade(self.outline_colour),
+			shadow_colour: fade(self.shadow_colour),
+			glow_colour: fade(self.glow_colour),
+			..*self
+		}
+	}
+
+	/// Returns true if there is no outline, shadow or glow, so drawing is the same as
+	/// Dvr::draw_tinted() with the colour.
+	pub fn is_plain(&self) -> bool {
+		!self.has_outline() && !self.has_shadow() && !self.has_glow()
+	}
+
+	fn has_outline(&self) -> bool {
+		self.outline_width > 0.0 && self.outline_colour.3 > 0.0
+	}
+
+	fn has_shadow(&self) -> bool {
+		self.shadow_colour.3 > 0.0
+	}
+
+	fn has_glow(&self) -> bool {
+		self.glow_width > 0.0 && self.glow_colour.3 > 0.0
+	}
+
+	/// How far the effects reach outside the shapes, in texture pixels.
+	#[cfg(any(target_arch = "wasm32", target_os = "windows", test))]
+	pub(crate) fn margin(&self) -> f32 {
+		let outline = if self.has_outline() { self.outline_width } else { 0.0 };
+		let glow = if self.has_glow() { self.glow_width } else { 0.0 };
+		let shadow = match self.has_shadow() {
+			true => self.shadow_offset.0.abs().max(self.shadow_offset.1.abs()) + self.shadow_blur,
+			false => 0.0,
+		};
+		(outline + glow).max(outline + shadow).ceil()
+	}
+}
+
+/// The values that the effects shaders get, where the colours have premultiplied alpha.
+#[cfg(any(target_arch = "wasm32", target_os = "windows"))]
+pub(crate) struct EffectParams {
+	/// 1 for textures where the alpha is the coverage and 2 for distance fields.
+	pub mode: i32,
+	pub colour: [f32; 4],
+	pub outline_colour: [f32; 4],
+	pub shadow_colour: [f32; 4],
+	pub glow_colour: [f32; 4],
+	/// These are in units of the field for distance fields and in texture pixels otherwise.
+	pub outline_width: f32,
+	pub shadow_blur: f32,
+	pub glow_width: f32,
+	/// How many screen pixels the range of the field covers.
+	pub px_range: f32,
+	/// 1 for multi-channel distance fields and 0 otherwise.
+	pub multi_channel: f32,
+	/// 0 draws everything, 1 only the effects and 2 only the fill (see EffectPass).
+	pub pass: i32,
+}
+
+#[cfg(any(target_arch = "wasm32", target_os = "windows"))]
+impl EffectParams {
+	/// Scale is the number of screen pixels per texture pixel.
+	pub fn new(effects: &TextEffects, field: Option<DistanceField>, scale: f32, pass: EffectPass) -> EffectParams {
+		let premultiply = |(r, g, b, a): (f32, f32, f32, f32)| [r * a, g * a, b * a, a];
+		// Effects that are turned off are transparent, since e.g. the outline colour would
+		// otherwise show at the edges even without an outline
+		let colour_if = |on: bool, colour| if on { premultiply(colour) } else { [0.0; 4] };
+		let (mode, range, multi_channel) = match field {
+			Some(field) => (2, field.range.max(f32::EPSILON), match field.kind {
+				DistanceFieldKind::Sdf => 0.0,
+				DistanceFieldKind::Msdf => 1.0,
+			}),
+			None => (1, 1.0, 0.0),
+		};
+		EffectParams {
+			mode,
+			colour: premultiply(effects.colour),
+			outline_colour: colour_if(effects.has_outline(), effects.outline_colour),
+			shadow_colour: colour_if(effects.has_shadow(), effects.shadow_colour),
+			glow_colour: colour_if(effects.has_glow(), effects.glow_colour),
+			outline_width: effects.outline_width.max(0.0) / range,
+			shadow_blur: effects.shadow_blur.max(0.0) / range,
+			glow_width: effects.glow_width.max(0.0) / range,
+			// Below one pixel per range the edges would be wider than the shapes
+			px_range: (range * scale).max(1.0),
+			multi_channel,
+			pass: match pass {
+				EffectPass::All => 0,
+				EffectPass::Effects => 1,
+				EffectPass::Fill => 2,
+			},
+		}
+	}
+}
+
+/// The parts of effects that a draw call draws. Text with effects is drawn in two passes,
+/// the effects of all glyphs and then their fills, so that the effects of a glyph do not
+/// cover the glyphs before it.
+#[cfg(any(target_arch = "wasm32", target_os = "windows"))]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum EffectPass {
+	All,
+	/// The effects without the fill, where they are also drawn behind the fill.
+	Effects,
+	Fill,
+}
+
+#[cfg(any(target_arch = "wasm32", target_os = "windows"))]
+impl EffectPass {
+	/// The passes that text with effects is drawn in.
+	pub fn for_text(effects: &TextEffects) -> &'static [EffectPass] {
+		match effects.is_plain() {
+			true => &[EffectPass::All],
+			false => &[EffectPass::Effects, EffectPass::Fill],
+		}
+	}
+}
+
+/// The quad that is drawn for a texture with effects, which is larger than the texture
+/// rectangle so that there is room for the effects.
+#[cfg(any(target_arch = "wasm32", target_os = "windows", test))]
+pub(crate) struct EffectQuad {
+	pub size: (f32, f32),
+	/// The texture rectangle with the margin around it, in texture pixels.
+	pub tex_rect: ((f32, f32), (f32, f32)),
+	/// The texture rectangle without the margin. Everything outside it is treated as empty.
+	pub clamp_rect: ((f32, f32), (f32, f32)),
+}
+
+#[cfg(any(target_arch = "wasm32", target_os = "windows", test))]
+impl EffectQuad {
+	/// Grows a quad with a size and texture rectangle by the margin of the effects. The
+	/// centre stays where it is, so the quad can still be rotated around it.
+	pub fn new(effects: &TextEffects, size: (f32, f32), tex_rect: ((f32, f32), (f32, f32))) -> EffectQuad {
+		let margin = effects.margin();
+		let ((x, y), (w, h)) = tex_rect;
+		let grow = |drawn: f32, texture: f32| match texture > 0.0 {
+			true => drawn * (texture + 2.0 * margin) / texture,
+			false => drawn,
+		};
+		EffectQuad {
+			size: (grow(size.0, w), grow(size.1, h)),
+			tex_rect: ((x - margin, y - margin), (w + 2.0 * margin, h + 2.0 * margin)),
+			clamp_rect: tex_rect,
+		}
+	}
+}
@@ -1,11 +1,47 @@
-use std::cmp::{max, min};
+use std::{cell::RefCell, cmp::{max, min}, collections::HashMap, rc::Rc};
+use unicode_linebreak::{break_property, BreakClass};
+use unicode_segmentation::UnicodeSegmentation;
 
-use crate::{Dvr, Texture};
+use crate::{bmfont::BmFont, cache::Asset, effects::TextEffects, sdf::{DistanceField, DistanceFieldKind, SdfStyle}, ttf::{GlyphAtlas, TtfFont}, Texture};
+#[cfg(any(target_arch = "wasm32", target_os = "windows"))]
+use crate::{bundle::Bundle, effects::EffectPass, ttf::AtlasChanges, Dvr};
+
+#[cfg(any(target_arch = "wasm32", target_os = "windows"))]
+mod block;
+mod layout;
+mod markup;
+mod metrics;
+mod rich;
+mod tabs;
+mod wrap;
+
+#[cfg(any(target_arch = "wasm32", target_os = "windows"))]
+pub use block::TextBlock;
+pub use layout::{CharBox, LineBox, Overflow, PositionedGlyph, TextLayout, TextOptions};
+pub use markup::{RichText, Span, TextStyle};
+pub use metrics::{FontMetrics, GlyphMetrics};
+pub use rich::MarkupContext;
+pub use tabs::TabStop;
+pub use wrap::Hyphenator;
+use wrap::SOFT_HYPHEN;
 
 pub struct Font {
 	sheets: Vec<FontSheet>,
 	leading: f32,
 	tofu_char: char,
+	kerning: KerningTable,
+	hyphenator: Option<Hyphenator>,
+	/// The colour, outline, shadow and glow that draw_text() uses.
+	effects: TextEffects,
+	/// The effects without the glow, for get_sdf_style().
+	sdf_style: SdfStyle,
+	/// The fonts that characters which are not in the sheets are looked up in, in order.
+	fallbacks: Vec<Rc<Font>>,
+	tab_stops: Vec<TabStop>,
+	/// The distance between the tab stops after tab_stops, in units of the line height.
+	tab_width: Option<f32>,
+	/// The advance of every glyph in units of the line height, if the font is monospace.
+	monospace: Option<f32>,
 }
 
 impl Font {
@@ -14,59 +50,219 @@ impl Font {
 			sheets,
 			leading,
 			tofu_char,
+			kerning: KerningTable::new(),
+			hyphenator: None,
+			effects: TextEffects::default(),
+			sdf_style: SdfStyle::default(),
+			fallbacks: Vec::new(),
+			tab_stops: Vec::new(),
+			tab_width: None,
+			monospace: None,
 		}
 	}
 
-	pub fn draw_text(&self, dvr: &Dvr, text: &str, x: f32, y: f32, max_w: Option<f32>, h: f32, text_align: Align, vert_align: VAlign) -> Result<(), String> {
-		let text = match max_w {
-			Some(max_w) => &self.auto_line_split(text, max_w, h)?,
-			None => text,
-		};
-		let vert_align_factor: f32 = match vert_align {
-			VAlign::Top => -0.5,
-			VAlign::Centre => 0.0,
-			VAlign::Bottom => 0.5,
-		};
-		let num_lines = self.calculate_num_lines(text);
-		let mut y_offset: f32 = match vert_align {
-			VAlign::Top => 0.0,
-			VAlign::Centre => 0.5 * h * (num_lines as f32 - 1.0),
-			VAlign::Bottom => h * (num_lines as f32 - 1.0),
+	/// Sets the kerning table of the font. The pairs in the table are used instead of the
+	/// kerning of the sheets (which TrueType/OpenType sheets get from the font file).
+	pub fn set_kerning(&mut self, kerning: KerningTable) {
+		self.kerning = kerning;
+	}
+
+	pub fn get_kerning_table(&self) -> &KerningTable {
+		&self.kerning
+	}
+
+	/// Makes every grapheme cluster take up a cell that is advance wide (in units of the line
+	/// height), with the glyph centred in it, or lays the text out normally with None. There
+	/// is no kerning between monospace glyphs. Together with tab stops that are multiples of
+	/// the advance this lines up text in columns, e.g. for consoles and tables.
+	pub fn set_monospace(&mut self, advance: Option<f32>) {
+		self.monospace = advance;
+	}
+
+	pub fn get_monospace(&self) -> Option<f32> {
+		self.monospace
+	}
+
+	/// Sets the function that decides where words may be hyphenated when draw_text() wraps
+	/// lines. Without one, words are only hyphenated at soft hyphens (U+00AD).
+	pub fn set_hyphenator(&mut self, hyphenator: Option<Hyphenator>) {
+		self.hyphenator = hyphenator;
+	}
+
+	/// Adds a font to the end of the fallback chain. Characters that are not in the sheets
+	/// of this font are looked up in the fallback fonts (and their fallbacks) in the order they
+	/// were added, and only if no font has them the tofu character is drawn. Glyphs from every
+	/// sheet are scaled so that its line height is h and moved so that its baseline is on the
+	/// baseline of this font, so lines stay the same height when fonts are mixed. The
+	/// leading, kerning table and tofu character of this font are used for all of the text.
+	pub fn add_fallback(&mut self, font: Rc<Font>) {
+		self.fallbacks.push(font);
+	}
+
+	pub fn get_fallbacks(&self) -> &[Rc<Font>] {
+		&self.fallbacks
+	}
+
+	/// Returns true if the font or one of its fallbacks has a glyph for c.
+	pub fn has_char(&self, c: char) -> bool {
+		self.find_sheet(c).is_some()
+	}
+
+	/// Sets the effects that draw_text() draws the text with, e.g. an outline or a shadow.
+	/// Other ways of drawing the text (e.g. TextBlock) multiply the colour of the effects by
+	/// the colour of the text.
+	pub fn set_effects(&mut self, effects: TextEffects) {
+		self.effects = effects;
+		self.sdf_style = SdfStyle::from_effects(&effects);
+	}
+
+	pub fn get_effects(&self) -> &TextEffects {
+		&self.effects
+	}
+
+	/// Sets the colour, outline and shadow of the effects (see set_effects()) and keeps
+	/// the glow.
+	pub fn set_sdf_style(&mut self, style: SdfStyle) {
+		self.effects = style.with_glow(&self.effects);
+		self.sdf_style = style;
+	}
+
+	pub fn get_sdf_style(&self) -> &SdfStyle {
+		&self.sdf_style
+	}
+
+	/// Creates a font from a BMFont descriptor. Pages contains the textures of the pages,
+	/// in the same order as in the descriptor (see BmFont::page_paths()). The line height
+	/// of the descriptor becomes the h that is passed to draw_text(). The pages of distance
+	/// field fonts should be loaded with Dvr::load_data_texture(), and on the web this is
+	/// required for MTSDF fonts, whose alpha is a distance as well.
+	pub fn from_bmfont(bmfont: &BmFont, pages: Vec<Texture>, leading: f32, tofu_char: char) -> Result<Font, String> {
+		if pages.len() != bmfont.pages.len() {
+			return Err(format!("The font has {} pages but {} textures were given", bmfont.pages.len(), pages.len()));
+		}
+		let line_height = bmfont.line_height as f32;
+		let distance_field = match &bmfont.distance_field {
+			Some(field) => Some(DistanceField {
+				kind: match field.field_type.as_str() {
+					"sdf" | "psdf" => DistanceFieldKind::Sdf,
+					"msdf" | "mtsdf" => DistanceFieldKind::Msdf,
+					field_type => return Err(format!("Unsupported distance field type \"{}\"", field_type)),
+				},
+				range: field.distance_range as f32,
+			}),
+			None => None,
 		};
-		for line in text.lines() {
-			let mut x_offset: f32 = match text_align {
-				Align::Left => 0.0,
-				Align::Centre => -0.5 * self.calculate_line_width(line, h)?,
-				Align::Right => -self.calculate_line_width(line, h)?,
+		#[cfg(target_arch = "wasm32")]
+		if bmfont.distance_field.as_ref().is_some_and(|field| field.field_type == "mtsdf") && pages.iter().any(|page| page.is_premultiplied()) {
+			return Err("The pages of MTSDF fonts must be loaded with Dvr::load_data_texture()".to_string());
+		}
+		let mut sheets: Vec<FontSheet> = pages
+			.into_iter()
+			.map(|texture| FontSheet {
+				texture,
+				glyphs: RefCell::new(HashMap::new()),
+				sequences: HashMap::new(),
+				line_height,
+				baseline: bmfont.base as f32,
+				rasterizer: None,
+				distance_field,
+			})
+			.collect();
+		for c in &bmfont.chars {
+			let Some(ch) = char::from_u32(c.id) else {
+				continue;
 			};
-			for c in line.chars() {
-				let (c, sheet) = self.get_char_and_sheet(c, h)?;
-				let charw = sheet.get_char_width(c, h)?;
-				let (cx, cy) = sheet.get_pos(c);
-				let (cw, ch) = sheet.get_char_cell_size()?;
-				let actual_height = h / (1.0 - sheet.extra_ascent - sheet.extra_descent);
-				dvr.draw(
-					&sheet.texture,
-					x + x_offset + 0.5 * charw,
-					y + y_offset + vert_align_factor * h + (sheet.extra_ascent - sheet.extra_descent) * h,
-					Some((cw * actual_height / ch, actual_height)),
-					Some(((cx as f32 * cw, cy as f32 * ch), (cw, ch))),
-					0.0
-				)?;
-				x_offset += charw;
+			sheets[c.page as usize].glyphs.get_mut().insert(ch, Glyph {
+				pos: (c.x as f32, c.y as f32),
+				size: (c.width as f32, c.height as f32),
+				offset: (c.x_offset as f32, c.y_offset as f32),
+				advance: c.x_advance as f32,
+			});
+		}
+		let mut kerning = KerningTable::new();
+		for k in &bmfont.kernings {
+			if let (Some(first), Some(second)) = (char::from_u32(k.first), char::from_u32(k.second)) {
+				kerning.set(first, second, k.amount as f32 / line_height);
 			}
-			y_offset -= h * self.leading;
 		}
-		Ok(())
+		Ok(Font {
+			kerning,
+			..Font::new(sheets, leading, tofu_char)
+		})
+	}
+
+	/// Loads a BMFont descriptor and its pages. The pages are loaded from the same
+	/// directory as the descriptor.
+	#[cfg(target_os = "windows")]
+	pub fn load_bmfont(dvr: &Dvr, path: &str, leading: f32, tofu_char: char) -> Result<Font, String> {
+		let data = std::fs::read(path)
+			.map_err(|e| format!("Failed to read font file \"{}\" ({})", path, e))?;
+		let bmfont = BmFont::parse(&data)?;
+		let mut pages = Vec::new();
+		for page in bmfont.page_paths(path) {
+			pages.push(match bmfont.distance_field {
+				Some(_) => dvr.load_data_texture(&page)?,
+				None => dvr.load_texture(&page)?,
+			});
+		}
+		Self::from_bmfont(&bmfont, pages, leading, tofu_char)
+	}
+
+	/// Downloads a BMFont descriptor and its pages. The pages are loaded from the same
+	/// directory as the descriptor.
+	#[cfg(target_arch = "wasm32")]
+	pub async fn fetch_bmfont(dvr: &Dvr, url: &str, leading: f32, tofu_char: char) -> Result<Font, String> {
+		let bmfont = BmFont::parse(&crate::wasm_utils::fetch_bytes(url).await?)?;
+		let mut pages = Vec::new();
+		for page in bmfont.page_paths(url) {
+			pages.push(match bmfont.distance_field {
+				Some(_) => dvr.load_data_texture(&page).await?,
+				None => dvr.load_texture(&page).await?,
+			});
+		}
+		Self::from_bmfont(&bmfont, pages, leading, tofu_char)
 	}
 
+	/// Creates a font from a BMFont descriptor in an asset bundle. The pages are taken from
+	/// the bundle as well, relative to the name of the descriptor.
+	#[cfg(any(target_arch = "wasm32", target_os = "windows"))]
+	pub fn from_bmfont_bundle(dvr: &Dvr, bundle: &Bundle, name: &str, leading: f32, tofu_char: char) -> Result<Font, String> {
+		let bmfont = BmFont::parse(&bundle.get(name)?)?;
+		let mut pages = Vec::new();
+		for page in bmfont.page_paths(name) {
+			let data = bundle.get(&page)?;
+			let texture = match bmfont.distance_field {
+				Some(_) => dvr.load_data_texture_raw(&data),
+				None => dvr.load_texture_raw(&data),
+			};
+			let texture = texture
+				.map_err(|e| format!("Failed to load texture \"{}\" ({})", page, e))?;
+			pages.push(texture);
+		}
+		Self::from_bmfont(&bmfont, pages, leading, tofu_char)
+	}
+
+	/// Draws the text with the effects of the options, or else with the ones of the font
+	/// (see set_effects()). The effects of all glyphs are drawn before the glyphs, so that
+	/// they do not cover the glyphs before them. Returns true if the text was cut off (see
+	/// Overflow).
+	#[cfg(any(target_arch = "wasm32", target_os = "windows"))]
+	pub fn draw_text(&self, dvr: &Dvr, text: &str, x: f32, y: f32, h: f32, options: &TextOptions) -> Result<bool, String> {
+		let layout = self.layout_text(dvr, text, x, y, h, options)?;
+		layout.draw(dvr, (1.0, 1.0, 1.0, 1.0), options.effects.as_ref().unwrap_or(&self.effects))?;
+		Ok(layout.is_truncated())
+	}
+
+	/// The number of lines without wrapping, as counted by str::lines(). A line break at the end
+	/// of the text does not start a line here, see TextLayout::get_line_count() for that.
 	pub fn calculate_num_lines(&self, text: &str) -> usize {
 		text.lines().count()
 	}
 
 	pub fn calculate_text_width(&self, text: &str, h: f32) -> Result<f32, String> {
 		let mut max_width: f32 = 0.0;
-		for line in text.lines() {
+		for line in text.split('\n') {
+			let line = line.strip_suffix('\r').unwrap_or(line);
 			let line_width = self.calculate_line_width(line, h)?;
 			max_width = max_width.max(line_width);
 		}
@@ -74,129 +270,916 @@ impl Font {
 	}
 
 	fn calculate_line_width(&self, line: &str, h: f32) -> Result<f32, String> {
-		let mut width: f32 = 0.0;
-		for c in line.chars() {
-			let (c, sheet) = self.get_char_and_sheet(c, h)?;
-			let charw = sheet.get_char_width(c, h)?;
-			width += charw;
-		}
-		Ok(width)
-	}
-
-	fn auto_line_split(&self, text: &str, max_w: f32, h: f32) -> Result<String, String> {
-		let mut new_text = String::new();
-		let mut first = true;
-		for line in text.lines() {
-			if first {
-				first = false;
-			} else {
-				new_text.push('\n');
+		self.layout_line(line, h, |_| Ok(()))
+	}
+
+	/// Goes through the grapheme clusters of a line, calling f with where each one is placed.
+	/// Returns the width of the line. This is what measuring, wrapping and drawing use, so
+	/// that they always agree.
+	fn layout_line<'a>(&'a self, line: &str, h: f32, f: impl FnMut(&CharPlacement<'a>) -> Result<(), String>) -> Result<f32, String> {
+		self.layout_line_at(line, 0.0, h, f)
+	}
+
+	/// Like layout_line() for a line that starts at start_x from the start of the text line,
+	/// which is where tab stops are measured from. The positions are still relative to the
+	/// start of the line.
+	fn layout_line_at<'a>(&'a self, line: &str, start_x: f32, h: f32, mut f: impl FnMut(&CharPlacement<'a>) -> Result<(), String>) -> Result<f32, String> {
+		let mut pen_x: f32 = 0.0;
+		let mut prev: Option<(char, &FontSheet)> = None;
+		for (byte, cluster) in line.grapheme_indices(true) {
+			let mut chars = cluster.chars();
+			let c = chars.next().unwrap_or_default();
+			// Soft hyphens are invisible and only matter when wrapping
+			if c == SOFT_HYPHEN && chars.next().is_none() {
+				continue;
 			}
-			let mut width = 0.0;
-			for c in line.chars() {
-				let (c, sheet) = self.get_char_and_sheet(c, h)?;
-				let charw = sheet.get_char_width(c, h)?;
-				width += charw;
-				if width > max_w {
-					new_text.push('\n');
-					width = charw;
-				}
-				new_text.push(c);
+			if c == '\t' {
+				let (_, sheet, _) = self.get_char_glyph(' ')?;
+				let placement = CharPlacement {
+					c,
+					byte,
+					len: cluster.len(),
+					sheet,
+					glyph: Glyph::EMPTY,
+					marks: Vec::new(),
+					x: pen_x,
+					offset: 0.0,
+					y: 0.0,
+					advance: self.tab_advance(&line[byte + cluster.len()..], start_x + pen_x, h)?,
+				};
+				f(&placement)?;
+				pen_x += placement.advance;
+				prev = None;
+				continue;
 			}
+			let ClusterGlyphs { printed, sheet, glyph, marks, advance } = self.get_cluster_glyphs(cluster, h)?;
+			let kerning = match (prev, self.monospace) {
+				(Some(prev), None) => self.get_kerning(prev, (printed, sheet), h),
+				_ => 0.0,
+			};
+			pen_x += kerning;
+			let (advance, offset) = match self.monospace {
+				Some(cell) => (cell * h, 0.5 * (cell * h - advance)),
+				None => (advance, 0.0),
+			};
+			let placement = CharPlacement {
+				c,
+				byte,
+				len: cluster.len(),
+				sheet,
+				glyph,
+				marks,
+				x: pen_x,
+				offset,
+				y: self.get_baseline_shift(sheet, h),
+				advance,
+			};
+			f(&placement)?;
+			pen_x += placement.advance;
+			prev = Some((printed, sheet));
 		}
-		Ok(new_text)
-	}
-
-	/// This gets the character that will be printed and the sheet where it is.
-	/// Usually the character that is printed is simply c but if there is a tofu character
-	/// it may be the tofu character instead.
-	fn get_char_and_sheet(&self, c: char, h: f32) -> Result<(char, &FontSheet), String> {
-		let sheet = self.get_sheet(c);
-		match sheet {
-			Ok(_) => {},
-			Err(e) => match self.tofu_char {
-				'\0' => return Err(e),
-				_ => {
-					if c == self.tofu_char {
-						return Err("Tofu character does not exist".to_string());
-					}
-					return self.get_char_and_sheet(self.tofu_char, h);
-				}
+		Ok(pen_x)
+	}
+
+	/// Finds the glyphs that a grapheme cluster is drawn with. A sheet can have a glyph for
+	/// the whole cluster (see FontSheet::add_sequence()). Otherwise the first character is
+	/// drawn, combining marks that the font has are drawn on top of the character before them
+	/// and other characters (e.g. the second regional indicator of a flag or an emoji
+	/// modifier) are drawn after it. Everything from a zero width joiner on is left out, so
+	/// emoji sequences without a glyph show their first emoji.
+	fn get_cluster_glyphs(&self, cluster: &str, h: f32) -> Result<ClusterGlyphs<'_>, String> {
+		let mut chars = cluster.chars();
+		let c = chars.next().unwrap_or_default();
+		if chars.clone().next().is_some() {
+			// Emoji presentation selectors are often left out of the sequences in fonts
+			let without_selectors: String = cluster.chars().filter(|c| *c != '\u{fe0f}').collect();
+			if let Some((sheet, glyph)) = self.find_sequence(cluster).or_else(|| self.find_sequence(&without_selectors)) {
+				return Ok(ClusterGlyphs {
+					printed: c,
+					sheet,
+					glyph,
+					marks: Vec::new(),
+					advance: glyph.advance * sheet.get_scale(h),
+				});
 			}
 		}
-		let sheet = sheet?;
-		return Ok((c, sheet));
+		let (printed, sheet, glyph) = self.get_char_glyph(c)?;
+		// Where the glyph that marks go on starts and its advance
+		let mut base = (0.0, glyph.advance * sheet.get_scale(h));
+		let mut advance = base.1;
+		let mut marks = Vec::new();
+		for next in chars.take_while(|c| *c != '\u{200d}') {
+			if break_property(next as u32) != BreakClass::CombiningMark {
+				let (_, next_sheet, next_glyph) = self.get_char_glyph(next)?;
+				let next_advance = next_glyph.advance * next_sheet.get_scale(h);
+				marks.push(Mark {
+					sheet: next_sheet,
+					glyph: next_glyph,
+					x: advance,
+					y: self.get_baseline_shift(next_sheet, h),
+				});
+				base = (advance, next_advance);
+				advance += next_advance;
+				continue;
+			}
+			// Variation selectors and marks that the font does not have are not drawn
+			let Some(mark_sheet) = self.find_sheet(next) else {
+				continue;
+			};
+			let mark_glyph = mark_sheet.get_glyph(next)?;
+			let mark_advance = mark_glyph.advance * mark_sheet.get_scale(h);
+			let (base_x, base_advance) = base;
+			marks.push(Mark {
+				sheet: mark_sheet,
+				glyph: mark_glyph,
+				y: self.get_baseline_shift(mark_sheet, h),
+				// Marks without an advance (as in most TrueType fonts) are made to be drawn after
+				// the glyph they belong to. Other marks are centred on it.
+				x: match mark_advance {
+					0.0 => base_x + base_advance,
+					_ => base_x + 0.5 * (base_advance - mark_advance),
+				},
+			});
+		}
+		Ok(ClusterGlyphs { printed, sheet, glyph, marks, advance })
+	}
+
+	/// The kerning between two printed characters and their sheets when the line height is h.
+	fn get_kerning(&self, (first, first_sheet): (char, &FontSheet), (second, second_sheet): (char, &FontSheet), h: f32) -> f32 {
+		if let Some(amount) = self.kerning.get_pair(first, second) {
+			return amount * h;
+		}
+		match std::ptr::eq(first_sheet, second_sheet) {
+			true => second_sheet.get_kerning(first, second) * second_sheet.get_scale(h),
+			false => 0.0,
+		}
+	}
+
+	/// Gets the character that will be printed for c, the sheet where it is and its glyph.
+	/// Usually the character that is printed is simply c, but characters that are not in
+	/// the fallback chain are replaced by the tofu character. If there is no tofu character
+	/// either, the character gets an empty glyph and is not drawn.
+	fn get_char_glyph(&self, c: char) -> Result<(char, &FontSheet, Glyph), String> {
+		let (printed, sheet) = match self.find_sheet(c).map(|sheet| (c, sheet)).or_else(|| self.find_tofu()) {
+			Some(found) => found,
+			None => {
+				let sheet = self.get_first_sheet().ok_or("The font has no sheets")?;
+				return Ok((c, sheet, Glyph::EMPTY));
+			},
+		};
+		Ok((printed, sheet, sheet.get_glyph(printed)?))
+	}
+
+	/// Finds the first sheet in the fallback chain that has c.
+	fn find_sheet(&self, c: char) -> Option<&FontSheet> {
+		self.sheets
+			.iter()
+			.find(|sheet| sheet.has_glyph(c))
+			.or_else(|| self.fallbacks.iter().find_map(|font| font.find_sheet(c)))
 	}
 
-	fn get_sheet(&self, c: char) -> Result<&FontSheet, String> {
+	/// Finds the first sheet in the fallback chain with a glyph for a sequence.
+	fn find_sequence(&self, sequence: &str) -> Option<(&FontSheet, Glyph)> {
+		self.sheets
+			.iter()
+			.find_map(|sheet| sheet.sequences.get(sequence).map(|glyph| (sheet, *glyph)))
+			.or_else(|| self.fallbacks.iter().find_map(|font| font.find_sequence(sequence)))
+	}
+
+	/// Finds the tofu character of this font, or else of the first fallback that has one.
+	fn find_tofu(&self) -> Option<(char, &FontSheet)> {
+		let tofu = match self.tofu_char {
+			'\0' => None,
+			tofu => self.find_sheet(tofu).map(|sheet| (tofu, sheet)),
+		};
+		tofu.or_else(|| self.fallbacks.iter().find_map(|font| font.find_tofu()))
+	}
+
+	/// The first sheet in the fallback chain, whose baseline is the baseline of the text.
+	fn get_first_sheet(&self) -> Option<&FontSheet> {
+		self.sheets
+			.first()
+			.or_else(|| self.fallbacks.iter().find_map(|font| font.get_first_sheet()))
+	}
+
+	/// How far glyphs from a sheet are moved down so that the baseline of the sheet is
+	/// on the baseline of the text.
+	fn get_baseline_shift(&self, sheet: &FontSheet, h: f32) -> f32 {
+		match self.get_first_sheet() {
+			Some(first) => first.baseline * first.get_scale(h) - sheet.baseline * sheet.get_scale(h),
+			None => 0.0,
+		}
+	}
+
+	/// All sheets of the fallback chain, in the order they are searched.
+	#[cfg(any(target_arch = "wasm32", target_os = "windows"))]
+	fn get_chain_sheets(&self) -> Vec<&FontSheet> {
+		let mut sheets: Vec<&FontSheet> = self.sheets.iter().collect();
+		for font in &self.fallbacks {
+			sheets.extend(font.get_chain_sheets());
+		}
+		sheets
+	}
+
+	/// Uploads the glyphs that have been rasterized since the last time in all sheets of
+	/// the fallback chain.
+	#[cfg(any(target_arch = "wasm32", target_os = "windows"))]
+	fn update_textures(&self, dvr: &Dvr) -> Result<(), String> {
 		for sheet in &self.sheets {
-			if c >= sheet.range.0 && c <= sheet.range.1 && sheet.char_widths[c as usize - sheet.range.0 as usize] != 0.0 {
-				return Ok(&sheet);
-			}
+			sheet.update_texture(dvr)?;
+		}
+		for font in &self.fallbacks {
+			font.update_textures(dvr)?;
 		}
-		Err("Character not in font".to_string())
+		Ok(())
 	}
 }
 
+impl Asset for Font {
+	fn memory(&self) -> usize {
+		self.sheets
+			.iter()
+			.map(|sheet| sheet.texture.get_memory())
+			.sum()
+	}
+}
+
+/// Where a grapheme cluster of a line is placed.
+#[derive(Clone)]
+struct CharPlacement<'a> {
+	/// The first character of the cluster in the text. This may not be the character that
+	/// is drawn (see get_char_and_sheet()).
+	c: char,
+	/// Where the cluster is in the line and its length, in bytes.
+	byte: usize,
+	len: usize,
+	sheet: &'a FontSheet,
+	glyph: Glyph,
+	/// The other glyphs of the cluster (see get_cluster_glyphs()).
+	marks: Vec<Mark<'a>>,
+	/// The position of the cursor relative to the start of the line, including the kerning.
+	x: f32,
+	/// How far the glyph is moved right from the cursor, which centres it in its cell with
+	/// monospace fonts.
+	offset: f32,
+	/// How far the glyph is moved down to put it on the baseline (see get_baseline_shift()).
+	y: f32,
+	/// The scaled advance of the glyph.
+	advance: f32,
+}
+
+impl<'a> CharPlacement<'a> {
+	/// The glyphs of the cluster with the positions of the cursor and the tops of the lines
+	/// to draw them at, when the cursor is at x for the cluster and the line starts at line_top.
+	fn glyphs(&self, (x, line_top): (f32, f32)) -> impl Iterator<Item = (&'a FontSheet, Glyph, (f32, f32))> + '_ {
+		let x = x + self.offset;
+		std::iter::once((self.sheet, self.glyph, (x, line_top - self.y)))
+			.chain(self.marks.iter().map(move |mark| (mark.sheet, mark.glyph, (x + mark.x, line_top - mark.y))))
+	}
+}
+
+/// The glyphs that a grapheme cluster is drawn with (see Font::get_cluster_glyphs()).
+struct ClusterGlyphs<'a> {
+	/// The character that is printed first, for kerning.
+	printed: char,
+	sheet: &'a FontSheet,
+	glyph: Glyph,
+	marks: Vec<Mark<'a>>,
+	/// The scaled advance of all the glyphs together.
+	advance: f32,
+}
+
+/// A glyph that is drawn with another glyph, e.g. a combining accent on top of it or the
+/// second half of a flag after it.
+#[derive(Clone)]
+struct Mark<'a> {
+	sheet: &'a FontSheet,
+	glyph: Glyph,
+	/// The position of the cursor for the mark relative to the cursor for the glyph it is on.
+	x: f32,
+	/// How far the mark is moved down to put it on the baseline.
+	y: f32,
+}
+
+/// Extra space between pairs of characters, e.g. to move "A" and "V" closer together.
+/// The amounts are fractions of the line height, so that they work at any size.
+/// Negative amounts move the characters closer together.
+#[derive(Clone, Default)]
+pub struct KerningTable {
+	pairs: HashMap<(char, char), f32>,
+}
+
+impl KerningTable {
+	pub fn new() -> KerningTable {
+		KerningTable {
+			pairs: HashMap::new(),
+		}
+	}
+
+	/// Sets the kerning between first and second, when first comes before second.
+	pub fn set(&mut self, first: char, second: char, amount: f32) {
+		self.pairs.insert((first, second), amount);
+	}
+
+	/// Returns the kerning between two characters, or 0 if the pair is not in the table.
+	pub fn get(&self, first: char, second: char) -> f32 {
+		self.get_pair(first, second).unwrap_or(0.0)
+	}
+
+	fn get_pair(&self, first: char, second: char) -> Option<f32> {
+		self.pairs.get(&(first, second)).copied()
+	}
+
+	pub fn len(&self) -> usize {
+		self.pairs.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.pairs.is_empty()
+	}
+}
+
+impl FromIterator<((char, char), f32)> for KerningTable {
+	fn from_iter<T: IntoIterator<Item = ((char, char), f32)>>(iter: T) -> Self {
+		KerningTable {
+			pairs: iter.into_iter().collect(),
+		}
+	}
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum Align {
 	Left,
 	Centre,
 	Right,
+	/// Like Left, but the spaces on lines that are wrapped are stretched so that the lines
+	/// are max_w wide. Lines that end a paragraph, and all lines without max_w, are not stretched.
+	Justify,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum VAlign {
 	Bottom,
 	Centre,
 	Top,
 }
 
+/// A texture containing glyphs.
 pub struct FontSheet {
-	range: (char, char),
 	texture: Texture,
-	chars_per_row: usize,
-	rows: usize,
-	extra_ascent: f32,
-	extra_descent: f32,
-	char_widths: Vec<f32>,
+	glyphs: RefCell<HashMap<char, Glyph>>,
+	/// Glyphs for grapheme clusters with more than one character.
+	sequences: HashMap<String, Glyph>,
+	/// The height of a line in texture pixels. The glyphs are scaled so that this
+	/// becomes the h that is passed to draw_text().
+	line_height: f32,
+	/// The distance from the top of a line to the baseline in texture pixels.
+	baseline: f32,
+	/// Sheets created from TrueType/OpenType fonts add glyphs to the texture the
+	/// first time they are used.
+	rasterizer: Option<RefCell<Rasterizer>>,
+	/// Set if the texture is a distance field, so that the glyphs stay sharp at any size.
+	distance_field: Option<DistanceField>,
+}
+
+struct Rasterizer {
+	font: TtfFont,
+	px: f32,
+	ascent: f32,
+	atlas: GlyphAtlas,
+}
+
+impl Rasterizer {
+	fn rasterize(&mut self, c: char) -> Result<Glyph, String> {
+		let glyph = self.font.rasterize(c, self.px);
+		let (x, y) = self.atlas.insert(glyph.width, glyph.height, &glyph.coverage)?;
+		Ok(Glyph {
+			pos: (x as f32, y as f32),
+			size: (glyph.width as f32, glyph.height as f32),
+			offset: (glyph.left, self.ascent - glyph.top),
+			advance: glyph.advance,
+		})
+	}
+}
+
+/// Where a glyph is in the texture of a FontSheet and how it is placed. Everything
+/// is in texture pixels.
+#[derive(Clone, Copy)]
+struct Glyph {
+	/// The top left corner of the glyph in the texture.
+	pos: (f32, f32),
+	size: (f32, f32),
+	/// Where the top left corner of the glyph is drawn, relative to the cursor
+	/// and the top of the line.
+	offset: (f32, f32),
+	/// How far the cursor moves after the glyph.
+	advance: f32,
+}
+
+/// A glyph in a texture for FontSheet::from_rects() and FontSheet::add_glyph(). Everything
+/// is in texture pixels.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct GlyphRect {
+	/// The top left corner of the glyph in the texture.
+	pub pos: (f32, f32),
+	pub size: (f32, f32),
+	/// Where the top left corner of the glyph is drawn, relative to the cursor and the top
+	/// of the line, where y goes down.
+	pub offset: (f32, f32),
+	/// How far the cursor moves after the glyph.
+	pub advance: f32,
+}
+
+impl From<GlyphRect> for Glyph {
+	fn from(rect: GlyphRect) -> Glyph {
+		Glyph {
+			pos: rect.pos,
+			size: rect.size,
+			offset: rect.offset,
+			advance: rect.advance,
+		}
+	}
+}
+
+impl Glyph {
+	/// A glyph that is not drawn and takes up no space.
+	const EMPTY: Glyph = Glyph {
+		pos: (0.0, 0.0),
+		size: (0.0, 0.0),
+		offset: (0.0, 0.0),
+		advance: 0.0,
+	};
 }
 
 impl FontSheet {
+	/// Creates a sheet where the characters in range are laid out in a grid, row by row.
+	/// Extra ascent and descent are the fractions of the cells that are above and below the
+	/// line. Char widths contains, for each character, the fraction of the cell on each side
+	/// of the character that is not part of it. Characters with 0.5 are not in the sheet.
 	pub fn new(range: (char, char), texture: Texture, chars_per_row: usize, rows: usize, extra_ascent: f32, extra_descent: f32, char_widths: Vec<f32>) -> Result<FontSheet, String> {
 		let range = (min(range.0, range.1), max(range.0, range.1));
 		if char_widths.len() != (range.1 as usize - range.0 as usize + 1) {
 			return Err("The number of character widths does not match the number of characters in the range".to_string());
 		}
+		let cells: Vec<(char, usize, f32)> = char_widths
+			.iter()
+			.enumerate()
+			.filter_map(|(i, margin)| char::from_u32(range.0 as u32 + i as u32).map(|c| (c, i, *margin)))
+			.collect();
+		Self::from_cells(texture, chars_per_row, rows, extra_ascent, extra_descent, &cells)
+	}
+
+	/// Like new() but only the characters in cells are in the sheet, so e.g. Latin-1 and a few
+	/// symbols can share a small texture. Each character has the index of its cell (counted row
+	/// by row) and the fraction of the cell on each side of it that is not part of it.
+	pub fn from_cells(texture: Texture, chars_per_row: usize, rows: usize, extra_ascent: f32, extra_descent: f32, cells: &[(char, usize, f32)]) -> Result<FontSheet, String> {
+		if chars_per_row == 0 || rows == 0 {
+			return Err("A grid sheet needs at least one row and column".to_string());
+		}
+		let (w, h) = texture.get_size();
+		let cell_w = (w as usize / chars_per_row) as f32;
+		let cell_h = (h as usize / rows) as f32;
+		let mut glyphs = HashMap::new();
+		for &(c, i, margin) in cells {
+			if i >= chars_per_row * rows {
+				return Err(format!("The cell of '{}' is outside the grid", c));
+			}
+			let width = 1.0 - 2.0 * margin;
+			if width == 0.0 {
+				continue;
+			}
+			let advance = cell_w * width;
+			glyphs.insert(c, Glyph {
+				pos: ((i % chars_per_row) as f32 * cell_w, (i / chars_per_row) as f32 * cell_h),
+				size: (cell_w, cell_h),
+				// The cell is centred on the advance
+				offset: (0.5 * (advance - cell_w), -extra_ascent * cell_h),
+				advance,
+			});
+		}
+		let line_height = cell_h * (1.0 - extra_ascent - extra_descent);
 		Ok(FontSheet {
-			range,
 			texture,
-			chars_per_row,
-			rows,
-			extra_ascent,
-			extra_descent,
-			char_widths: char_widths.iter().map(|x| 1.0 - 2.0 * x).collect(),
+			glyphs: RefCell::new(glyphs),
+			sequences: HashMap::new(),
+			line_height,
+			// Grid sheets do not know where their baseline is
+			baseline: line_height,
+			rasterizer: None,
+			distance_field: None,
 		})
 	}
 
-	fn get_char_cell_size(&self) -> Result<(f32, f32), String> {
+	/// Creates a sheet where every glyph has its own rectangle in the texture, e.g. from a
+	/// texture packer. Line height and baseline are in texture pixels, where the baseline is
+	/// the distance from the top of a line to the baseline.
+	pub fn from_rects(texture: Texture, line_height: f32, baseline: f32, glyphs: &[(char, GlyphRect)]) -> Result<FontSheet, String> {
+		if line_height <= 0.0 {
+			return Err("The line height of a sheet has to be positive".to_string());
+		}
+		let mut sheet = FontSheet {
+			texture,
+			glyphs: RefCell::new(HashMap::new()),
+			sequences: HashMap::new(),
+			line_height,
+			baseline,
+			rasterizer: None,
+			distance_field: None,
+		};
+		for &(c, rect) in glyphs {
+			sheet.add_glyph(c, rect)?;
+		}
+		Ok(sheet)
+	}
+
+	/// Adds a glyph for c, or replaces the glyph the sheet has for it. The rectangle has to be
+	/// inside the texture.
+	pub fn add_glyph(&mut self, c: char, rect: GlyphRect) -> Result<(), String> {
+		if !self.is_in_texture(&rect) {
+			return Err(format!("The rectangle of '{}' is outside the texture", c));
+		}
+		self.glyphs.get_mut().insert(c, rect.into());
+		Ok(())
+	}
+
+	fn is_in_texture(&self, rect: &GlyphRect) -> bool {
 		let (w, h) = self.texture.get_size();
-		Ok(((w as usize / self.chars_per_row) as f32, (h as usize / self.rows) as f32))
+		let ((x, y), (gw, gh)) = (rect.pos, rect.size);
+		x >= 0.0 && y >= 0.0 && gw >= 0.0 && gh >= 0.0 && x + gw <= w as f32 && y + gh <= h as f32
+	}
+
+	/// Creates a sheet that rasterizes the glyphs of a TrueType/OpenType font at a size in
+	/// pixels (the distance between two lines) and packs them into a texture that grows as
+	/// needed. The glyphs are rasterized the first time they are used, so any character
+	/// in the font can be drawn. Drawing the text at the same h as the size gives the
+	/// sharpest result.
+	#[cfg(any(target_arch = "wasm32", target_os = "windows"))]
+	pub fn from_ttf(dvr: &Dvr, font: TtfFont, px_size: f32) -> Result<FontSheet, String> {
+		let mut atlas = GlyphAtlas::new(256, 256);
+		let texture = dvr.load_texture_for_updates(&atlas.to_image())?;
+		atlas.take_changes();
+		Self::from_atlas(texture, atlas, font, px_size)
+	}
+
+	/// Like from_ttf(), with an atlas that has been uploaded to texture.
+	#[cfg(any(target_arch = "wasm32", target_os = "windows", test))]
+	fn from_atlas(texture: Texture, atlas: GlyphAtlas, font: TtfFont, px_size: f32) -> Result<FontSheet, String> {
+		let metrics = font.get_line_metrics(1.0)?;
+		// The font is rasterized at the size that gives the requested line height
+		let px = px_size / metrics.line_height();
+		let ascent = metrics.ascent * px;
+		Ok(FontSheet {
+			texture,
+			glyphs: RefCell::new(HashMap::new()),
+			sequences: HashMap::new(),
+			line_height: px_size,
+			baseline: ascent,
+			rasterizer: Some(RefCell::new(Rasterizer {
+				font,
+				px,
+				ascent,
+				atlas,
+			})),
+			distance_field: None,
+		})
+	}
+
+	/// Adds a glyph for a sequence of characters that form one grapheme cluster, e.g. a letter
+	/// with a combining accent, an emoji ZWJ sequence or a flag. The rectangle has to be
+	/// inside the texture, like in add_glyph().
+	pub fn add_sequence(&mut self, sequence: &str, rect: GlyphRect) -> Result<(), String> {
+		if !self.is_in_texture(&rect) {
+			return Err(format!("The rectangle of \"{}\" is outside the texture", sequence));
+		}
+		self.sequences.insert(sequence.to_string(), rect.into());
+		Ok(())
+	}
+
+	/// Sets the distance from the top of a line to the baseline in texture pixels, which is
+	/// used to align the glyphs with the glyphs from other sheets (see Font::add_fallback()).
+	/// This is the bottom of the line for sheets created with new().
+	pub fn set_baseline(&mut self, baseline: f32) {
+		self.baseline = baseline;
+	}
+
+	/// Makes the glyphs be drawn as a distance field (see Dvr::draw_sdf()), or as a normal
+	/// texture with None. Sheets from BMFont descriptors with a distanceField tag have this
+	/// set already.
+	pub fn set_distance_field(&mut self, distance_field: Option<DistanceField>) {
+		self.distance_field = distance_field;
+	}
+
+	pub fn get_distance_field(&self) -> Option<DistanceField> {
+		self.distance_field
 	}
 
-	fn get_char_width(&self, c: char, h: f32) -> Result<f32, String> {
-		let (sheet_width, sheet_height) = self.texture.get_size();
-		if c < self.range.0 || c > self.range.1 {
-			return Err("Character not in sheet".to_string());
+	fn has_glyph(&self, c: char) -> bool {
+		self.glyphs.borrow().contains_key(&c)
+			|| self.rasterizer.as_ref().is_some_and(|rasterizer| rasterizer.borrow().font.has_glyph(c))
+	}
+
+	/// Uploads the glyphs that have been rasterized since the last time. The texture is only
+	/// created again when the atlas has grown.
+	#[cfg(any(target_arch = "wasm32", target_os = "windows"))]
+	fn update_texture(&self, dvr: &Dvr) -> Result<(), String> {
+		if let Some(rasterizer) = &self.rasterizer {
+			let atlas = &mut rasterizer.borrow_mut().atlas;
+			if self.texture.take_contents_lost() {
+				atlas.invalidate();
+			}
+			match atlas.take_changes() {
+				AtlasChanges::None => {},
+				AtlasChanges::Resized => self.texture.set(dvr.load_texture_for_updates(&atlas.to_image())?),
+				AtlasChanges::Rects(rects) => for rect in rects {
+					dvr.update_texture(&self.texture, rect.x, rect.y, &atlas.copy_rect(rect)?)?;
+				},
+			}
+		}
+		Ok(())
+	}
+
+	/// The kerning from the font file in texture pixels. Only sheets created from
+	/// TrueType/OpenType fonts have this.
+	fn get_kerning(&self, first: char, second: char) -> f32 {
+		match &self.rasterizer {
+			Some(rasterizer) => {
+				let rasterizer = rasterizer.borrow();
+				rasterizer.font.get_kerning(first, second, rasterizer.px)
+			},
+			None => 0.0,
+		}
+	}
+
+	/// Where a glyph is drawn (its centre and size) with the cursor at x and the top of
+	/// the line at line_top, or None if the glyph is empty.
+	fn glyph_quad(&self, glyph: Glyph, x: f32, line_top: f32, h: f32) -> Option<((f32, f32), (f32, f32))> {
+		if glyph.size.0 == 0.0 || glyph.size.1 == 0.0 {
+			return None;
 		}
-		let charw = self.char_widths[c as usize - self.range.0 as usize];
-		let scale = h * self.rows as f32 / sheet_height as f32  / (1.0 - self.extra_ascent - self.extra_descent);
-		Ok((sheet_width as usize / self.chars_per_row) as f32 * charw * scale)
+		let scale = self.get_scale(h);
+		let (w, gh) = (glyph.size.0 * scale, glyph.size.1 * scale);
+		let left = x + glyph.offset.0 * scale;
+		let top = line_top - glyph.offset.1 * scale;
+		Some(((left + 0.5 * w, top - 0.5 * gh), (w, gh)))
+	}
+
+	/// Draws a pass of a glyph at a quad from glyph_quad(), with the colour of effects
+	/// multiplied by colour.
+	#[cfg(any(target_arch = "wasm32", target_os = "windows"))]
+	fn draw_glyph(&self, dvr: &Dvr, glyph: Glyph, quad: ((f32, f32), (f32, f32)), colour: (f32, f32, f32, f32), effects: &TextEffects, pass: EffectPass) -> Result<(), String> {
+		dvr.draw_effect_pass(&self.texture, quad, (glyph.pos, glyph.size), self.distance_field, &effects.tinted(colour), pass)
+	}
+
+	/// How much the glyphs are scaled when the line height is h.
+	fn get_scale(&self, h: f32) -> f32 {
+		h / self.line_height
+	}
+
+	fn get_glyph(&self, c: char) -> Result<Glyph, String> {
+		if let Some(glyph) = self.glyphs.borrow().get(&c) {
+			return Ok(*glyph);
+		}
+		let rasterizer = self.rasterizer
+			.as_ref()
+			.filter(|rasterizer| rasterizer.borrow().font.has_glyph(c))
+			.ok_or("Character not in sheet")?;
+		let glyph = rasterizer.borrow_mut().rasterize(c)?;
+		self.glyphs.borrow_mut().insert(c, glyph);
+		Ok(glyph)
+	}
+}
+
+// The sheets are made with the headless Texture, which has no backend
+#[cfg(all(test, not(any(target_arch = "wasm32", target_os = "windows"))))]
+mod tests {
+	use super::*;
+
+	const TEST_FONT: &[u8] = include_bytes!("../test_data/test_font.ttf");
+
+	/// A sheet where line height is 10 and the glyphs have the advances in the list.
+	fn rect_sheet(advances: &[(char, f32)]) -> FontSheet {
+		let glyphs: Vec<(char, GlyphRect)> = advances
+			.iter()
+			.enumerate()
+			.map(|(i, &(c, advance))| (c, GlyphRect {
+				pos: (i as f32 * 10.0, 0.0),
+				size: (advance, 10.0),
+				offset: (0.0, 0.0),
+				advance,
+			}))
+			.collect();
+		FontSheet::from_rects(Texture::new(256, 16), 10.0, 8.0, &glyphs).unwrap()
+	}
+
+	/// A sheet from the test font with a line height of 100, where the advances are
+	/// A 60, V 60, o 50 and space 25 and the kerning is A V -8, V A -6, V o -4.
+	fn ttf_sheet() -> FontSheet {
+		let font = TtfFont::parse(TEST_FONT).unwrap();
+		FontSheet::from_atlas(Texture::new(256, 256), GlyphAtlas::new(256, 256), font, 100.0).unwrap()
+	}
+
+	/// The width of a line worked out from the placements: the advances of all clusters
+	/// plus the kerning between them, which is how far each cluster is from where the
+	/// one before it ended.
+	fn placed_width(font: &Font, line: &str, h: f32) -> (f32, f32) {
+		let mut advances = 0.0;
+		let mut kerning = 0.0;
+		let mut pen = 0.0;
+		font.layout_line(line, h, |placement| {
+			kerning += placement.x - pen;
+			advances += placement.advance;
+			pen = placement.x + placement.advance;
+			Ok(())
+		}).unwrap();
+		(advances, kerning)
+	}
+
+	fn assert_width(font: &Font, line: &str, h: f32, advances: f32, kerning: f32) {
+		assert_eq!(placed_width(font, line, h), (advances, kerning), "{:?}", line);
+		assert_eq!(font.calculate_line_width(line, h).unwrap(), advances + kerning, "{:?}", line);
+		let layout = font.measure_text(line, 0.0, 0.0, h, &TextOptions::default()).unwrap();
+		assert_eq!(layout.get_lines()[0].width, advances + kerning, "{:?}", line);
+	}
+
+	#[test]
+	fn width_with_kerning_table() {
+		let mut font = Font::new(vec![rect_sheet(&[('A', 6.0), ('V', 6.0), ('o', 5.0)])], 1.0, '\0');
+		font.set_kerning([(('A', 'V'), -0.1), (('V', 'A'), -0.05)].into_iter().collect());
+		// The sheet is scaled by 2, and the kerning is a fraction of h
+		assert_width(&font, "AVAo", 20.0, 12.0 + 12.0 + 12.0 + 10.0, -2.0 - 1.0);
+		assert_width(&font, "oVo", 20.0, 10.0 + 12.0 + 10.0, 0.0);
+		assert_width(&font, "", 20.0, 0.0, 0.0);
+	}
+
+	#[test]
+	fn width_with_font_kerning() {
+		let mut font = Font::new(vec![ttf_sheet()], 1.0, '\0');
+		assert_width(&font, "AVo", 100.0, 60.0 + 60.0 + 50.0, -8.0 - 4.0);
+		assert_width(&font, "VA V", 50.0, 30.0 + 30.0 + 12.5 + 30.0, -3.0);
+		// The kerning table replaces the kerning of the font for the pairs it has
+		font.set_kerning([(('A', 'V'), 0.0)].into_iter().collect());
+		assert_width(&font, "AVo", 100.0, 60.0 + 60.0 + 50.0, -4.0);
+	}
+
+	#[test]
+	fn width_with_mixed_sheets() {
+		let fallback = Font::new(vec![ttf_sheet()], 1.0, '\0');
+		let mut font = Font::new(vec![rect_sheet(&[('A', 6.0), (' ', 2.0)])], 1.0, '\0');
+		font.add_fallback(Rc::new(fallback));
+		// A is from the first sheet, so only V o is kerned by the font
+		assert_width(&font, "AVo", 100.0, 60.0 + 60.0 + 50.0, -4.0);
+		assert_width(&font, "A A", 100.0, 60.0 + 20.0 + 60.0, 0.0);
+		// The kerning table works between sheets as well
+		font.set_kerning([(('A', 'V'), -0.05)].into_iter().collect());
+		assert_width(&font, "AVo", 100.0, 60.0 + 60.0 + 50.0, -5.0 - 4.0);
+	}
+
+	/// The advance of the only cluster of a line and where its other glyphs are drawn.
+	fn cluster_glyphs(font: &Font, cluster: &str) -> (f32, Vec<f32>) {
+		let mut found = Vec::new();
+		font.layout_line(cluster, 10.0, |placement| {
+			found.push((placement.advance, placement.marks.iter().map(|mark| mark.x).collect()));
+			Ok(())
+		}).unwrap();
+		assert_eq!(found.len(), 1, "{:?}", cluster);
+		found.remove(0)
+	}
+
+	#[test]
+	fn marks_and_following_glyphs() {
+		let font = Font::new(vec![rect_sheet(&[
+			('e', 5.0),
+			('\u{301}', 3.0),
+			('\u{1f1eb}', 8.0),
+			('\u{1f1f7}', 8.0),
+			('\u{1f44d}', 10.0),
+			('\u{1f3fd}', 4.0),
+		])], 1.0, '\0');
+		// Combining marks are centred on the character
+		assert_eq!(cluster_glyphs(&font, "e\u{301}"), (5.0, vec![1.0]));
+		// Regional indicators and emoji modifiers are drawn after it
+		assert_eq!(cluster_glyphs(&font, "\u{1f1eb}\u{1f1f7}"), (16.0, vec![8.0]));
+		assert_eq!(cluster_glyphs(&font, "\u{1f44d}\u{1f3fd}"), (14.0, vec![10.0]));
+		// A mark after them is centred on the glyph before it
+		assert_eq!(cluster_glyphs(&font, "\u{1f1eb}\u{1f1f7}\u{301}"), (16.0, vec![8.0, 10.5]));
+		// Everything from a zero width joiner on is left out
+		assert_eq!(cluster_glyphs(&font, "\u{1f44d}\u{200d}\u{1f44d}"), (10.0, vec![]));
+		assert_width(&font, "e\u{301}\u{1f1eb}\u{1f1f7}e", 10.0, 5.0 + 16.0 + 5.0, 0.0);
+	}
+
+	#[test]
+	fn monospace_has_no_kerning() {
+		let mut font = Font::new(vec![ttf_sheet()], 1.0, '\0');
+		font.set_monospace(Some(0.7));
+		assert_width(&font, "AVo", 100.0, 210.0, 0.0);
+	}
+
+	#[test]
+	fn sdf_style_is_effects_without_glow() {
+		let mut font = Font::new(Vec::new(), 1.0, '\0');
+		font.set_effects(TextEffects { glow_width: 3.0, shadow_blur: 2.0, ..TextEffects::default() });
+		assert_eq!(font.get_sdf_style().shadow_softness, 2.0);
+		let style = SdfStyle { outline_width: 1.5, ..SdfStyle::default() };
+		font.set_sdf_style(style);
+		assert_eq!(font.get_sdf_style(), &style);
+		assert_eq!(font.get_effects().outline_width, 1.5);
+		assert_eq!(font.get_effects().shadow_blur, 0.0);
+		assert_eq!(font.get_effects().glow_width, 3.0);
+	}
+
+	#[test]
+	fn sequences_are_in_the_texture() {
+		let mut sheet = rect_sheet(&[('e', 5.0)]);
+		let rect = |x: f32| GlyphRect { pos: (x, 0.0), size: (7.0, 10.0), offset: (0.0, 0.0), advance: 7.0 };
+		assert!(sheet.add_sequence("e\u{301}", rect(250.0)).is_err());
+		sheet.add_sequence("e\u{301}", rect(20.0)).unwrap();
+		let font = Font::new(vec![sheet], 1.0, '\0');
+		assert_eq!(cluster_glyphs(&font, "e\u{301}").0, 7.0);
+	}
+
+	#[test]
+	fn missing_characters() {
+		let font = Font::new(vec![rect_sheet(&[('A', 6.0), ('?', 4.0)])], 1.0, '?');
+		// Characters that the font does not have are drawn as the tofu character
+		assert_width(&font, "AxA", 10.0, 6.0 + 4.0 + 6.0, 0.0);
+		let font = Font::new(vec![rect_sheet(&[('A', 6.0)])], 1.0, '\0');
+		assert_width(&font, "AxA", 10.0, 12.0, 0.0);
+	}
+
+	#[test]
+	fn line_counts() {
+		let font = Font::new(vec![rect_sheet(&[('A', 6.0)])], 1.0, '\0');
+		let count = |text: &str| font.measure_text(text, 0.0, 0.0, 10.0, &TextOptions::new(Some(15.0), Align::Left, VAlign::Top)).unwrap().get_line_count();
+		assert_eq!(font.calculate_num_lines(""), 0);
+		assert_eq!(count(""), 1);
+		assert_eq!(font.calculate_num_lines("A\nA\n"), 2);
+		assert_eq!(count("A\nA\n"), 3);
+		assert_eq!(font.calculate_num_lines("A\r\n\nA"), 3);
+		assert_eq!(count("A\r\n\nA"), 3);
+		// Only the layout wraps
+		assert_eq!(font.calculate_num_lines("AAAAA"), 1);
+		assert_eq!(count("AAAAA"), 3);
+	}
+
+	#[test]
+	fn tabs_on_wrapped_lines() {
+		let mut font = Font::new(vec![rect_sheet(&[('A', 6.0), (' ', 3.0)])], 1.0, '\0');
+		font.set_tab_stops(vec![TabStop::new(2.0, Align::Left)]);
+		let layout = font.measure_text("AAAA AA\tA", 0.0, 0.0, 10.0, &TextOptions::new(Some(30.0), Align::Left, VAlign::Top)).unwrap();
+		assert_eq!(layout.get_line_count(), 2);
+		// The stop is measured from the start of the second line, not of the paragraph
+		let last = layout.get_chars().last().unwrap();
+		assert_eq!((last.line, last.left), (1, 20.0));
+		// The tab is placed from the start of the line before the line is wrapped, so the
+		// second line does not overflow. From the start of the text line the tab would be
+		// 1 wide and "AA\tAA" would fit.
+		font.set_tab_width(Some(0.1));
+		let layout = font.measure_text("AAAA AA\tAA", 0.0, 0.0, 10.0, &TextOptions::new(Some(30.0), Align::Justify, VAlign::Top)).unwrap();
+		let widths: Vec<f32> = layout.get_lines().iter().map(|line| line.width).collect();
+		assert_eq!(widths, vec![24.0, 12.0, 12.0]);
+		font.set_tab_width(None);
+		// The same goes for rich text, where the second line ends at 26
+		let text = RichText::parse("AAAA AA\tA").unwrap();
+		let (width, _) = font.calculate_rich_text_size(&text, &MarkupContext::new(), Some(30.0), 10.0).unwrap();
+		assert_eq!(width, 26.0);
+		font.set_tab_width(Some(0.1));
+		let text = RichText::parse("AAAA AA\tAA").unwrap();
+		let (width, height) = font.calculate_rich_text_size(&text, &MarkupContext::new(), Some(30.0), 10.0).unwrap();
+		assert_eq!((width, height), (24.0, 30.0));
+	}
+
+	#[test]
+	fn rich_text_sizes() {
+		let font = Font::new(vec![rect_sheet(&[('A', 6.0), (' ', 3.0)])], 1.0, '\0');
+		let wide = Font::new(vec![rect_sheet(&[('A', 8.0)])], 1.0, '\0');
+		let icon = Texture::new(20, 10);
+		let mut context = MarkupContext::new();
+		context.add_font("wide", &wide);
+		context.add_icon("star", &icon, None);
+		context.add_icon("narrow", &icon, Some(((0.0, 0.0), (5.0, 10.0))));
+		let size = |markup: &str, max_w: Option<f32>, context: &MarkupContext| {
+			font.calculate_rich_text_size(&RichText::parse(markup).unwrap(), context, max_w, 10.0)
+		};
+		// Each line is as high as its highest part
+		assert_eq!(size("A[size=2]A[/size]", None, &context), Ok((18.0, 20.0)));
+		assert_eq!(size("[size=2]A[/size]\nA", None, &context), Ok((12.0, 30.0)));
+		assert_eq!(size("AA [size=2]AA[/size]", Some(30.0), &context), Ok((24.0, 30.0)));
+		assert_eq!(size("[font=wide]A[/font]A", None, &context), Ok((14.0, 10.0)));
+		// Icons are as high as the text and keep their aspect ratio
+		assert_eq!(size("A[icon=star][size=2][icon=narrow][/size]", None, &context), Ok((6.0 + 20.0 + 10.0, 20.0)));
+		assert!(size("[font=missing]A[/font]", None, &context).is_err());
+		assert!(size("[icon=missing]", None, &context).is_err());
+		// Fonts without a bold version are used as they are
+		assert_eq!(size("[b]A[/b]", None, &context), Ok((6.0, 10.0)));
+		context.set_bold("", &wide);
+		assert_eq!(size("[b]A[/b]A", None, &context), Ok((14.0, 10.0)));
+		// Like plain text, empty text is one empty line
+		assert_eq!(size("", None, &context), Ok((0.0, 10.0)));
 	}
 
-	fn get_pos(&self, c: char) -> (usize, usize) {
-		let char_index = c as usize - self.range.0 as usize;
-		(
-			char_index % self.chars_per_row,
-			char_index / self.chars_per_row,
-		)
+	#[test]
+	fn text_width_is_widest_line() {
+		let font = Font::new(vec![rect_sheet(&[('A', 6.0), ('V', 6.0)])], 1.0, '\0');
+		assert_eq!(font.calculate_text_width("A\nAVA\r\nV", 10.0).unwrap(), 18.0);
 	}
 }
@@ -0,0 +1,206 @@
+use std::ptr;
+
+use crate::{batch::BatchQuad, effects::EffectPass, sdf::DistanceField, Dvr, QuadBatch, Texture};
+
+use super::{Align, Font, Overflow, TextOptions, VAlign};
+
+/// Text that is laid out once and then drawn from vertex buffers with one draw call per
+/// sheet, which is much cheaper than calling draw_text() every frame for text that rarely
+/// changes (menus, labels).
+/// The text is laid out again only when the text, width, height, alignment or overflow is changed.
+/// Moving it or changing its colour does not lay it out again.
+///
+/// The glyphs refer to the sheets of the font that laid them out, so the block has to be
+/// drawn with the same font every time. Call invalidate() if the font is changed (e.g. when
+/// a fallback is added).
+pub struct TextBlock {
+	text: String,
+	max_w: Option<f32>,
+	h: f32,
+	text_align: Align,
+	vert_align: VAlign,
+	overflow: Overflow,
+	colour: (f32, f32, f32, f32),
+	/// None if the text has to be laid out again.
+	cache: Option<Cache>,
+}
+
+struct Cache {
+	/// The glyphs of each sheet that has any, in the order of Font::get_chain_sheets().
+	sheets: Vec<SheetGlyphs>,
+	size: (f32, f32),
+	truncated: bool,
+	/// A batch for each sheet, and the margin of the effects that its quads were grown for
+	/// (see EffectQuad). They are made when the text is first drawn.
+	batches: Option<(f32, Vec<QuadBatch>)>,
+}
+
+/// The glyphs of a layout that are from one sheet.
+struct SheetGlyphs {
+	texture: Texture,
+	distance_field: Option<DistanceField>,
+	glyphs: Vec<CachedGlyph>,
+}
+
+/// A glyph of a PositionedGlyph, where the position is relative to the position of the block.
+struct CachedGlyph {
+	pos: (f32, f32),
+	size: (f32, f32),
+	tex_rect: ((f32, f32), (f32, f32)),
+}
+
+impl TextBlock {
+	/// The arguments work like in Font::draw_text() and TextOptions.
+	pub fn new(text: &str, max_w: Option<f32>, h: f32, text_align: Align, vert_align: VAlign) -> TextBlock {
+		TextBlock {
+			text: text.to_string(),
+			max_w,
+			h,
+			text_align,
+			vert_align,
+			overflow: Overflow::default(),
+			colour: (1.0, 1.0, 1.0, 1.0),
+			cache: None,
+		}
+	}
+
+	pub fn get_text(&self) -> &str {
+		&self.text
+	}
+
+	pub fn set_text(&mut self, text: &str) {
+		if self.text != text {
+			self.text = text.to_string();
+			self.cache = None;
+		}
+	}
+
+	pub fn set_max_width(&mut self, max_w: Option<f32>) {
+		if self.max_w != max_w {
+			self.max_w = max_w;
+			self.cache = None;
+		}
+	}
+
+	pub fn set_height(&mut self, h: f32) {
+		if self.h != h {
+			self.h = h;
+			self.cache = None;
+		}
+	}
+
+	pub fn set_align(&mut self, text_align: Align, vert_align: VAlign) {
+		if self.text_align != text_align || self.vert_align != vert_align {
+			self.text_align = text_align;
+			self.vert_align = vert_align;
+			self.cache = None;
+		}
+	}
+
+	/// Limits how much of the text is shown, e.g. to fit it in a card of a fixed size.
+	pub fn set_overflow(&mut self, overflow: Overflow) {
+		if self.overflow != overflow {
+			self.overflow = overflow;
+			self.cache = None;
+		}
+	}
+
+	/// Sets the colour that the glyphs are multiplied by.
+	pub fn set_colour(&mut self, colour: (f32, f32, f32, f32)) {
+		self.colour = colour;
+	}
+
+	/// Makes the text be laid out again the next time it is drawn or measured.
+	pub fn invalidate(&mut self) {
+		self.cache = None;
+	}
+
+	/// Returns the width of the widest line and the height of the text.
+	pub fn get_size(&mut self, font: &Font) -> Result<(f32, f32), String> {
+		Ok(self.get_cache(font)?.size)
+	}
+
+	/// Returns true if the text does not fit in the limits of the overflow and is cut off.
+	pub fn is_truncated(&mut self, font: &Font) -> Result<bool, String> {
+		Ok(self.get_cache(font)?.truncated)
+	}
+
+	/// Draws the text like Font::draw_text() would at (x, y).
+	pub fn draw(&mut self, dvr: &Dvr, font: &Font, x: f32, y: f32) -> Result<(), String> {
+		let effects = font.effects.tinted(self.colour);
+		self.get_cache(font)?;
+		let cache = self.cache.as_mut().unwrap();
+		// Glyphs that were rasterized while laying out are uploaded the first time they are drawn
+		font.update_textures(dvr)?;
+		let margin = effects.margin();
+		if cache.batches.as_ref().is_none_or(|(batch_margin, _)| *batch_margin != margin) {
+			let batches = cache.sheets
+				.iter()
+				.map(|sheet| {
+					let quads: Vec<BatchQuad> = sheet.glyphs
+						.iter()
+						.map(|glyph| BatchQuad::new(glyph.pos, glyph.size, glyph.tex_rect, &effects))
+						.collect();
+					dvr.create_quad_batch(&sheet.texture, &quads)
+				})
+				.collect::<Result<Vec<QuadBatch>, String>>()?;
+			cache.batches = Some((margin, batches));
+		}
+		// The effects of all glyphs are drawn before the glyphs
+		let (_, batches) = cache.batches.as_ref().unwrap();
+		for pass in EffectPass::for_text(&effects) {
+			for (sheet, batch) in cache.sheets.iter().zip(batches) {
+				dvr.draw_quad_batch(batch, (x, y), sheet.distance_field, &effects, *pass)?;
+			}
+		}
+		Ok(())
+	}
+
+	fn get_cache(&mut self, font: &Font) -> Result<&Cache, String> {
+		if self.cache.is_none() {
+			self.cache = Some(self.layout(font)?);
+		}
+		Ok(self.cache.as_ref().unwrap())
+	}
+
+	fn layout(&self, font: &Font) -> Result<Cache, String> {
+		let options = TextOptions {
+			overflow: self.overflow.clone(),
+			..TextOptions::new(self.max_w, self.text_align, self.vert_align)
+		};
+		let layout = font.measure_text(&self.text, 0.0, 0.0, self.h, &options)?;
+		let chain = font.get_chain_sheets();
+		let mut sheets: Vec<Option<SheetGlyphs>> = chain.iter().map(|_| None).collect();
+		for glyph in layout.get_glyphs() {
+			let index = chain
+				.iter()
+				.position(|sheet| ptr::eq(&sheet.texture, glyph.texture))
+				.ok_or("Glyph is not from a sheet of the font")?;
+			let sheet = chain[index];
+			sheets[index]
+				.get_or_insert_with(|| SheetGlyphs {
+					texture: sheet.texture.share(),
+					distance_field: sheet.distance_field,
+					glyphs: Vec::new(),
+				})
+				.glyphs
+				.push(CachedGlyph {
+					pos: glyph.pos,
+					size: glyph.size,
+					tex_rect: glyph.tex_rect,
+				});
+		}
+		let lines = layout.get_lines();
+		let width = lines.iter().map(|line| line.width).fold(0.0, f32::max);
+		let height = match (lines.first(), lines.last()) {
+			(Some(first), Some(last)) => first.top - (last.top - last.height),
+			_ => 0.0,
+		};
+		Ok(Cache {
+			sheets: sheets.into_iter().flatten().collect(),
+			size: (width, height),
+			truncated: layout.is_truncated(),
+			batches: None,
+		})
+	}
+}
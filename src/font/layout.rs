@@ -0,0 +1,512 @@
+use std::ops::Range;
+
+use crate::{effects::TextEffects, sdf::DistanceField, Texture};
+#[cfg(any(target_arch = "wasm32", target_os = "windows"))]
+use crate::{effects::EffectPass, Dvr};
+use super::{wrap::{justify_space, line_width, wrap, Break, Placed}, Align, CharPlacement, Font, FontSheet, Glyph, VAlign};
+
+/// Text that has been laid out by Font::layout_text(). Everything is in the same coordinates
+/// as in draw_text() (where y goes up), and indices are byte indices into the text.
+pub struct TextLayout<'a> {
+	glyphs: Vec<PositionedGlyph<'a>>,
+	lines: Vec<LineBox>,
+	chars: Vec<CharBox>,
+	truncated_at: Option<usize>,
+}
+
+/// Limits how much text is laid out, e.g. to fit it in a box of a fixed size. Text that
+/// does not fit is cut off after the last line that fits, which ends with the ellipsis.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Overflow {
+	pub max_lines: Option<usize>,
+	/// The maximum height from the top of the first line to the bottom of the last line.
+	/// The first line is always laid out, even if it is higher than this.
+	pub max_height: Option<f32>,
+	/// Drawn at the end of the last line when text is cut off, which is made shorter if
+	/// there is not enough room for it. It can be empty.
+	pub ellipsis: String,
+}
+
+impl Default for Overflow {
+	/// No limits, with "..." as the ellipsis.
+	fn default() -> Self {
+		Overflow {
+			max_lines: None,
+			max_height: None,
+			ellipsis: "...".to_string(),
+		}
+	}
+}
+
+impl Overflow {
+	/// The number of lines that fit when the lines are h high with leading, or None for any number.
+	fn max_line_count(&self, h: f32, leading: f32) -> Option<usize> {
+		let by_height = match self.max_height {
+			// The small tolerance keeps heights that were calculated from a number of lines from losing one
+			Some(max_height) if h > 0.0 && leading > 0.0 => Some(((max_height - h) / (h * leading) + 1e-4).max(0.0) as usize + 1),
+			_ => None,
+		};
+		match (self.max_lines, by_height) {
+			(Some(lines), Some(by_height)) => Some(lines.min(by_height).max(1)),
+			(lines, by_height) => lines.or(by_height).map(|lines| lines.max(1)),
+		}
+	}
+}
+
+/// How Font::draw_text(), layout_text() and measure_text() lay out and draw text.
+#[derive(Clone, PartialEq, Debug)]
+pub struct TextOptions {
+	/// The width that lines are wrapped at, or None to only break lines at line breaks.
+	pub max_w: Option<f32>,
+	pub text_align: Align,
+	pub vert_align: VAlign,
+	pub overflow: Overflow,
+	/// The effects that the text is drawn with instead of the ones of the font (see
+	/// Font::set_effects()). They do not change the layout.
+	pub effects: Option<TextEffects>,
+}
+
+impl TextOptions {
+	/// Options without an overflow, for the effects of the font.
+	pub fn new(max_w: Option<f32>, text_align: Align, vert_align: VAlign) -> TextOptions {
+		TextOptions {
+			max_w,
+			text_align,
+			vert_align,
+			overflow: Overflow::default(),
+			effects: None,
+		}
+	}
+}
+
+impl Default for TextOptions {
+	/// Text aligned at the left and the top, without wrapping.
+	fn default() -> Self {
+		TextOptions::new(None, Align::Left, VAlign::Top)
+	}
+}
+
+/// A glyph and where it is drawn. This has what Dvr::draw() needs to draw it.
+#[derive(Clone, Copy)]
+pub struct PositionedGlyph<'a> {
+	pub texture: &'a Texture,
+	/// The centre of the glyph.
+	pub pos: (f32, f32),
+	pub size: (f32, f32),
+	/// The part of the texture with the glyph (position and size, in pixels).
+	pub tex_rect: ((f32, f32), (f32, f32)),
+	/// The index of the character that the glyph is drawn for. Hyphens that are added
+	/// when a line is broken have the index of the first character on the next line.
+	pub index: usize,
+	pub line: usize,
+	/// Set if the texture is a distance field.
+	pub distance_field: Option<DistanceField>,
+}
+
+/// A line after wrapping.
+#[derive(Clone, Debug)]
+pub struct LineBox {
+	pub left: f32,
+	pub top: f32,
+	/// The width without the whitespace at the end.
+	pub width: f32,
+	pub height: f32,
+	/// The characters on the line, without the line break.
+	pub range: Range<usize>,
+}
+
+/// The space that a grapheme cluster takes up on its line, which goes from the cursor
+/// before it to the cursor after it and is as high as the line.
+#[derive(Clone, Copy, Debug)]
+pub struct CharBox {
+	pub index: usize,
+	/// The length of the cluster in bytes.
+	pub len: usize,
+	/// The first character of the cluster.
+	pub c: char,
+	pub left: f32,
+	pub top: f32,
+	pub width: f32,
+	pub height: f32,
+	pub line: usize,
+}
+
+impl PositionedGlyph<'_> {
+	/// Draws the glyph with effects (see Font::get_effects()), where the colour of the
+	/// effects is multiplied by colour.
+	#[cfg(any(target_arch = "wasm32", target_os = "windows"))]
+	pub fn draw(&self, dvr: &Dvr, colour: (f32, f32, f32, f32), effects: &TextEffects) -> Result<(), String> {
+		self.draw_pass(dvr, colour, effects, EffectPass::All)
+	}
+
+	/// Like draw() but only the parts of the effects of the pass.
+	#[cfg(any(target_arch = "wasm32", target_os = "windows"))]
+	pub(crate) fn draw_pass(&self, dvr: &Dvr, colour: (f32, f32, f32, f32), effects: &TextEffects, pass: EffectPass) -> Result<(), String> {
+		dvr.draw_effect_pass(self.texture, (self.pos, self.size), self.tex_rect, self.distance_field, &effects.tinted(colour), pass)
+	}
+}
+
+impl<'a> TextLayout<'a> {
+	/// Draws the glyphs like PositionedGlyph::draw(), but the effects of all glyphs are
+	/// drawn before the glyphs.
+	#[cfg(any(target_arch = "wasm32", target_os = "windows"))]
+	pub(crate) fn draw(&self, dvr: &Dvr, colour: (f32, f32, f32, f32), effects: &TextEffects) -> Result<(), String> {
+		for pass in EffectPass::for_text(effects) {
+			for glyph in &self.glyphs {
+				glyph.draw_pass(dvr, colour, effects, *pass)?;
+			}
+		}
+		Ok(())
+	}
+
+	pub fn get_glyphs(&self) -> &[PositionedGlyph<'a>] {
+		&self.glyphs
+	}
+
+	/// There is always at least one line.
+	pub fn get_lines(&self) -> &[LineBox] {
+		&self.lines
+	}
+
+	/// The number of lines after wrapping, which is at least one. A line break at the end of
+	/// the text starts an empty line.
+	pub fn get_line_count(&self) -> usize {
+		self.lines.len()
+	}
+
+	/// The grapheme clusters in the order they are in the text. Line breaks and soft hyphens
+	/// do not have boxes.
+	pub fn get_chars(&self) -> &[CharBox] {
+		&self.chars
+	}
+
+	/// Returns where a caret before the character at index is drawn, as the top of the caret
+	/// and its height. An index at the end of a line that has been wrapped is at the start
+	/// of the next line.
+	pub fn caret_position(&self, index: usize) -> ((f32, f32), f32) {
+		let line_index = self.lines
+			.iter()
+			.rposition(|line| line.range.start <= index)
+			.unwrap_or(0);
+		let line = &self.lines[line_index];
+		let chars = self.get_line_chars(line_index);
+		let x = match chars.iter().find(|c| c.index >= index) {
+			Some(c) => c.left,
+			None => chars.last().map(|c| c.left + c.width).unwrap_or(line.left),
+		};
+		((x, line.top), line.height)
+	}
+
+	/// Returns the index where a caret should be placed when the text is clicked at (x, y).
+	/// Points outside the text go to the closest line.
+	pub fn hit_test(&self, x: f32, y: f32) -> usize {
+		let line_index = self.lines
+			.iter()
+			.position(|line| y > line.top - line.height)
+			.unwrap_or(self.lines.len() - 1);
+		let line = &self.lines[line_index];
+		let chars = self.get_line_chars(line_index);
+		if let Some(c) = chars.iter().find(|c| x < c.left + 0.5 * c.width) {
+			return c.index;
+		}
+		let wrapped = self.lines
+			.get(line_index + 1)
+			.is_some_and(|next| next.range.start == line.range.end);
+		match (chars.last(), wrapped) {
+			// The end of a wrapped line is the start of the next line, so the caret is
+			// placed before the last character to keep it on this line
+			(Some(last), true) => last.index,
+			_ => line.range.end,
+		}
+	}
+
+	/// Returns the rectangles (top left corner and size) that cover the characters in range,
+	/// with one rectangle per line. This can be used to highlight a selection.
+	pub fn selection_rects(&self, range: Range<usize>) -> Vec<((f32, f32), (f32, f32))> {
+		let mut rects = Vec::new();
+		for (i, line) in self.lines.iter().enumerate() {
+			let mut selected = self.get_line_chars(i)
+				.iter()
+				.filter(|c| range.contains(&c.index));
+			let Some(first) = selected.next() else {
+				continue;
+			};
+			let last = selected.next_back().unwrap_or(first);
+			rects.push(((first.left, line.top), (last.left + last.width - first.left, line.height)));
+		}
+		rects
+	}
+
+	/// Returns true if the text was cut off because it did not fit (see Overflow).
+	pub fn is_truncated(&self) -> bool {
+		self.truncated_at.is_some()
+	}
+
+	/// Returns the index where the text was cut off, or None if all of it was laid out.
+	pub fn get_truncated_at(&self) -> Option<usize> {
+		self.truncated_at
+	}
+
+	fn get_line_chars(&self, line: usize) -> &[CharBox] {
+		let start = self.chars.partition_point(|c| c.line < line);
+		let end = self.chars.partition_point(|c| c.line <= line);
+		&self.chars[start..end]
+	}
+}
+
+/// A line after wrapping, before it has been positioned.
+struct WrappedLine<'a> {
+	range: Range<usize>,
+	/// The characters on the line, where the bytes are relative to the start of the text line.
+	placed: Vec<Placed>,
+	placements: Vec<CharPlacement<'a>>,
+	/// Where the text line that the line is part of starts.
+	line_start: usize,
+	hyphen: bool,
+	/// Set on the last line when the text is cut off.
+	ellipsis: bool,
+	/// How much wider the spaces are with Align::Justify.
+	justify_space: f32,
+}
+
+impl WrappedLine<'_> {
+	/// Removes characters from the end of the line until it is at most max_w wide and does
+	/// not end with whitespace, so that the ellipsis can be drawn after it.
+	fn truncate(&mut self, max_w: Option<f32>) {
+		self.hyphen = false;
+		self.ellipsis = true;
+		self.justify_space = 0.0;
+		while let Some(last) = self.placed.last() {
+			let too_wide = max_w.is_some_and(|max_w| line_width(&self.placed) > max_w);
+			if !too_wide && !last.c.is_whitespace() {
+				break;
+			}
+			self.range.end = self.line_start + last.byte;
+			self.placed.pop();
+			self.placements.pop();
+		}
+	}
+}
+
+impl Font {
+	/// Lays out text the same way draw_text() draws it and returns where everything is.
+	/// The textures of sheets that rasterize glyphs are updated so that the glyphs can be
+	/// drawn right away. A line break at the end of the text starts an empty line.
+	#[cfg(any(target_arch = "wasm32", target_os = "windows"))]
+	pub fn layout_text(&self, dvr: &Dvr, text: &str, x: f32, y: f32, h: f32, options: &TextOptions) -> Result<TextLayout<'_>, String> {
+		let layout = self.measure_text(text, x, y, h, options)?;
+		self.update_textures(dvr)?;
+		Ok(layout)
+	}
+
+	/// Lays out a line for wrapping, where the bytes are offset by start.
+	fn place_line(&self, line: &str, start: usize, h: f32) -> Result<(Vec<Placed>, Vec<CharPlacement<'_>>), String> {
+		let mut placed: Vec<Placed> = Vec::new();
+		let mut placements: Vec<CharPlacement> = Vec::new();
+		self.layout_line(line, h, |placement| {
+			placed.push(Placed {
+				byte: start + placement.byte,
+				c: placement.c,
+				x: placement.x,
+				advance: placement.advance,
+			});
+			placements.push(CharPlacement {
+				byte: start + placement.byte,
+				..placement.clone()
+			});
+			Ok(())
+		})?;
+		Ok((placed, placements))
+	}
+
+	/// Like layout_text() but the textures are not updated, so the glyphs may not be in them
+	/// yet. This is enough for measuring, caret placement and hit-testing.
+	/// TextLayout::is_truncated() tells if the text was cut off (see Overflow).
+	pub fn measure_text(&self, text: &str, x: f32, y: f32, h: f32, options: &TextOptions) -> Result<TextLayout<'_>, String> {
+		let TextOptions { max_w, text_align, vert_align, .. } = *options;
+		let overflow = &options.overflow;
+		// Lines can only be hyphenated if the font can draw the hyphen
+		let hyphen_width = match self.has_char('-') {
+			true => Some(self.calculate_line_width("-", h)?),
+			false => None,
+		};
+		let mut lines: Vec<WrappedLine> = Vec::new();
+		let mut line_start = 0;
+		for line in text.split('\n') {
+			let start = line_start;
+			line_start += line.len() + 1;
+			let line = line.strip_suffix('\r').unwrap_or(line);
+
+			// Tab stops are measured from the start of the line that the tab is on, so the rest
+			// of the text line is placed again after each line that comes before a tab
+			let mut byte = 0;
+			loop {
+				let rest_start = byte;
+				let rest = &line[rest_start..];
+				let (mut placed, mut placements) = self.place_line(rest, rest_start, h)?;
+				let mut ends = match max_w {
+					Some(max_w) if !placed.is_empty() => {
+						let breaks = self.find_breaks(rest, &placed, rest_start);
+						wrap(&placed, &breaks, max_w, |_| hyphen_width)
+					},
+					_ => vec![Break { index: placed.len(), hyphen: false }],
+				};
+				let line_count = ends.len();
+				// The first line is placed from where it starts, and so are the lines after it
+				// until one has a tab
+				let misplaced = (1..ends.len()).find(|&i| placed[ends[i - 1].index..ends[i].index].iter().any(|p| p.c == '\t'));
+				if let Some(misplaced) = misplaced {
+					ends.truncate(misplaced);
+				}
+				let mut index = 0;
+				for (i, end) in ends.into_iter().enumerate() {
+					let count = end.index - index;
+					index = end.index;
+					let line_placed: Vec<Placed> = placed.drain(..count).collect();
+					let line_placements: Vec<CharPlacement> = placements.drain(..count).collect();
+					let line_end = placed.first().map(|next| next.byte).unwrap_or(line.len());
+					// Only lines that are wrapped are justified, not the last line of a paragraph
+					let justify_space = match max_w {
+						Some(max_w) if i + 1 < line_count => {
+							let hyphen = if end.hyphen { hyphen_width.unwrap_or(0.0) } else { 0.0 };
+							justify_space(&line_placed, max_w - line_width(&line_placed) - hyphen)
+						},
+						_ => 0.0,
+					};
+					lines.push(WrappedLine {
+						range: start + byte..start + line_end,
+						placed: line_placed,
+						placements: line_placements,
+						line_start: start,
+						hyphen: end.hyphen,
+						ellipsis: false,
+						justify_space,
+					});
+					byte = line_end;
+				}
+				if misplaced.is_none() {
+					break;
+				}
+			}
+		}
+
+		let mut ellipsis: Vec<CharPlacement> = Vec::new();
+		let max_lines = overflow.max_line_count(h, self.leading);
+		let truncated_at = match max_lines {
+			Some(max_lines) if lines.len() > max_lines => {
+				lines.truncate(max_lines);
+				let ellipsis_width = self.layout_line(&overflow.ellipsis, h, |placement| {
+					ellipsis.push(placement.clone());
+					Ok(())
+				})?;
+				let last = lines.last_mut().unwrap();
+				last.truncate(max_w.map(|max_w| max_w - ellipsis_width));
+				Some(last.range.end)
+			},
+			_ => None,
+		};
+
+		let text_height = h + h * self.leading * (lines.len() - 1) as f32;
+		let mut line_top: f32 = match vert_align {
+			VAlign::Top => y,
+			VAlign::Centre => y + 0.5 * text_height,
+			VAlign::Bottom => y + text_height,
+		};
+		let mut layout = TextLayout {
+			glyphs: Vec::new(),
+			lines: Vec::new(),
+			chars: Vec::new(),
+			truncated_at,
+		};
+		for (i, line) in lines.into_iter().enumerate() {
+			let start_x = line.placed.first().map(|p| p.x).unwrap_or(0.0);
+			let text_width = line_width(&line.placed);
+			let hyphen = match line.hyphen {
+				true => {
+					let (_, sheet, glyph) = self.get_char_glyph('-')?;
+					Some((sheet, glyph))
+				},
+				false => None,
+			};
+			let space_stretch = match text_align {
+				Align::Justify => line.justify_space,
+				_ => 0.0,
+			};
+			let stretched = |p: &Placed| p.c == ' ' && p.x - start_x < text_width;
+			let text_width = text_width + space_stretch * line.placed.iter().filter(|p| stretched(p)).count() as f32;
+			let ellipsis_width = match line.ellipsis {
+				true => ellipsis.last().map(|e| e.x + e.advance).unwrap_or(0.0),
+				false => 0.0,
+			};
+			let width = match hyphen {
+				Some((sheet, glyph)) => text_width + glyph.advance * sheet.get_scale(h),
+				None => text_width + ellipsis_width,
+			};
+			let left = x + match text_align {
+				Align::Left | Align::Justify => 0.0,
+				Align::Centre => -0.5 * width,
+				Align::Right => -width,
+			};
+			let mut shift = 0.0;
+			for (p, placement) in line.placed.iter().zip(&line.placements) {
+				let char_left = left + p.x - start_x + shift;
+				let char_width = match stretched(p) {
+					true => p.advance + space_stretch,
+					false => p.advance,
+				};
+				shift += char_width - p.advance;
+				layout.chars.push(CharBox {
+					index: line.line_start + p.byte,
+					len: placement.len,
+					c: p.c,
+					left: char_left,
+					top: line_top,
+					width: char_width,
+					height: h,
+					line: i,
+				});
+				for (sheet, glyph, glyph_pos) in placement.glyphs((char_left, line_top)) {
+					layout.push_glyph(sheet, glyph, glyph_pos, h, line.line_start + p.byte, i);
+				}
+			}
+			if let Some((sheet, glyph)) = hyphen {
+				let hyphen_top = line_top - self.get_baseline_shift(sheet, h);
+				layout.push_glyph(sheet, glyph, (left + text_width, hyphen_top), h, line.range.end, i);
+			}
+			if line.ellipsis {
+				// The ellipsis has no character boxes, like the hyphen
+				for placement in &ellipsis {
+					for (sheet, glyph, glyph_pos) in placement.glyphs((left + text_width + placement.x, line_top)) {
+						layout.push_glyph(sheet, glyph, glyph_pos, h, line.range.end, i);
+					}
+				}
+			}
+			layout.lines.push(LineBox {
+				left,
+				top: line_top,
+				width,
+				height: h,
+				range: line.range,
+			});
+			line_top -= h * self.leading;
+		}
+
+		Ok(layout)
+	}
+}
+
+impl<'a> TextLayout<'a> {
+	fn push_glyph(&mut self, sheet: &'a FontSheet, glyph: Glyph, (x, line_top): (f32, f32), h: f32, index: usize, line: usize) {
+		if let Some((pos, size)) = sheet.glyph_quad(glyph, x, line_top, h) {
+			self.glyphs.push(PositionedGlyph {
+				texture: &sheet.texture,
+				pos,
+				size,
+				tex_rect: (glyph.pos, glyph.size),
+				index,
+				line,
+				distance_field: sheet.distance_field,
+			});
+		}
+	}
+}
@@ -0,0 +1,347 @@
+/// Text where parts can have different styles, which is drawn with Font::draw_rich_text().
+/// It is usually parsed from markup with tags in square brackets:
+///
+/// - `[colour=#rrggbb]...[/colour]` or `[colour=#rrggbbaa]...[/colour]` sets the colour.
+///   `color` works as well.
+/// - `[size=1.5]...[/size]` multiplies the height of the text.
+/// - `[font=name]...[/font]` switches to a font in the MarkupContext.
+/// - `[b]...[/b]` switches to the bold version of the font (see MarkupContext::set_bold()).
+/// - `[icon=name]` draws an icon from the MarkupContext, which is as high as the text.
+/// - `[[` is a `[` that does not start a tag.
+///
+/// Tags can be nested, and a closing tag ends the innermost open tag of its kind. Sizes
+/// are multiplied when size tags are nested.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct RichText {
+	spans: Vec<Span>,
+}
+
+/// A part of a RichText where everything has the same style.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Span {
+	/// Text, which may contain line breaks.
+	Text { text: String, style: TextStyle },
+	/// An icon from the MarkupContext, which is placed like a character.
+	Icon { name: String, style: TextStyle },
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct TextStyle {
+	/// Red, green, blue and alpha, from 0 to 1. The glyphs are multiplied by this.
+	pub colour: (f32, f32, f32, f32),
+	/// The height of the text relative to the h that is passed to draw_rich_text().
+	pub size: f32,
+	/// The name of the font in the MarkupContext, or None for the font that draws the text.
+	pub font: Option<String>,
+	pub bold: bool,
+}
+
+impl Default for TextStyle {
+	fn default() -> Self {
+		TextStyle {
+			colour: (1.0, 1.0, 1.0, 1.0),
+			size: 1.0,
+			font: None,
+			bold: false,
+		}
+	}
+}
+
+impl RichText {
+	pub fn from_spans(spans: Vec<Span>) -> RichText {
+		RichText { spans }
+	}
+
+	/// Parses markup (see RichText for the tags).
+	pub fn parse(markup: &str) -> Result<RichText, String> {
+		let mut parser = Parser::default();
+		let mut chars = markup.chars().peekable();
+		while let Some(c) = chars.next() {
+			if c != '[' {
+				parser.text.push(c);
+				continue;
+			}
+			if chars.next_if_eq(&'[').is_some() {
+				parser.text.push('[');
+				continue;
+			}
+			let mut tag = String::new();
+			loop {
+				match chars.next() {
+					Some(']') => break,
+					Some(c) => tag.push(c),
+					None => return Err(format!("Unterminated tag \"[{}\"", tag)),
+				}
+			}
+			parser.tag(tag.trim())?;
+		}
+		if let Some(kind) = parser.unclosed() {
+			return Err(format!("Tag \"{}\" is never closed", kind.name()));
+		}
+		parser.flush();
+		Ok(RichText { spans: parser.spans })
+	}
+
+	pub fn get_spans(&self) -> &[Span] {
+		&self.spans
+	}
+
+	/// The text without styles. Icons become U+FFFC (the object replacement character).
+	pub fn to_plain_text(&self) -> String {
+		self.spans
+			.iter()
+			.map(|span| match span {
+				Span::Text { text, .. } => text.as_str(),
+				Span::Icon { .. } => "\u{fffc}",
+			})
+			.collect()
+	}
+}
+
+impl From<&str> for RichText {
+	/// Text without markup, in the default style.
+	fn from(text: &str) -> Self {
+		RichText {
+			spans: vec![Span::Text {
+				text: text.to_string(),
+				style: TextStyle::default(),
+			}],
+		}
+	}
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum TagKind {
+	Colour,
+	Size,
+	Font,
+	Bold,
+}
+
+impl TagKind {
+	fn parse(name: &str) -> Result<TagKind, String> {
+		match name {
+			"colour" | "color" => Ok(TagKind::Colour),
+			"size" => Ok(TagKind::Size),
+			"font" => Ok(TagKind::Font),
+			"b" => Ok(TagKind::Bold),
+			_ => Err(format!("Unknown tag \"{}\"", name)),
+		}
+	}
+
+	fn name(&self) -> &'static str {
+		match self {
+			TagKind::Colour => "colour",
+			TagKind::Size => "size",
+			TagKind::Font => "font",
+			TagKind::Bold => "b",
+		}
+	}
+}
+
+/// Each kind of tag has a stack of the values of the open tags, so that tags of
+/// different kinds do not have to be closed in order.
+#[derive(Default)]
+struct Parser {
+	spans: Vec<Span>,
+	/// The text since the last tag.
+	text: String,
+	colours: Vec<(f32, f32, f32, f32)>,
+	sizes: Vec<f32>,
+	fonts: Vec<String>,
+	bold: usize,
+}
+
+impl Parser {
+	fn style(&self) -> TextStyle {
+		TextStyle {
+			colour: self.colours.last().copied().unwrap_or((1.0, 1.0, 1.0, 1.0)),
+			size: self.sizes.iter().product(),
+			font: self.fonts.last().cloned(),
+			bold: self.bold > 0,
+		}
+	}
+
+	fn tag(&mut self, tag: &str) -> Result<(), String> {
+		self.flush();
+		if let Some(name) = tag.strip_prefix('/') {
+			let closed = match TagKind::parse(name.trim())? {
+				TagKind::Colour => self.colours.pop().is_some(),
+				TagKind::Size => self.sizes.pop().is_some(),
+				TagKind::Font => self.fonts.pop().is_some(),
+				TagKind::Bold => self.bold.checked_sub(1).map(|bold| self.bold = bold).is_some(),
+			};
+			return match closed {
+				true => Ok(()),
+				false => Err(format!("Closing tag \"[{}]\" has no opening tag", tag)),
+			};
+		}
+		let (name, value) = match tag.split_once('=') {
+			Some((name, value)) => (name.trim(), Some(value.trim())),
+			None => (tag, None),
+		};
+		if name == "icon" {
+			let name = value.ok_or("The icon tag needs the name of an icon")?;
+			self.spans.push(Span::Icon {
+				name: name.to_string(),
+				style: self.style(),
+			});
+			return Ok(());
+		}
+		let kind = TagKind::parse(name)?;
+		let value = match (value, kind) {
+			(None, TagKind::Bold) => "",
+			(Some(_), TagKind::Bold) => return Err(format!("The {} tag does not take a value", name)),
+			(Some(value), _) => value,
+			(None, _) => return Err(format!("The {} tag needs a value", name)),
+		};
+		match kind {
+			TagKind::Colour => self.colours.push(parse_colour(value)?),
+			TagKind::Size => {
+				let size: f32 = value
+					.parse()
+					.ok()
+					.filter(|size: &f32| *size > 0.0 && size.is_finite())
+					.ok_or(format!("Invalid size \"{}\"", value))?;
+				self.sizes.push(size);
+			},
+			TagKind::Font => self.fonts.push(value.to_string()),
+			TagKind::Bold => self.bold += 1,
+		}
+		Ok(())
+	}
+
+	/// The first tag that is still open.
+	fn unclosed(&self) -> Option<TagKind> {
+		[
+			(!self.colours.is_empty(), TagKind::Colour),
+			(!self.sizes.is_empty(), TagKind::Size),
+			(!self.fonts.is_empty(), TagKind::Font),
+			(self.bold > 0, TagKind::Bold),
+		]
+			.into_iter()
+			.find_map(|(open, kind)| open.then_some(kind))
+	}
+
+	/// Turns the text since the last tag into a span.
+	fn flush(&mut self) {
+		if self.text.is_empty() {
+			return;
+		}
+		let text = std::mem::take(&mut self.text);
+		let style = self.style();
+		// Text in the same style as the previous span is added to it
+		if let Some(Span::Text { text: last, style: last_style }) = self.spans.last_mut() {
+			if *last_style == style {
+				last.push_str(&text);
+				return;
+			}
+		}
+		self.spans.push(Span::Text { text, style });
+	}
+}
+
+/// Parses #rrggbb or #rrggbbaa.
+fn parse_colour(value: &str) -> Result<(f32, f32, f32, f32), String> {
+	let invalid = || format!("Invalid colour \"{}\" (expected #rrggbb or #rrggbbaa)", value);
+	let hex = value.strip_prefix('#').ok_or_else(invalid)?;
+	if !(hex.len() == 6 || hex.len() == 8) || !hex.is_ascii() {
+		return Err(invalid());
+	}
+	let mut channels = [255u8; 4];
+	for (i, channel) in channels.iter_mut().take(hex.len() / 2).enumerate() {
+		*channel = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| invalid())?;
+	}
+	let [r, g, b, a] = channels.map(|channel| channel as f32 / 255.0);
+	Ok((r, g, b, a))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn text(text: &str, style: TextStyle) -> Span {
+		Span::Text { text: text.to_string(), style }
+	}
+
+	fn coloured(colour: (f32, f32, f32, f32)) -> TextStyle {
+		TextStyle { colour, ..TextStyle::default() }
+	}
+
+	#[test]
+	fn spans() {
+		let parsed = RichText::parse("a[colour=#ff000080]b[size=2]c[/size][/colour]d").unwrap();
+		let red = (1.0, 0.0, 0.0, 128.0 / 255.0);
+		assert_eq!(parsed.get_spans(), [
+			text("a", TextStyle::default()),
+			text("b", coloured(red)),
+			text("c", TextStyle { size: 2.0, ..coloured(red) }),
+			text("d", TextStyle::default()),
+		]);
+		assert_eq!(RichText::parse("plain\ntext").unwrap(), RichText::from("plain\ntext"));
+		assert!(RichText::parse("").unwrap().get_spans().is_empty());
+	}
+
+	#[test]
+	fn nesting() {
+		let parsed = RichText::parse("[size=2][size=1.5]a[/size]b[/size]").unwrap();
+		assert_eq!(parsed.get_spans(), [
+			text("a", TextStyle { size: 3.0, ..TextStyle::default() }),
+			text("b", TextStyle { size: 2.0, ..TextStyle::default() }),
+		]);
+		let parsed = RichText::parse("[font=x][font=y]a[/font]b[/font]").unwrap();
+		let font = |name: &str| TextStyle { font: Some(name.to_string()), ..TextStyle::default() };
+		assert_eq!(parsed.get_spans(), [text("a", font("y")), text("b", font("x"))]);
+		// Both bold parts are in one span, as their style is the same
+		let parsed = RichText::parse("[b][b]a[/b]b[/b]c").unwrap();
+		let bold = TextStyle { bold: true, ..TextStyle::default() };
+		assert_eq!(parsed.get_spans(), [text("ab", bold), text("c", TextStyle::default())]);
+		// Tags of different kinds can be closed in any order
+		let parsed = RichText::parse("[b][color=#00ff00]a[/b]b[/color]").unwrap();
+		let green = coloured((0.0, 1.0, 0.0, 1.0));
+		assert_eq!(parsed.get_spans(), [text("a", TextStyle { bold: true, ..green.clone() }), text("b", green)]);
+	}
+
+	#[test]
+	fn escaped_brackets() {
+		let parsed = RichText::parse("[[b]] [[[b]x[/b]").unwrap();
+		assert_eq!(parsed.get_spans(), [
+			text("[b]] [", TextStyle::default()),
+			text("x", TextStyle { bold: true, ..TextStyle::default() }),
+		]);
+		assert_eq!(RichText::parse("[[").unwrap().to_plain_text(), "[");
+	}
+
+	#[test]
+	fn icons() {
+		let parsed = RichText::parse("[colour=#0000ff]a[icon = star]b[/colour]").unwrap();
+		let blue = coloured((0.0, 0.0, 1.0, 1.0));
+		assert_eq!(parsed.get_spans(), [
+			text("a", blue.clone()),
+			Span::Icon { name: "star".to_string(), style: blue.clone() },
+			text("b", blue),
+		]);
+		assert_eq!(parsed.to_plain_text(), "a\u{fffc}b");
+	}
+
+	#[test]
+	fn unclosed_and_mismatched_tags() {
+		for markup in ["[b]a", "[colour=#ffffff]a[b]b[/b]", "[b]a[/colour][/b]", "a[/size]", "[b]a[/b][/b]", "a[b", "[unknown]a[/unknown]"] {
+			assert!(RichText::parse(markup).is_err(), "{}", markup);
+		}
+	}
+
+	#[test]
+	fn bad_values() {
+		let colours = ["red", "#fff", "#ff00ff0", "#gg0000", "#ffé000", "#ff0000ff00", "ff0000"];
+		for colour in colours {
+			assert!(RichText::parse(&format!("[colour={}]a[/colour]", colour)).is_err(), "{}", colour);
+		}
+		for size in ["0", "-1", "abc", "inf", "NaN", ""] {
+			assert!(RichText::parse(&format!("[size={}]a[/size]", size)).is_err(), "{}", size);
+		}
+		for markup in ["[size]a[/size]", "[font]a[/font]", "[b=1]a[/b]", "[icon]"] {
+			assert!(RichText::parse(markup).is_err(), "{}", markup);
+		}
+	}
+}
@@ -0,0 +1,112 @@
+use super::{Font, FontSheet, Glyph};
+
+/// The vertical metrics of a font when its lines are h high, in the same units as h.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct FontMetrics {
+	/// The distance from the top of a line to the baseline.
+	pub ascent: f32,
+	/// The distance from the baseline to the bottom of a line. This is positive.
+	pub descent: f32,
+	/// The height of a line, which is h.
+	pub line_height: f32,
+	/// The distance from the top of a line to the top of the next line (h times the leading).
+	pub line_advance: f32,
+}
+
+/// Where a glyph is drawn when its lines are h high, in the same units as h.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct GlyphMetrics {
+	/// How far the cursor moves after the glyph, without kerning.
+	pub advance: f32,
+	/// Where the top left corner of the glyph is relative to the cursor and the baseline,
+	/// where y goes up like in Dvr coordinates.
+	pub bearing: (f32, f32),
+	/// The size of the glyph, which is (0, 0) for glyphs that are not drawn (e.g. spaces).
+	pub size: (f32, f32),
+}
+
+impl GlyphMetrics {
+	fn new(sheet: &FontSheet, glyph: Glyph, h: f32) -> GlyphMetrics {
+		let scale = sheet.get_scale(h);
+		GlyphMetrics {
+			advance: glyph.advance * scale,
+			bearing: (glyph.offset.0 * scale, (sheet.baseline - glyph.offset.1) * scale),
+			size: (glyph.size.0 * scale, glyph.size.1 * scale),
+		}
+	}
+}
+
+impl Font {
+	/// Returns the metrics of the text when the lines are h high. The baseline is the baseline
+	/// of the first sheet, which the glyphs of all sheets and fallbacks are aligned to.
+	pub fn get_metrics(&self, h: f32) -> FontMetrics {
+		let ascent = match self.get_first_sheet() {
+			Some(sheet) => sheet.baseline * sheet.get_scale(h),
+			None => h,
+		};
+		FontMetrics {
+			ascent,
+			descent: h - ascent,
+			line_height: h,
+			line_advance: h * self.leading,
+		}
+	}
+
+	/// Returns where the glyph for c is drawn when the lines are h high, with the glyph from
+	/// the fallback chain or the tofu glyph if the font does not have c. Monospace fonts give
+	/// the advance of their cells (see set_monospace()).
+	pub fn get_glyph_metrics(&self, c: char, h: f32) -> Result<GlyphMetrics, String> {
+		let (_, sheet, glyph) = self.get_char_glyph(c)?;
+		let metrics = GlyphMetrics::new(sheet, glyph, h);
+		Ok(match self.monospace {
+			Some(cell) => GlyphMetrics {
+				advance: cell * h,
+				bearing: (metrics.bearing.0 + 0.5 * (cell * h - metrics.advance), metrics.bearing.1),
+				..metrics
+			},
+			None => metrics,
+		})
+	}
+
+	/// The distance between the tops of two lines as a fraction of the line height.
+	pub fn get_leading(&self) -> f32 {
+		self.leading
+	}
+
+	pub fn set_leading(&mut self, leading: f32) {
+		self.leading = leading;
+	}
+}
+
+impl FontSheet {
+	/// The height of a line in texture pixels, which is drawn h high.
+	pub fn get_line_height(&self) -> f32 {
+		self.line_height
+	}
+
+	/// The distance from the top of a line to the baseline in texture pixels.
+	pub fn get_baseline(&self) -> f32 {
+		self.baseline
+	}
+
+	/// Returns the metrics of the sheet on its own when the lines are h high. The line
+	/// advance is h, since the leading is part of the font.
+	pub fn get_metrics(&self, h: f32) -> FontMetrics {
+		let ascent = self.baseline * self.get_scale(h);
+		FontMetrics {
+			ascent,
+			descent: h - ascent,
+			line_height: h,
+			line_advance: h,
+		}
+	}
+
+	/// Returns where the glyph for c is drawn relative to the baseline of the sheet when the
+	/// lines are h high, or None if the sheet does not have c.
+	pub fn get_glyph_metrics(&self, c: char, h: f32) -> Option<GlyphMetrics> {
+		if !self.has_glyph(c) {
+			return None;
+		}
+		self.get_glyph(c).ok().map(|glyph| GlyphMetrics::new(self, glyph, h))
+	}
+}
@@ -0,0 +1,374 @@
+use std::collections::HashMap;
+
+use crate::Texture;
+#[cfg(any(target_arch = "wasm32", target_os = "windows"))]
+use crate::{effects::EffectPass, quad::Quad, Dvr};
+
+use super::{markup::{RichText, Span, TextStyle}, wrap::{justify_space, line_width, wrap, Break, Placed}, Font, FontSheet, Glyph};
+#[cfg(any(target_arch = "wasm32", target_os = "windows"))]
+use super::{Align, TextOptions, VAlign};
+
+/// The fonts and icons that rich text can refer to by name.
+#[derive(Default)]
+pub struct MarkupContext<'a> {
+	fonts: HashMap<String, &'a Font>,
+	/// The bold versions of the fonts. The font that draws the text has the name "".
+	bold: HashMap<String, &'a Font>,
+	icons: HashMap<String, Icon<'a>>,
+}
+
+struct Icon<'a> {
+	texture: &'a Texture,
+	rect: Option<((f32, f32), (f32, f32))>,
+}
+
+impl<'a> MarkupContext<'a> {
+	pub fn new() -> MarkupContext<'a> {
+		MarkupContext::default()
+	}
+
+	/// Adds a font that can be used with [font=name].
+	pub fn add_font(&mut self, name: &str, font: &'a Font) {
+		self.fonts.insert(name.to_string(), font);
+	}
+
+	/// Sets the font that [b] switches to inside [font=name]. Use the name "" for the
+	/// font that draws the text. Fonts without a bold version are used as they are.
+	pub fn set_bold(&mut self, name: &str, bold: &'a Font) {
+		self.bold.insert(name.to_string(), bold);
+	}
+
+	/// Adds an icon that can be used with [icon=name]. Rect is the part of the texture
+	/// that is drawn (position and size, in pixels), or None for the whole texture.
+	pub fn add_icon(&mut self, name: &str, texture: &'a Texture, rect: Option<((f32, f32), (f32, f32))>) {
+		self.icons.insert(name.to_string(), Icon { texture, rect });
+	}
+
+	/// Finds the font for a style. The default font is the font that draws the text.
+	fn get_font(&self, style: &TextStyle, default: &'a Font) -> Result<&'a Font, String> {
+		let name = style.font.as_deref().unwrap_or("");
+		if style.bold {
+			if let Some(bold) = self.bold.get(name) {
+				return Ok(bold);
+			}
+		}
+		match &style.font {
+			Some(name) => self.fonts
+				.get(name)
+				.copied()
+				.ok_or(format!("Font \"{}\" is not in the markup context", name)),
+			None => Ok(default),
+		}
+	}
+
+	fn get_icon(&self, name: &str) -> Result<&Icon<'a>, String> {
+		self.icons.get(name).ok_or(format!("Icon \"{}\" is not in the markup context", name))
+	}
+}
+
+/// A line of rich text after it has been laid out.
+// Without a backend the items are only measured, not drawn
+#[cfg_attr(not(any(target_arch = "wasm32", target_os = "windows")), allow(dead_code))]
+struct RichLine<'a> {
+	items: Vec<RichItem<'a>>,
+	/// The width without the whitespace at the end.
+	width: f32,
+	/// The height of the highest item.
+	height: f32,
+	/// How much wider the spaces are with Align::Justify.
+	justify_space: f32,
+}
+
+/// A glyph or an icon in a RichLine.
+struct RichItem<'a> {
+	kind: ItemKind<'a>,
+	/// The position of the cursor relative to the start of the line.
+	x: f32,
+	advance: f32,
+	/// The height of the text that the item is part of.
+	h: f32,
+	colour: (f32, f32, f32, f32),
+	/// Set for spaces that are stretched with Align::Justify.
+	stretched: bool,
+}
+
+#[cfg_attr(not(any(target_arch = "wasm32", target_os = "windows")), allow(dead_code))]
+enum ItemKind<'a> {
+	/// The glyphs of a grapheme cluster, with the positions of the cursor and the tops of
+	/// the lines for them relative to the item.
+	Glyph { font: &'a Font, glyphs: Vec<(&'a FontSheet, Glyph, (f32, f32))> },
+	Icon { texture: &'a Texture, rect: Option<((f32, f32), (f32, f32))> },
+}
+
+impl Font {
+	/// Draws rich text (see RichText) with this font as the default font. Fonts and icons
+	/// are looked up in the context. Text with different sizes is aligned at the bottom of
+	/// each line, and each line is as high as its highest part. The other arguments work like
+	/// in draw_text(), where h is the height of text with size 1. Rich text is not cut off,
+	/// and each font draws with its own effects, so the overflow and effects of the options
+	/// are not used.
+	#[cfg(any(target_arch = "wasm32", target_os = "windows"))]
+	pub fn draw_rich_text(&self, dvr: &Dvr, text: &RichText, context: &MarkupContext, (x, y): (f32, f32), h: f32, options: &TextOptions) -> Result<(), String> {
+		let TextOptions { max_w, text_align, vert_align, .. } = *options;
+		let lines = self.layout_rich_text(text, context, max_w, h)?;
+		// Glyphs from TrueType/OpenType sheets are rasterized while they are laid out
+		let mut fonts: Vec<&Font> = Vec::new();
+		for item in lines.iter().flat_map(|line| &line.items) {
+			if let ItemKind::Glyph { font, .. } = &item.kind {
+				if !fonts.iter().any(|f| std::ptr::eq(*f, *font)) {
+					fonts.push(*font);
+				}
+			}
+		}
+		for font in fonts {
+			font.update_textures(dvr)?;
+		}
+
+		let text_height = self.rich_text_height(&lines);
+		let top: f32 = match vert_align {
+			VAlign::Top => y,
+			VAlign::Centre => y + 0.5 * text_height,
+			VAlign::Bottom => y + text_height,
+		};
+		// The effects of all glyphs are drawn before the glyphs and icons, so that they do not
+		// cover the glyphs before them
+		for pass in [EffectPass::Effects, EffectPass::Fill] {
+			let mut line_top = top;
+			for line in &lines {
+				let x_offset: f32 = match text_align {
+					Align::Left | Align::Justify => 0.0,
+					Align::Centre => -0.5 * line.width,
+					Align::Right => -line.width,
+				};
+				let space_stretch = match text_align {
+					Align::Justify => line.justify_space,
+					_ => 0.0,
+				};
+				let mut shift = 0.0;
+				for item in &line.items {
+					// Everything on a line is aligned at the bottom
+					let item_top = line_top - (line.height - item.h);
+					let item_x = x + x_offset + item.x + shift;
+					if item.stretched {
+						shift += space_stretch;
+					}
+					match &item.kind {
+						ItemKind::Glyph { font, glyphs } => {
+							let glyph_pass = match font.effects.is_plain() {
+								true if pass == EffectPass::Effects => continue,
+								true => EffectPass::All,
+								false => pass,
+							};
+							for (sheet, glyph, (glyph_x, glyph_y)) in glyphs {
+								if let Some(quad) = sheet.glyph_quad(*glyph, item_x + glyph_x, item_top + glyph_y, item.h) {
+									sheet.draw_glyph(dvr, *glyph, quad, item.colour, &font.effects, glyph_pass)?;
+								}
+							}
+						},
+						ItemKind::Icon { .. } if pass == EffectPass::Effects => {},
+						ItemKind::Icon { texture, rect } => dvr.draw_tinted(
+							texture,
+							&Quad::new(item_x + 0.5 * item.advance, item_top - 0.5 * item.h, Some((item.advance, item.h)), *rect, 0.0),
+							item.colour
+						)?,
+					}
+				}
+				line_top -= line.height * self.leading;
+			}
+		}
+		Ok(())
+	}
+
+	/// Returns the width and height of rich text when it is drawn with draw_rich_text().
+	pub fn calculate_rich_text_size(&self, text: &RichText, context: &MarkupContext, max_w: Option<f32>, h: f32) -> Result<(f32, f32), String> {
+		let lines = self.layout_rich_text(text, context, max_w, h)?;
+		let width = lines.iter().map(|line| line.width).fold(0.0, f32::max);
+		Ok((width, self.rich_text_height(&lines)))
+	}
+
+	/// The height of lines of rich text, from the top of the first line to the bottom of the last.
+	fn rich_text_height(&self, lines: &[RichLine]) -> f32 {
+		let Some((last, lines)) = lines.split_last() else {
+			return 0.0;
+		};
+		lines.iter().map(|line| line.height * self.leading).sum::<f32>() + last.height
+	}
+
+	/// Splits rich text into lines (at line breaks and where it is wrapped) and places the
+	/// items on them.
+	fn layout_rich_text<'a>(&'a self, text: &RichText, context: &MarkupContext<'a>, max_w: Option<f32>, h: f32) -> Result<Vec<RichLine<'a>>, String> {
+		// The spans are split at line breaks, and each line is laid out separately
+		let mut source_lines: Vec<Vec<(&str, Option<&str>, &TextStyle)>> = vec![Vec::new()];
+		for span in text.get_spans() {
+			match span {
+				Span::Text { text, style } => {
+					for (i, part) in text.split('\n').enumerate() {
+						if i != 0 {
+							source_lines.push(Vec::new());
+						}
+						let part = part.strip_suffix('\r').unwrap_or(part);
+						source_lines.last_mut().unwrap().push((part, None, style));
+					}
+				},
+				Span::Icon { name, style } => source_lines.last_mut().unwrap().push(("\u{fffc}", Some(name), style)),
+			}
+		}
+
+		let mut lines = Vec::new();
+		for parts in source_lines {
+			let plain: String = parts.iter().map(|(part, _, _)| *part).collect();
+			// Tab stops are measured from the start of the line that the tab is on, so the rest
+			// of the text line is placed again after each line that comes before a tab
+			let mut byte = 0;
+			loop {
+				let (items, placed) = self.place_rich_parts(&parts, byte, context, h)?;
+				let mut ends = match max_w {
+					Some(max_w) => {
+						let breaks = self.find_breaks(&plain[byte..], &placed, byte);
+						let hyphen_width = |i: usize| match &items[i].kind {
+							ItemKind::Glyph { font, .. } => match font.has_char('-') {
+								true => font.calculate_line_width("-", items[i].h).ok(),
+								false => None,
+							},
+							ItemKind::Icon { .. } => None,
+						};
+						wrap(&placed, &breaks, max_w, hyphen_width)
+					},
+					None => vec![Break { index: placed.len(), hyphen: false }],
+				};
+				if ends.is_empty() {
+					lines.push(RichLine { items: Vec::new(), width: 0.0, height: h, justify_space: 0.0 });
+				}
+				let line_count = ends.len();
+				// The first line is placed from where it starts, and so are the lines after it
+				// until one has a tab
+				let misplaced = (1..ends.len()).find(|&i| placed[ends[i - 1].index..ends[i].index].iter().any(|p| p.c == '\t'));
+				if let Some(misplaced) = misplaced {
+					ends.truncate(misplaced);
+				}
+				let mut items = items.into_iter();
+				let mut start = 0;
+				for (i, end) in ends.into_iter().enumerate() {
+					let start_x = placed.get(start).map(|p| p.x).unwrap_or(0.0);
+					let mut line_items: Vec<RichItem> = items
+						.by_ref()
+						.take(end.index - start)
+						.map(|item| RichItem { x: item.x - start_x, ..item })
+						.collect();
+					let line_placed = &placed[start..end.index];
+					let text_width = line_width(line_placed);
+					// Spaces at the end of the line are not part of its width
+					for item in &mut line_items {
+						item.stretched &= item.x < text_width;
+					}
+					let mut width = text_width;
+					if end.hyphen {
+						if let Some(hyphen) = Self::hyphen_item(line_items.last(), width)? {
+							width += hyphen.advance;
+							line_items.push(hyphen);
+						}
+					}
+					// Only lines that are wrapped are justified, not the last line of a paragraph
+					let justify_space = match max_w {
+						Some(max_w) if i + 1 < line_count => justify_space(line_placed, max_w - width),
+						_ => 0.0,
+					};
+					let height = line_items.iter().map(|item| item.h).fold(0.0, f32::max);
+					lines.push(RichLine {
+						items: line_items,
+						width,
+						height: if height > 0.0 { height } else { h },
+						justify_space,
+					});
+					start = end.index;
+				}
+				match (misplaced, placed.get(start)) {
+					(Some(_), Some(next)) => byte = next.byte,
+					_ => break,
+				}
+			}
+		}
+		Ok(lines)
+	}
+
+	/// Places the items of a text line from the byte from on, where the cursor starts at 0.
+	/// The bytes of the placed characters are bytes in the whole text line.
+	fn place_rich_parts<'a>(&'a self, parts: &[(&str, Option<&str>, &TextStyle)], from: usize, context: &MarkupContext<'a>, h: f32) -> Result<(Vec<RichItem<'a>>, Vec<Placed>), String> {
+		let mut items: Vec<RichItem> = Vec::new();
+		let mut placed: Vec<Placed> = Vec::new();
+		let mut part_start = 0;
+		let mut pen_x: f32 = 0.0;
+		for &(part, icon, style) in parts {
+			let part_end = part_start + part.len();
+			if part_end <= from {
+				part_start = part_end;
+				continue;
+			}
+			// Only the first part that is placed can start after from
+			let skipped = from.saturating_sub(part_start);
+			let item_h = h * style.size;
+			match icon {
+				Some(name) => {
+					let icon = context.get_icon(name)?;
+					let (tw, th) = icon.texture.get_size();
+					let (w, ih) = match icon.rect {
+						Some((_, size)) => size,
+						None => (tw as f32, th as f32),
+					};
+					let advance = if ih > 0.0 { item_h * w / ih } else { 0.0 };
+					placed.push(Placed { byte: part_start, c: '\u{fffc}', x: pen_x, advance });
+					items.push(RichItem {
+						kind: ItemKind::Icon { texture: icon.texture, rect: icon.rect },
+						x: pen_x,
+						advance,
+						h: item_h,
+						colour: style.colour,
+						stretched: false,
+					});
+					pen_x += advance;
+				},
+				None => {
+					let font = context.get_font(style, self)?;
+					let width = font.layout_line_at(&part[skipped..], pen_x, item_h, |placement| {
+						let x = pen_x + placement.x;
+						placed.push(Placed {
+							byte: part_start + skipped + placement.byte,
+							c: placement.c,
+							x,
+							advance: placement.advance,
+						});
+						items.push(RichItem {
+							kind: ItemKind::Glyph { font, glyphs: placement.glyphs((0.0, 0.0)).collect() },
+							x,
+							advance: placement.advance,
+							h: item_h,
+							colour: style.colour,
+							stretched: placement.c == ' ',
+						});
+						Ok(())
+					})?;
+					pen_x += width;
+				},
+			}
+			part_start = part_end;
+		}
+		Ok((items, placed))
+	}
+
+	/// The hyphen at the end of a line that is hyphenated, in the style of the last item
+	/// on the line. The line is width wide without it.
+	fn hyphen_item<'a>(last: Option<&RichItem<'a>>, width: f32) -> Result<Option<RichItem<'a>>, String> {
+		let Some(RichItem { kind: ItemKind::Glyph { font, .. }, h, colour, .. }) = last else {
+			return Ok(None);
+		};
+		let (_, sheet, glyph) = font.get_char_glyph('-')?;
+		Ok(Some(RichItem {
+			kind: ItemKind::Glyph { font, glyphs: vec![(sheet, glyph, (0.0, -font.get_baseline_shift(sheet, *h)))] },
+			x: width,
+			advance: glyph.advance * sheet.get_scale(*h),
+			h: *h,
+			colour: *colour,
+			stretched: false,
+		}))
+	}
+}
@@ -0,0 +1,67 @@
+use super::{Align, Font};
+
+/// A position that a tab character moves the text after it to. Text with a tab between the
+/// cells of each row is laid out as a table, with a column at each stop.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TabStop {
+	/// The distance from the start of the line in units of the line height h, so that the
+	/// stops scale with the text.
+	pub x: f32,
+	/// How the text after the tab (up to the next tab or the end of the line) is aligned at
+	/// the stop. Right is useful for columns of numbers. Justify is the same as Left.
+	pub align: Align,
+}
+
+impl TabStop {
+	pub fn new(x: f32, align: Align) -> TabStop {
+		TabStop { x, align }
+	}
+}
+
+impl Font {
+	/// Sets the tab stops, which have to be in increasing order. Tabs after the last stop
+	/// move to the next multiple of the tab width (see set_tab_width()).
+	pub fn set_tab_stops(&mut self, tab_stops: Vec<TabStop>) {
+		self.tab_stops = tab_stops;
+	}
+
+	pub fn get_tab_stops(&self) -> &[TabStop] {
+		&self.tab_stops
+	}
+
+	/// Sets the distance between the tab stops after the ones from set_tab_stops(), in units
+	/// of the line height. None makes it the width of four spaces.
+	pub fn set_tab_width(&mut self, tab_width: Option<f32>) {
+		self.tab_width = tab_width;
+	}
+
+	pub fn get_tab_width(&self) -> Option<f32> {
+		self.tab_width
+	}
+
+	/// The advance of a tab at x from the start of the line, where rest is the text after
+	/// the tab on the line.
+	pub(super) fn tab_advance(&self, rest: &str, x: f32, h: f32) -> Result<f32, String> {
+		let stop = match self.tab_stops.iter().find(|stop| stop.x * h > x) {
+			Some(stop) => TabStop::new(stop.x * h, stop.align),
+			None => {
+				let tab_width = match self.tab_width {
+					Some(tab_width) => tab_width * h,
+					None => 4.0 * self.calculate_line_width(" ", h)?,
+				};
+				if tab_width <= 0.0 {
+					return Ok(0.0);
+				}
+				TabStop::new(((x / tab_width).floor() + 1.0) * tab_width, Align::Left)
+			},
+		};
+		let cell = rest.split('\t').next().unwrap_or("");
+		let cell_width = match stop.align {
+			Align::Left | Align::Justify => 0.0,
+			Align::Centre => 0.5 * self.calculate_line_width(cell, h)?,
+			Align::Right => self.calculate_line_width(cell, h)?,
+		};
+		// Text that is too wide for the stop starts right after the text before the tab
+		Ok((stop.x - cell_width - x).max(0.0))
+	}
+}
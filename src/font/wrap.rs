@@ -0,0 +1,136 @@
+use unicode_linebreak::linebreaks;
+
+use super::Font;
+
+/// Finds the places where a word may be hyphenated. It is given a word (without surrounding
+/// whitespace) and returns the byte offsets in the word where it may be split with a hyphen,
+/// e.g. [2, 6] for "hyphenation" means "hy-phenation" and "hyphen-ation". This can be used
+/// to plug in language-specific hyphenation.
+pub type Hyphenator = Box<dyn Fn(&str) -> Vec<usize>>;
+
+/// The soft hyphen, which is invisible unless a line is broken after it.
+pub(super) const SOFT_HYPHEN: char = '\u{ad}';
+
+/// A place where a line may be broken.
+#[derive(Clone, Copy)]
+pub(super) struct Break {
+	/// The index of the first character after the break.
+	pub index: usize,
+	/// Whether a hyphen is shown at the end of the line if it is broken here.
+	pub hyphen: bool,
+}
+
+/// A character of the line and its position. Soft hyphens are not placed.
+pub(super) struct Placed {
+	/// Where the character is in the line.
+	pub byte: usize,
+	pub c: char,
+	pub x: f32,
+	pub advance: f32,
+}
+
+impl Font {
+	/// Finds the places where the line may be broken, as indices into placed. The
+	/// end of the line is always included. The bytes of placed are offset by start.
+	pub(super) fn find_breaks(&self, line: &str, placed: &[Placed], start: usize) -> Vec<Break> {
+		let index_of_byte = |byte: usize| placed
+			.binary_search_by_key(&(start + byte), |p| p.byte)
+			.unwrap_or_else(|i| i);
+		let mut breaks = Vec::new();
+		let mut word_start = 0;
+		for (byte, _) in linebreaks(line) {
+			let word = &line[word_start..byte];
+			if let Some(hyphenator) = &self.hyphenator {
+				let trimmed = word.trim();
+				let offset = word_start + (word.len() - word.trim_start().len());
+				let mut points = hyphenator(trimmed);
+				points.sort_unstable();
+				points.dedup();
+				for point in points {
+					if point > 0 && point < trimmed.len() && trimmed.is_char_boundary(point) {
+						breaks.push(Break {
+							index: index_of_byte(offset + point),
+							hyphen: true,
+						});
+					}
+				}
+			}
+			breaks.push(Break {
+				index: index_of_byte(byte),
+				hyphen: line[..byte].ends_with(SOFT_HYPHEN),
+			});
+			word_start = byte;
+		}
+		if breaks.last().is_none_or(|b| b.index != placed.len()) {
+			breaks.push(Break {
+				index: placed.len(),
+				hyphen: false,
+			});
+		}
+		breaks
+	}
+}
+
+/// Chooses where a line that has been placed is broken so that no part is wider than max_w,
+/// from the breaks that find_breaks() found. The last break is the end of the line, unless the
+/// line is empty. hyphen_width gives the width of the hyphen after a character (by index), or
+/// None if a hyphen cannot be drawn there.
+pub(super) fn wrap(placed: &[Placed], breaks: &[Break], max_w: f32, hyphen_width: impl Fn(usize) -> Option<f32>) -> Vec<Break> {
+	let mut ends = Vec::new();
+	let mut start = 0;
+	while start < placed.len() {
+		// The furthest break where the line fits
+		let mut end: Option<Break> = None;
+		for candidate in breaks.iter().filter(|b| b.index > start) {
+			let width = line_width(&placed[start..candidate.index]);
+			let hyphen = match candidate.hyphen {
+				true => match hyphen_width(candidate.index - 1) {
+					Some(hyphen) => hyphen,
+					None => continue,
+				},
+				false => 0.0,
+			};
+			if width + hyphen <= max_w {
+				end = Some(*candidate);
+			}
+			if width > max_w {
+				break;
+			}
+		}
+		// If not even the first word fits it is broken between characters
+		let end = end.unwrap_or_else(|| {
+			let mut index = start + 1;
+			while index < placed.len() && line_width(&placed[start..=index]) <= max_w {
+				index += 1;
+			}
+			Break { index, hyphen: false }
+		});
+		ends.push(end);
+		start = end.index;
+	}
+	ends
+}
+
+/// How much wider each space on a wrapped line is drawn with Align::Justify so that the line
+/// becomes extra wider. Spaces at the end of the line are not part of its width, so they are
+/// not stretched.
+pub(super) fn justify_space(placed: &[Placed], extra: f32) -> f32 {
+	let start_x = placed.first().map(|p| p.x).unwrap_or(0.0);
+	let width = line_width(placed);
+	let spaces = placed.iter().filter(|p| p.c == ' ' && p.x - start_x < width).count();
+	match spaces > 0 && extra > 0.0 {
+		true => extra / spaces as f32,
+		false => 0.0,
+	}
+}
+
+/// The width of some characters if they start a line, without the whitespace at the end.
+pub(super) fn line_width(placed: &[Placed]) -> f32 {
+	let Some(first) = placed.first() else {
+		return 0.0;
+	};
+	match placed.iter().rev().find(|p| !p.c.is_whitespace()) {
+		Some(last) => last.x + last.advance - first.x,
+		None => 0.0,
+	}
+}
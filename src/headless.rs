@@ -0,0 +1,38 @@
+use crate::cache::Asset;
+
+/// Stands in for the texture of a backend on platforms without one. It only has a size,
+/// which is all that fonts need to lay out and measure text (e.g. in tests and tools).
+pub struct Texture {
+	size: (u32, u32),
+}
+
+impl Texture {
+	pub fn new(width: u32, height: u32) -> Texture {
+		Texture {
+			size: (width, height),
+		}
+	}
+
+	pub fn get_size(&self) -> (u32, u32) {
+		self.size
+	}
+
+	pub fn get_width(&self) -> u32 {
+		self.size.0
+	}
+
+	pub fn get_height(&self) -> u32 {
+		self.size.1
+	}
+
+	/// Always 0, since nothing is uploaded anywhere.
+	pub fn get_memory(&self) -> usize {
+		0
+	}
+}
+
+impl Asset for Texture {
+	fn memory(&self) -> usize {
+		self.get_memory()
+	}
+}
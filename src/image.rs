@@ -0,0 +1,373 @@
+/// An image decoded into 8-bit RGBA pixels with straight (not premultiplied) alpha.
+/// The rows are stored from top to bottom. This is what all backends upload, so an
+/// image decodes the same way regardless of platform.
+#[derive(Clone)]
+pub struct Image {
+	width: u32,
+	height: u32,
+	pixels: Vec<u8>,
+}
+
+impl Image {
+	/// Creates an image from RGBA pixels with straight alpha, stored top to bottom.
+	pub fn new(width: u32, height: u32, pixels: Vec<u8>) -> Result<Image, String> {
+		let len = (width as usize).checked_mul(height as usize).and_then(|n| n.checked_mul(4));
+		if len != Some(pixels.len()) {
+			return Err("The number of bytes does not match the size of the image".to_string());
+		}
+		Ok(Image {
+			width,
+			height,
+			pixels,
+		})
+	}
+
+	/// Decodes an image, detecting the format from the data. See ImageFormat for the
+	/// supported formats.
+	pub fn decode(data: &[u8]) -> Result<Image, String> {
+		match ImageFormat::detect(data) {
+			Some(ImageFormat::Png) => Self::decode_png(data),
+			Some(ImageFormat::Qoi) => Self::decode_qoi(data),
+			None => Err("Unsupported image format".to_string()),
+		}
+	}
+
+	pub fn decode_png(data: &[u8]) -> Result<Image, String> {
+		let mut decoder = png::Decoder::new(data);
+		// Expands palettes, low bit depths and tRNS chunks and strips 16-bit channels to 8 bits
+		decoder.set_transformations(png::Transformations::normalize_to_color8());
+		let mut reader = decoder.read_info()
+			.map_err(|e| format!("Failed to decode PNG ({})", e))?;
+		let mut buf = vec![0u8; reader.output_buffer_size()];
+		let info = reader.next_frame(&mut buf)
+			.map_err(|e| format!("Failed to decode PNG ({})", e))?;
+		buf.truncate(info.buffer_size());
+
+		let pixels = match info.color_type {
+			png::ColorType::Rgba => buf,
+			png::ColorType::Rgb => buf
+				.chunks_exact(3)
+				.flat_map(|p| [p[0], p[1], p[2], 255])
+				.collect(),
+			png::ColorType::GrayscaleAlpha => buf
+				.chunks_exact(2)
+				.flat_map(|p| [p[0], p[0], p[0], p[1]])
+				.collect(),
+			png::ColorType::Grayscale => buf
+				.iter()
+				.flat_map(|&g| [g, g, g, 255])
+				.collect(),
+			png::ColorType::Indexed => return Err("Failed to expand PNG palette".to_string()),
+		};
+		Self::new(info.width, info.height, pixels)
+	}
+
+	pub fn decode_qoi(data: &[u8]) -> Result<Image, String> {
+		let mut decoder = qoi::Decoder::new(data)
+			.map_err(|e| format!("Failed to decode QOI ({})", e))?
+			.with_channels(qoi::Channels::Rgba);
+		let header = *decoder.header();
+		let pixels = decoder.decode_to_vec()
+			.map_err(|e| format!("Failed to decode QOI ({})", e))?;
+		Self::new(header.width, header.height, pixels)
+	}
+
+	pub fn get_size(&self) -> (u32, u32) {
+		(self.width, self.height)
+	}
+
+	pub fn get_width(&self) -> u32 {
+		self.width
+	}
+
+	pub fn get_height(&self) -> u32 {
+		self.height
+	}
+
+	/// The RGBA pixels with straight alpha, from top to bottom.
+	pub fn pixels(&self) -> &[u8] {
+		&self.pixels
+	}
+
+	pub fn into_pixels(self) -> Vec<u8> {
+		self.pixels
+	}
+
+	/// Returns the pixels with the colour channels multiplied by alpha.
+	pub fn premultiplied_pixels(&self) -> Vec<u8> {
+		self.pixels
+			.chunks_exact(4)
+			.flat_map(|p| {
+				let a = p[3] as u32;
+				let mul = |c: u8| ((c as u32 * a + 127) / 255) as u8;
+				[mul(p[0]), mul(p[1]), mul(p[2]), p[3]]
+			})
+			.collect()
+	}
+}
+
+/// The image formats that can be decoded by Image::decode().
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ImageFormat {
+	Png,
+	Qoi,
+}
+
+impl ImageFormat {
+	/// Detects the format from the signature at the start of the data.
+	pub fn detect(data: &[u8]) -> Option<ImageFormat> {
+		if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+			Some(ImageFormat::Png)
+		} else if data.starts_with(b"qoif") {
+			Some(ImageFormat::Qoi)
+		} else {
+			None
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Small images with every alpha value that matters (opaque, transparent and in between).
+	fn test_pixels(width: u32, height: u32) -> Vec<u8> {
+		(0..width * height)
+			.flat_map(|i| [(i * 37) as u8, (i * 91 + 7) as u8, (i * 13 + 200) as u8, [255, 0, 128, 1][i as usize % 4]])
+			.collect()
+	}
+
+	fn encode_png(width: u32, height: u32, colour: png::ColorType, depth: png::BitDepth, data: &[u8], palette: Option<(&[u8], &[u8])>) -> Vec<u8> {
+		let mut out = Vec::new();
+		{
+			let mut encoder = png::Encoder::new(&mut out, width, height);
+			encoder.set_color(colour);
+			encoder.set_depth(depth);
+			if let Some((palette, trns)) = palette {
+				encoder.set_palette(palette.to_vec());
+				encoder.set_trns(trns.to_vec());
+			}
+			let mut writer = encoder.write_header().unwrap();
+			writer.write_image_data(data).unwrap();
+		}
+		out
+	}
+
+	fn crc32(bytes: &[u8]) -> u32 {
+		let mut crc = 0xffff_ffffu32;
+		for &byte in bytes {
+			crc ^= byte as u32;
+			for _ in 0..8 {
+				crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+			}
+		}
+		!crc
+	}
+
+	fn push_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+		out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+		let start = out.len();
+		out.extend_from_slice(kind);
+		out.extend_from_slice(data);
+		let crc = crc32(&out[start..]);
+		out.extend_from_slice(&crc.to_be_bytes());
+	}
+
+	fn png_header(width: u32, height: u32, colour_type: u8, interlaced: bool) -> Vec<u8> {
+		let mut out = b"\x89PNG\r\n\x1a\n".to_vec();
+		let mut ihdr = Vec::new();
+		ihdr.extend_from_slice(&width.to_be_bytes());
+		ihdr.extend_from_slice(&height.to_be_bytes());
+		ihdr.extend_from_slice(&[8, colour_type, 0, 0, interlaced as u8]);
+		push_chunk(&mut out, b"IHDR", &ihdr);
+		out
+	}
+
+	/// An Adam7 interlaced 8-bit RGBA PNG, which the png crate cannot write.
+	fn encode_interlaced_png(width: u32, height: u32, rgba: &[u8]) -> Vec<u8> {
+		const PASSES: [(u32, u32, u32, u32); 7] = [(0, 0, 8, 8), (4, 0, 8, 8), (0, 4, 4, 8), (2, 0, 4, 4), (0, 2, 2, 4), (1, 0, 2, 2), (0, 1, 1, 2)];
+		let mut raw = Vec::new();
+		for (x0, y0, dx, dy) in PASSES {
+			if x0 >= width || y0 >= height {
+				continue;
+			}
+			for y in (y0..height).step_by(dy as usize) {
+				// Every row starts with its filter type, which is None here
+				raw.push(0);
+				for x in (x0..width).step_by(dx as usize) {
+					let i = (y * width + x) as usize * 4;
+					raw.extend_from_slice(&rgba[i..i + 4]);
+				}
+			}
+		}
+		let mut out = png_header(width, height, 6, true);
+		push_chunk(&mut out, b"IDAT", &miniz_oxide::deflate::compress_to_vec_zlib(&raw, 6));
+		push_chunk(&mut out, b"IEND", &[]);
+		out
+	}
+
+	fn encode_qoi(width: u32, height: u32, pixels: &[u8]) -> Vec<u8> {
+		qoi::encode_to_vec(pixels, width, height).unwrap()
+	}
+
+	/// A xorshift generator, so that the fuzzing tests are the same every time.
+	struct Random(u64);
+
+	impl Random {
+		fn next(&mut self) -> u64 {
+			self.0 ^= self.0 << 13;
+			self.0 ^= self.0 >> 7;
+			self.0 ^= self.0 << 17;
+			self.0
+		}
+
+		fn below(&mut self, n: usize) -> usize {
+			(self.next() % n as u64) as usize
+		}
+	}
+
+	#[test]
+	fn qoi_round_trip() {
+		let pixels = test_pixels(7, 5);
+		let image = Image::decode(&encode_qoi(7, 5, &pixels)).unwrap();
+		assert_eq!(image.get_size(), (7, 5));
+		assert_eq!(image.pixels(), &pixels[..]);
+	}
+
+	#[test]
+	fn qoi_rgb_is_opaque() {
+		let rgb: Vec<u8> = (0..12u8).collect();
+		let image = Image::decode(&encode_qoi(2, 2, &rgb)).unwrap();
+		assert_eq!(image.pixels(), &[0, 1, 2, 255, 3, 4, 5, 255, 6, 7, 8, 255, 9, 10, 11, 255]);
+	}
+
+	#[test]
+	fn png_rgba_round_trip() {
+		let pixels = test_pixels(5, 3);
+		let data = encode_png(5, 3, png::ColorType::Rgba, png::BitDepth::Eight, &pixels, None);
+		let image = Image::decode(&data).unwrap();
+		assert_eq!(image.get_size(), (5, 3));
+		assert_eq!(image.pixels(), &pixels[..]);
+	}
+
+	#[test]
+	fn png_palette_with_transparency() {
+		let palette = [255, 0, 0, 0, 255, 0, 0, 0, 255];
+		// The third entry has no alpha in tRNS, so it is opaque
+		let trns = [0, 128];
+		let data = encode_png(3, 1, png::ColorType::Indexed, png::BitDepth::Eight, &[2, 1, 0], Some((&palette, &trns)));
+		let image = Image::decode(&data).unwrap();
+		assert_eq!(image.pixels(), &[0, 0, 255, 255, 0, 255, 0, 128, 255, 0, 0, 0]);
+	}
+
+	#[test]
+	fn png_low_bit_depth_palette() {
+		let palette = [10, 20, 30, 40, 50, 60];
+		// Four 2-bit indices in one byte: 1, 0, 1, 1
+		let data = encode_png(4, 1, png::ColorType::Indexed, png::BitDepth::Two, &[0b0100_0101], Some((&palette, &[])));
+		let image = Image::decode(&data).unwrap();
+		assert_eq!(image.pixels(), &[40, 50, 60, 255, 10, 20, 30, 255, 40, 50, 60, 255, 40, 50, 60, 255]);
+	}
+
+	#[test]
+	fn png_grey() {
+		let data = encode_png(2, 1, png::ColorType::Grayscale, png::BitDepth::Eight, &[0, 200], None);
+		assert_eq!(Image::decode(&data).unwrap().pixels(), &[0, 0, 0, 255, 200, 200, 200, 255]);
+		let data = encode_png(2, 1, png::ColorType::GrayscaleAlpha, png::BitDepth::Eight, &[50, 0, 60, 70], None);
+		assert_eq!(Image::decode(&data).unwrap().pixels(), &[50, 50, 50, 0, 60, 60, 60, 70]);
+	}
+
+	#[test]
+	fn png_16_bit_keeps_high_bytes() {
+		let data = encode_png(2, 1, png::ColorType::Grayscale, png::BitDepth::Sixteen, &[0x12, 0x34, 0xfe, 0xdc], None);
+		assert_eq!(Image::decode(&data).unwrap().pixels(), &[0x12, 0x12, 0x12, 255, 0xfe, 0xfe, 0xfe, 255]);
+		let rgba16 = [1, 2, 3, 4, 5, 6, 7, 8];
+		let data = encode_png(1, 1, png::ColorType::Rgba, png::BitDepth::Sixteen, &rgba16, None);
+		assert_eq!(Image::decode(&data).unwrap().pixels(), &[1, 3, 5, 7]);
+	}
+
+	#[test]
+	fn png_interlaced() {
+		// Sizes that leave some of the passes empty and some partly filled
+		for (width, height) in [(1, 1), (3, 2), (9, 10), (17, 5)] {
+			let pixels = test_pixels(width, height);
+			let image = Image::decode(&encode_interlaced_png(width, height, &pixels)).unwrap();
+			assert_eq!(image.get_size(), (width, height));
+			assert_eq!(image.pixels(), &pixels[..], "{}x{}", width, height);
+		}
+	}
+
+	#[test]
+	fn unknown_and_empty_input() {
+		assert!(Image::decode(&[]).is_err());
+		assert!(Image::decode(b"GIF89a").is_err());
+		assert_eq!(ImageFormat::detect(b"qoif"), Some(ImageFormat::Qoi));
+	}
+
+	#[test]
+	fn truncated_input_is_an_error() {
+		let pixels = test_pixels(6, 6);
+		let inputs = [
+			encode_png(6, 6, png::ColorType::Rgba, png::BitDepth::Eight, &pixels, None),
+			encode_interlaced_png(6, 6, &pixels),
+			encode_qoi(6, 6, &pixels),
+		];
+		for data in inputs {
+			for len in 0..data.len() {
+				let result = Image::decode(&data[..len]);
+				// The end marker of QOI and the IEND chunk of PNG are not needed for the pixels
+				if let Ok(image) = result {
+					assert!(len + 12 >= data.len(), "{} of {} bytes decoded", len, data.len());
+					assert_eq!(image.pixels(), &pixels[..]);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn oversized_headers_are_errors() {
+		// Headers that claim huge images, without the data for them
+		for (width, height) in [(1 << 30, 1 << 30), (u32::MAX, u32::MAX), (0x7fff_ffff, 2)] {
+			let mut png = png_header(width, height, 6, false);
+			push_chunk(&mut png, b"IDAT", &miniz_oxide::deflate::compress_to_vec_zlib(&[0, 1, 2, 3, 4], 6));
+			push_chunk(&mut png, b"IEND", &[]);
+			assert!(Image::decode(&png).is_err(), "PNG {}x{}", width, height);
+
+			let mut qoi = b"qoif".to_vec();
+			qoi.extend_from_slice(&width.to_be_bytes());
+			qoi.extend_from_slice(&height.to_be_bytes());
+			qoi.extend_from_slice(&[4, 0, 0xfe, 1, 2, 3, 0, 0, 0, 0, 0, 0, 0, 1]);
+			assert!(Image::decode(&qoi).is_err(), "QOI {}x{}", width, height);
+		}
+		assert!(Image::new(u32::MAX, u32::MAX, Vec::new()).is_err());
+	}
+
+	#[test]
+	fn fuzz_decode() {
+		let pixels = test_pixels(8, 5);
+		let seeds = [
+			encode_png(8, 5, png::ColorType::Rgba, png::BitDepth::Eight, &pixels, None),
+			encode_png(3, 1, png::ColorType::Indexed, png::BitDepth::Eight, &[2, 1, 0], Some((&[1, 2, 3, 4, 5, 6, 7, 8, 9], &[0]))),
+			encode_interlaced_png(8, 5, &pixels),
+			encode_qoi(8, 5, &pixels),
+		];
+		let mut random = Random(0x9e37_79b9_7f4a_7c15);
+		for _ in 0..4000 {
+			let mut data = seeds[random.below(seeds.len())].clone();
+			for _ in 0..1 + random.below(8) {
+				let i = random.below(data.len());
+				match random.below(4) {
+					0 => data[i] = random.next() as u8,
+					1 => data[i] ^= 1 << random.below(8),
+					2 => data.truncate(i.max(8)),
+					_ => data.insert(i, random.next() as u8),
+				}
+			}
+			// Anything may happen except panicking, and decoded images have all their pixels
+			if let Ok(image) = Image::decode(&data) {
+				let (width, height) = image.get_size();
+				assert_eq!(image.pixels().len(), width as usize * height as usize * 4);
+			}
+		}
+	}
+}
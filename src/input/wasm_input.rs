@@ -2,7 +2,7 @@ use std::{cell::RefCell, collections::HashMap, rc::Rc};
 use crate::{wasm_utils::{add_event_listener, js_val_err_to_string}, Dvr};
 use queues::{CircularBuffer, IsQueue, Queue};
 use wasm_bindgen::{prelude::Closure, JsCast};
-use web_sys::{CompositionEvent, HtmlCanvasElement, KeyboardEvent, MouseEvent};
+use web_sys::{CompositionEvent, Element, HtmlCanvasElement, HtmlInputElement, KeyboardEvent, MouseEvent};
 use super::{Event, KeyCodeEvent};
 
 pub struct Input {
@@ -11,9 +11,14 @@ pub struct Input {
 	events: Rc<RefCell<Box<dyn IsQueue<Event>>>>,
 	max_events: Option<usize>,
 	canvas: HtmlCanvasElement,
+	/// A hidden text input that has the keyboard focus while text is edited, because the
+	/// canvas does not get the events of input methods.
+	text_input: HtmlInputElement,
+	text_input_style: RefCell<String>,
 	keydown_closure: Closure<dyn FnMut(KeyboardEvent)>,
 	keyup_closure: Closure<dyn FnMut(KeyboardEvent)>,
 	compositionend_closure: Closure<dyn FnMut(CompositionEvent)>,
+	blur_closure: Closure<dyn FnMut(web_sys::Event)>,
 	mousedown_closure: Closure<dyn FnMut(MouseEvent)>,
 	mouseup_closure: Closure<dyn FnMut(MouseEvent)>,
 	mousemove_closure: Closure<dyn FnMut(MouseEvent)>,
@@ -22,9 +27,32 @@ pub struct Input {
 }
 
 impl Input {
+	/// Keyboard events are only received while the canvas has focus, so that several
+	/// Interfaces on one page get their own key presses. The canvas is made focusable, gets
+	/// focus when it is clicked and also when it is created if nothing else has focus. See
+	/// set_text_area() for text from input methods.
 	pub fn new(dvr: &Dvr, max_events: Option<usize>) -> Result<Input, String> {
 		let window = web_sys::window().ok_or("Unable to get window")?;
 		let canvas = dvr.canvas()?;
+		if !canvas.has_attribute("tabindex") {
+			canvas.set_tab_index(0);
+		}
+		let document = window.document().ok_or("Unable to get document")?;
+		let nothing_focused = document.active_element().is_none_or(|element| Some(&element) == document.body().as_deref());
+		if nothing_focused {
+			let _ = canvas.focus();
+		}
+		let text_input = document
+			.create_element("input")
+			.map_err(js_val_err_to_string)?
+			.dyn_into::<HtmlInputElement>()
+			.map_err(|_| "Unable to create text input")?;
+		text_input.set_type("text");
+		text_input.set_tab_index(-1);
+		text_input.set_attribute("autocomplete", "off").map_err(js_val_err_to_string)?;
+		let text_input_style = text_input_style(0.0, 0.0, 1.0, 1.0);
+		text_input.set_attribute("style", &text_input_style).map_err(js_val_err_to_string)?;
+		document.body().ok_or("Unable to get body")?.append_child(&text_input).map_err(js_val_err_to_string)?;
 		let key_states = Rc::new(RefCell::new(HashMap::new()));
 		let mouse_pos = Rc::new(RefCell::new(None));
 		let events = Rc::new(RefCell::new(Self::new_key_events(max_events)));
@@ -32,8 +60,9 @@ impl Input {
 		{
 			let key_states = key_states.clone();
 			let events = events.clone();
+			let typing_target: web_sys::EventTarget = text_input.clone().into();
 			keydown_closure = add_event_listener(
-				window.as_ref(),
+				canvas.as_ref(),
 				"keydown",
 				move |e: KeyboardEvent| {
 					let key_code = e.code();
@@ -41,21 +70,31 @@ impl Input {
 					let _ = events.borrow_mut().add(Event::KeyDown(js_key_event_to_dvr(&e)));
 					if !e.is_composing() {
 						let key = e.key();
-						if key == "Enter" {
-							let _ = events.borrow_mut().add(Event::Char("\n".to_string()));
+						let c = if key == "Enter" {
+							Some("\n".to_string())
 						} else if key.chars().count() == 1 {
-							let _ = events.borrow_mut().add(Event::Char(e.key()));
+							Some(key)
+						} else {
+							None
+						};
+						if let Some(c) = c {
+							let _ = events.borrow_mut().add(Event::Char(c));
+							// Keeps the text input empty, as the text is in the TextField
+							if e.target().as_ref() == Some(&typing_target) {
+								e.prevent_default();
+							}
 						}
 					}
 				}
 			).map_err(js_val_err_to_string)?;
+			text_input.add_event_listener_with_callback("keydown", keydown_closure.as_ref().unchecked_ref()).map_err(js_val_err_to_string)?;
 		}
 		let keyup_closure;
 		{
 			let key_states = key_states.clone();
 			let events = events.clone();
 			keyup_closure = add_event_listener(
-				window.as_ref(),
+				canvas.as_ref(),
 				"keyup",
 				move |e: KeyboardEvent| {
 					let key_code = e.code();
@@ -63,27 +102,45 @@ impl Input {
 					let _ = events.borrow_mut().add(Event::KeyUp(js_key_event_to_dvr(&e)));
 				}
 			).map_err(js_val_err_to_string)?;
+			text_input.add_event_listener_with_callback("keyup", keyup_closure.as_ref().unchecked_ref()).map_err(js_val_err_to_string)?;
 		}
 		let compositionend_closure;
 		{
 			let events = events.clone();
+			let composing_input = text_input.clone();
 			compositionend_closure = add_event_listener(
-				window.as_ref(),
+				text_input.as_ref(),
 				"compositionend",
 				move |e: CompositionEvent| {
 					if let Some(chars) = e.data() {
 						let _ = events.borrow_mut().add(Event::Char(chars));
 					}
+					composing_input.set_value("");
 				}
 			).map_err(js_val_err_to_string)?;
 		}
+		let blur_closure;
+		{
+			let key_states = key_states.clone();
+			// Keys that are released after the canvas lost focus would otherwise stay down
+			blur_closure = add_event_listener(
+				canvas.as_ref(),
+				"blur",
+				move |_: web_sys::Event| {
+					key_states.borrow_mut().clear();
+				}
+			).map_err(js_val_err_to_string)?;
+			text_input.add_event_listener_with_callback("blur", blur_closure.as_ref().unchecked_ref()).map_err(js_val_err_to_string)?;
+		}
 		let mousedown_closure;
 		{
 			let events = events.clone();
+			let focus_canvas = canvas.clone();
 			mousedown_closure = add_event_listener(
 				canvas.as_ref(),
 				"mousedown",
 				move |e: MouseEvent| {
+					let _ = focus_canvas.focus();
 					let _ = events.borrow_mut().add(Event::MouseDown(js_mouse_event_to_dvr(&e)));
 				}
 			).map_err(js_val_err_to_string)?;
@@ -138,9 +195,12 @@ impl Input {
 			events,
 			max_events,
 			canvas,
+			text_input,
+			text_input_style: RefCell::new(text_input_style),
 			keydown_closure,
 			keyup_closure,
 			compositionend_closure,
+			blur_closure,
 			mousedown_closure,
 			mouseup_closure,
 			mousemove_closure,
@@ -171,6 +231,43 @@ impl Input {
 		self.get_mouse_pos().map(|(_x, y)| y)
 	}
 
+	/// Lets input methods (e.g. for Chinese or Japanese) be used to enter text in the given
+	/// area, which is (x, y, w, h) in the coordinates of the Dvr with (x, y) as the top left
+	/// corner. While area is Some the keyboard focus is moved from the canvas to a hidden text
+	/// input over the area, so that the window of the input method is shown next to it, and
+	/// committed text arrives as Char events. Call this every frame, e.g. with
+	/// TextField::get_text_area(), and with None to give the focus back to the canvas.
+	pub fn set_text_area(&self, area: Option<(f32, f32, f32, f32)>) -> Result<(), String> {
+		let document = web_sys::window()
+			.ok_or("Unable to get window")?
+			.document()
+			.ok_or("Unable to get document")?;
+		let active = document.active_element();
+		let text_input: &Element = self.text_input.as_ref();
+		let canvas: &Element = self.canvas.as_ref();
+		let typing = active.as_ref() == Some(text_input);
+		match area {
+			Some((x, y, w, h)) => {
+				let rect = self.canvas.get_bounding_client_rect();
+				let left = rect.left() as f32 + x + self.canvas.client_width() as f32 * 0.5;
+				let top = rect.top() as f32 + self.canvas.client_height() as f32 * 0.5 - y;
+				let style = text_input_style(left, top, w, h);
+				if *self.text_input_style.borrow() != style {
+					self.text_input.set_attribute("style", &style).map_err(js_val_err_to_string)?;
+					*self.text_input_style.borrow_mut() = style;
+				}
+				// The focus is not taken from other elements on the page
+				if active.as_ref() == Some(canvas) {
+					self.text_input.focus().map_err(js_val_err_to_string)?;
+				}
+			},
+			None => if typing {
+				self.canvas.focus().map_err(js_val_err_to_string)?;
+			},
+		}
+		Ok(())
+	}
+
 	pub fn clear_events(&mut self) {
 		*self.events.borrow_mut() = Self::new_key_events(self.max_events);
 	}
@@ -193,6 +290,14 @@ impl Iterator for &Input {
 
 impl Drop for Input {
 	fn drop(&mut self) {
+		for (event, closure) in [
+			("blur", self.blur_closure.as_ref()),
+			("keyup", self.keyup_closure.as_ref()),
+			("keydown", self.keydown_closure.as_ref()),
+		] {
+			let _ = self.text_input.remove_event_listener_with_callback(event, closure.unchecked_ref());
+		}
+		self.text_input.remove();
 		let _ = self.canvas.remove_event_listener_with_callback(
 			"mouseleave",
 			self.mouseleave_closure.as_ref().unchecked_ref()
@@ -213,23 +318,35 @@ impl Drop for Input {
 			"mousedown",
 			self.mousedown_closure.as_ref().unchecked_ref()
 		);
-		if let Some(window) = web_sys::window() {
-			let _ = window.remove_event_listener_with_callback(
-				"compositionend",
-				self.compositionend_closure.as_ref().unchecked_ref()
-			);
-			let _ = window.remove_event_listener_with_callback(
-				"keyup",
-				self.keyup_closure.as_ref().unchecked_ref()
-			);
-			let _ = window.remove_event_listener_with_callback(
-				"keydown",
-				self.keydown_closure.as_ref().unchecked_ref()
-			);
-		}
+		let _ = self.canvas.remove_event_listener_with_callback(
+			"blur",
+			self.blur_closure.as_ref().unchecked_ref()
+		);
+		let _ = self.text_input.remove_event_listener_with_callback(
+			"compositionend",
+			self.compositionend_closure.as_ref().unchecked_ref()
+		);
+		let _ = self.canvas.remove_event_listener_with_callback(
+			"keyup",
+			self.keyup_closure.as_ref().unchecked_ref()
+		);
+		let _ = self.canvas.remove_event_listener_with_callback(
+			"keydown",
+			self.keydown_closure.as_ref().unchecked_ref()
+		);
 	}
 }
 
+/// The text input is invisible and lets the mouse through to the canvas.
+fn text_input_style(left: f32, top: f32, w: f32, h: f32) -> String {
+	format!(
+		"position: fixed; left: {left}px; top: {top}px; width: {}px; height: {}px; \
+		opacity: 0; pointer-events: none; border: 0; padding: 0; margin: 0;",
+		w.max(1.0),
+		h.max(1.0)
+	)
+}
+
 pub fn denormalise(key_code: &str) -> Vec<String> {
 	match key_code {
 		"Shift" => vec!["ShiftLeft".to_string(), "ShiftRight".to_string()],
@@ -1,33 +1,100 @@
 use wasm_bindgen::JsCast;
-use web_sys::WebGl2RenderingContext;
-use crate::DvrCtx;
+use web_sys::{Element, HtmlCanvasElement, WebGl2RenderingContext, WebGlContextAttributes};
+use crate::{wasm_utils::js_val_err_to_string, DvrCtx};
 
 pub struct Interface {
+	canvas: HtmlCanvasElement,
 	context: WebGl2RenderingContext
 }
 
 impl Interface {
+	/// Attaches to the canvas element with the id "canvas", using the default context attributes.
 	pub fn new() -> Result<Interface, String> {
+		Self::from_id("canvas", &ContextAttributes::default())
+	}
+
+	/// Attaches to the canvas element with the given id.
+	pub fn from_id(id: &str, attributes: &ContextAttributes) -> Result<Interface, String> {
 		let window = web_sys::window().ok_or("Unable to get window")?;
 		let document = window.document().ok_or("Unable to get document")?;
 
-		let canvas: web_sys::HtmlCanvasElement = document
-			.get_element_by_id("canvas")
-			.unwrap()
-			.dyn_into::<web_sys::HtmlCanvasElement>()
-			.map_err(|e| e.as_string().unwrap_or("Unknown error".to_string()))?; // TODO: better way?
+		let canvas = document
+			.get_element_by_id(id)
+			.ok_or(format!("There is no element with the id \"{}\"", id))?
+			.dyn_into::<HtmlCanvasElement>()
+			.map_err(|_| format!("The element with the id \"{}\" is not a canvas", id))?;
+
+		Self::from_canvas(canvas, attributes)
+	}
 
+	/// Creates a new canvas element and appends it to the given container element.
+	/// The canvas is given the CSS size 100% × 100% so it can be sized through the container.
+	pub fn in_container(container: &Element, attributes: &ContextAttributes) -> Result<Interface, String> {
+		let window = web_sys::window().ok_or("Unable to get window")?;
+		let document = window.document().ok_or("Unable to get document")?;
+
+		let canvas = document
+			.create_element("canvas")
+			.map_err(js_val_err_to_string)?
+			.dyn_into::<HtmlCanvasElement>()
+			.map_err(|_| "Created element is not a canvas")?;
+		canvas
+			.set_attribute("style", "display: block; width: 100%; height: 100%;")
+			.map_err(js_val_err_to_string)?;
+		container
+			.append_child(&canvas)
+			.map_err(js_val_err_to_string)?;
+
+		Self::from_canvas(canvas, attributes)
+	}
+
+	/// Attaches to an existing canvas element. Each Interface gets its own WebGL context,
+	/// so several Interfaces (and Dvrs and StateHandlers) can be used on the same page as long
+	/// as they use different canvases.
+	pub fn from_canvas(canvas: HtmlCanvasElement, attributes: &ContextAttributes) -> Result<Interface, String> {
 		let context = canvas
-			.get_context("webgl2")
-			.map_err(|e| e.as_string().unwrap_or("Unknown error".to_string()))?
-			.unwrap()
+			.get_context_with_context_options("webgl2", &attributes.to_js())
+			.map_err(js_val_err_to_string)?
+			.ok_or("Unable to get WebGL2 context")?
 			.dyn_into::<WebGl2RenderingContext>()
-			.map_err(|e| e.as_string().unwrap_or("Unknown error".to_string()))?;
+			.map_err(|_| "Context is not a WebGl2RenderingContext")?;
 
-		Ok(Interface { context })
+		Ok(Interface { canvas, context })
 	}
 
 	pub fn get_ctx(&self) -> DvrCtx {
 		self.context.clone()
 	}
-}
\ No newline at end of file
+
+	pub fn canvas(&self) -> HtmlCanvasElement {
+		self.canvas.clone()
+	}
+}
+
+/// Attributes for creating the WebGL context. The defaults are the same as the browser's.
+#[derive(Clone)]
+pub struct ContextAttributes {
+	pub alpha: bool,
+	pub antialias: bool,
+	pub preserve_drawing_buffer: bool,
+}
+
+impl ContextAttributes {
+	fn to_js(&self) -> WebGlContextAttributes {
+		let attributes = WebGlContextAttributes::new();
+		attributes.set_alpha(self.alpha);
+		attributes.set_antialias(self.antialias);
+		attributes.set_preserve_drawing_buffer(self.preserve_drawing_buffer);
+		attributes
+	}
+}
+
+impl Default for ContextAttributes {
+	fn default() -> Self {
+		ContextAttributes {
+			alpha: true,
+			antialias: true,
+			preserve_drawing_buffer: false,
+		}
+	}
+}
@@ -12,10 +12,43 @@ pub use crate::win::*;
 #[cfg(target_os = "windows")]
 mod win_utils;
 
-pub mod state;
+// Without a backend, fonts use textures that only have a size
+#[cfg(not(any(target_arch = "wasm32", target_os = "windows")))]
+mod headless;
+#[cfg(not(any(target_arch = "wasm32", target_os = "windows")))]
+pub use crate::headless::*;
+
+#[cfg(any(target_arch = "wasm32", test))]
+mod context;
+// This has no backend code, but only the backends track resources
+#[cfg(any(target_arch = "wasm32", target_os = "windows", test))]
+pub mod resources;
+// Only the backends draw batches, but the vertices do not depend on one
+#[cfg(any(target_arch = "wasm32", target_os = "windows", test))]
+mod batch;
+
+pub mod image;
+pub mod bundle;
+pub mod cache;
+pub mod bmfont;
+pub mod ttf;
+pub mod text_edit;
+pub mod sdf;
+pub mod effects;
+pub mod quad;
 pub mod font;
+
+// These depend on a backend, so they are not available on other platforms
+#[cfg(any(target_arch = "wasm32", target_os = "windows"))]
+pub mod state;
+#[cfg(any(target_arch = "wasm32", target_os = "windows"))]
 pub mod input;
+#[cfg(any(target_arch = "wasm32", target_os = "windows"))]
 pub mod interface;
+#[cfg(any(target_arch = "wasm32", target_os = "windows"))]
+pub mod loader;
+#[cfg(any(target_arch = "wasm32", target_os = "windows"))]
+pub mod text_field;
 
 #[cfg(target_arch = "wasm32")]
 type DvrCtx = web_sys::WebGl2RenderingContext;
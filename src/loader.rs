@@ -0,0 +1,138 @@
+use crate::{image::Image, state::{LogicStatus, State}, Dvr, TextureHandler};
+
+#[cfg(target_arch = "wasm32")]
+mod wasm_loader;
+#[cfg(target_arch = "wasm32")]
+pub use wasm_loader::*;
+
+#[cfg(target_os = "windows")]
+mod win_loader;
+#[cfg(target_os = "windows")]
+pub use win_loader::*;
+
+/// How far a Loader has come.
+#[derive(Clone, Default)]
+pub struct LoadProgress {
+	/// The total number of items.
+	pub total: usize,
+	/// The number of items that have been loaded successfully.
+	pub done: usize,
+	/// The names of the items that failed to load, along with the errors.
+	pub failed: Vec<(String, String)>,
+	/// The number of bytes that have been loaded. This only counts items whose size is known,
+	/// which does not include textures loaded from URLs in the browser.
+	pub bytes_loaded: u64,
+}
+
+impl LoadProgress {
+	fn new(total: usize) -> LoadProgress {
+		LoadProgress {
+			total,
+			..Default::default()
+		}
+	}
+
+	/// The number of items that have either been loaded or failed.
+	pub fn finished_items(&self) -> usize {
+		self.done + self.failed.len()
+	}
+
+	/// How much of the loading is finished, from 0.0 to 1.0.
+	pub fn fraction(&self) -> f32 {
+		match self.total {
+			0 => 1.0,
+			total => self.finished_items() as f32 / total as f32,
+		}
+	}
+
+	pub fn is_finished(&self) -> bool {
+		self.finished_items() >= self.total
+	}
+
+	/// Returns an error describing the failed items if there are any.
+	fn check_failures(&self) -> Result<(), String> {
+		if self.failed.is_empty() {
+			return Ok(());
+		}
+		Err(self.failed
+			.iter()
+			.map(|(name, e)| format!("Failed to load \"{}\" ({})", name, e))
+			.collect::<Vec<String>>()
+			.join(", "))
+	}
+}
+
+type NextState<Glob> = Box<dyn FnOnce(TextureHandler, &mut Glob) -> Result<Box<dyn State<Glob>>, String>>;
+type DrawProgress<Glob> = Box<dyn Fn(&Dvr, &Glob, &LoadProgress) -> Result<(), String>>;
+
+/// A state that shows a loading screen while a Loader is working and then switches to the
+/// state created by next, which is given the loaded textures. If anything fails to load,
+/// logic() returns an error.
+pub struct LoadingState<Glob> {
+	loader: Option<Loader>,
+	next: Option<NextState<Glob>>,
+	draw_progress: DrawProgress<Glob>,
+}
+
+impl<Glob> LoadingState<Glob> {
+	/// Creates a loading state that shows a progress bar.
+	pub fn new(dvr: &Dvr, loader: Loader, next: impl FnOnce(TextureHandler, &mut Glob) -> Result<Box<dyn State<Glob>>, String> + 'static) -> Result<LoadingState<Glob>, String> {
+		let white = dvr.load_texture_image(Image::new(1, 1, vec![255; 4])?)?;
+		Ok(Self::with_draw(loader, next, move |dvr, _glob, progress| draw_progress_bar(dvr, &white, progress)))
+	}
+
+	/// Creates a loading state that draws the loading screen using draw.
+	pub fn with_draw(loader: Loader, next: impl FnOnce(TextureHandler, &mut Glob) -> Result<Box<dyn State<Glob>>, String> + 'static, draw: impl Fn(&Dvr, &Glob, &LoadProgress) -> Result<(), String> + 'static) -> LoadingState<Glob> {
+		LoadingState {
+			loader: Some(loader),
+			next: Some(Box::new(next)),
+			draw_progress: Box::new(draw),
+		}
+	}
+}
+
+impl<Glob> State<Glob> for LoadingState<Glob> {
+	fn logic(&mut self, glob: &mut Glob) -> Result<LogicStatus<Glob>, String> {
+		let loader = self.loader.as_mut().ok_or("Loading state has already finished")?;
+		if !loader.update().is_finished() {
+			return Ok(LogicStatus::Continue);
+		}
+		let textures = self.loader.take().ok_or("Loading state has already finished")?.finish()?;
+		let next = self.next.take().ok_or("Loading state has already finished")?;
+		Ok(LogicStatus::NewState(next(textures, glob)?))
+	}
+
+	fn draw(&self, dvr: &Dvr, glob: &Glob) -> Result<(), String> {
+		let progress = match &self.loader {
+			Some(loader) => loader.progress(),
+			None => return Ok(()),
+		};
+		(self.draw_progress)(dvr, glob, progress)
+	}
+}
+
+fn draw_progress_bar(dvr: &Dvr, white: &crate::Texture, progress: &LoadProgress) -> Result<(), String> {
+	const WIDTH: f32 = 300.0;
+	const HEIGHT: f32 = 20.0;
+	const BORDER: f32 = 2.0;
+	dvr.clear(0.0, 0.0, 0.0, 1.0)?;
+	// The frame
+	dvr.draw(white, 0.0, 0.5 * (HEIGHT - BORDER), Some((WIDTH, BORDER)), None, 0.0)?;
+	dvr.draw(white, 0.0, -0.5 * (HEIGHT - BORDER), Some((WIDTH, BORDER)), None, 0.0)?;
+	dvr.draw(white, -0.5 * (WIDTH - BORDER), 0.0, Some((BORDER, HEIGHT)), None, 0.0)?;
+	dvr.draw(white, 0.5 * (WIDTH - BORDER), 0.0, Some((BORDER, HEIGHT)), None, 0.0)?;
+	// The bar, with a gap between it and the frame
+	let inner_width = WIDTH - 4.0 * BORDER;
+	let bar_width = inner_width * progress.fraction();
+	if bar_width > 0.0 {
+		dvr.draw(
+			white,
+			-0.5 * inner_width + 0.5 * bar_width,
+			0.0,
+			Some((bar_width, HEIGHT - 4.0 * BORDER)),
+			None,
+			0.0
+		)?;
+	}
+	Ok(())
+}
@@ -0,0 +1,65 @@
+use std::{future::Future, pin::Pin, task::{Context, Poll, Waker}};
+use crate::{Dvr, Texture, TextureHandler};
+use super::LoadProgress;
+
+type TextureFuture = Pin<Box<dyn Future<Output = Result<Texture, String>>>>;
+
+/// Loads textures while reporting the progress. Unlike TextureHandler::new() this is not
+/// awaited; instead update() should be called regularly, e.g. from State::logic().
+pub struct Loader {
+	pending: Vec<(String, String, TextureFuture)>,
+	textures: Vec<(String, String, Texture)>,
+	progress: LoadProgress,
+}
+
+impl Loader {
+	/// Starts loading the textures. The arguments work like the ones to TextureHandler::new().
+	pub fn new(dvr: &Dvr, names: &[&str], name_transform: impl Fn(&str) -> String) -> Result<Loader, String> {
+		let mut pending: Vec<(String, String, TextureFuture)> = Vec::new();
+		for name in names {
+			let url = name_transform(name);
+			let future = Box::pin(dvr.load_texture_internal(&url, true)?);
+			pending.push((name.to_string(), url, future));
+		}
+		Ok(Loader {
+			progress: LoadProgress::new(pending.len()),
+			pending,
+			textures: Vec::new(),
+		})
+	}
+
+	/// Checks which textures have finished loading and returns the progress.
+	pub fn update(&mut self) -> &LoadProgress {
+		// The images are loaded by the browser, so nothing has to be woken up
+		let mut cx = Context::from_waker(Waker::noop());
+		let textures = &mut self.textures;
+		let progress = &mut self.progress;
+		self.pending.retain_mut(|(name, url, future)| match future.as_mut().poll(&mut cx) {
+			Poll::Pending => true,
+			Poll::Ready(Ok(texture)) => {
+				textures.push((name.clone(), url.clone(), texture));
+				progress.done += 1;
+				false
+			},
+			Poll::Ready(Err(e)) => {
+				progress.failed.push((name.clone(), e));
+				false
+			},
+		});
+		&self.progress
+	}
+
+	pub fn progress(&self) -> &LoadProgress {
+		&self.progress
+	}
+
+	/// Returns a texture handler with the loaded textures. This fails if the loading is not
+	/// finished or if any texture failed to load.
+	pub fn finish(self) -> Result<TextureHandler, String> {
+		if !self.progress.is_finished() {
+			return Err("Loading is not finished".to_string());
+		}
+		self.progress.check_failures()?;
+		Ok(TextureHandler::from_textures(self.textures))
+	}
+}
@@ -0,0 +1,71 @@
+use std::{collections::VecDeque, time::{Duration, Instant}};
+use crate::{Dvr, Texture, TextureFactory, TextureHandler};
+use super::LoadProgress;
+
+/// How long update() may spend loading before it returns, so that the
+/// progress can be drawn.
+const UPDATE_TIME: Duration = Duration::from_millis(10);
+
+/// Loads textures while reporting the progress. Nothing is loaded until update() is called;
+/// each call loads textures for a short while and should be made regularly, e.g. from
+/// State::logic().
+pub struct Loader {
+	factory: TextureFactory,
+	pending: VecDeque<(String, String)>,
+	textures: Vec<(String, String, Texture)>,
+	progress: LoadProgress,
+}
+
+impl Loader {
+	/// The arguments work like the ones to TextureHandler::new().
+	pub fn new(dvr: &Dvr, names: &[&str], name_transform: impl Fn(&str) -> String) -> Result<Loader, String> {
+		let pending: VecDeque<(String, String)> = names
+			.iter()
+			.map(|name| (name.to_string(), name_transform(name)))
+			.collect();
+		Ok(Loader {
+			factory: dvr.texture_factory(),
+			progress: LoadProgress::new(pending.len()),
+			pending,
+			textures: Vec::new(),
+		})
+	}
+
+	/// Loads textures for a short while and returns the progress.
+	pub fn update(&mut self) -> &LoadProgress {
+		let start = Instant::now();
+		while let Some((name, filename)) = self.pending.pop_front() {
+			let res = std::fs::read(&filename)
+				.map_err(|e| format!("Failed to read image file \"{}\" ({})", filename, e))
+				.and_then(|data| Ok((self.factory.load_texture_raw(&data)?, data.len())));
+			match res {
+				Ok((texture, bytes)) => {
+					self.textures.push((name, filename, texture));
+					self.progress.done += 1;
+					self.progress.bytes_loaded += bytes as u64;
+				},
+				Err(e) => {
+					self.progress.failed.push((name, e));
+				},
+			}
+			if start.elapsed() >= UPDATE_TIME {
+				break;
+			}
+		}
+		&self.progress
+	}
+
+	pub fn progress(&self) -> &LoadProgress {
+		&self.progress
+	}
+
+	/// Returns a texture handler with the loaded textures. This fails if the loading is not
+	/// finished or if any texture failed to load.
+	pub fn finish(self) -> Result<TextureHandler, String> {
+		if !self.progress.is_finished() {
+			return Err("Loading is not finished".to_string());
+		}
+		self.progress.check_failures()?;
+		Ok(TextureHandler::from_textures(self.textures))
+	}
+}
@@ -0,0 +1,20 @@
+/// Where a texture, or a part of it, is drawn by Dvr::draw_tinted() and the other ways
+/// of drawing that take more than Dvr::draw(). The fields are the arguments of Dvr::draw().
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Quad {
+	/// The centre of the quad.
+	pub x: f32,
+	pub y: f32,
+	pub size: Option<(f32, f32)>,
+	/// The part of the texture that is drawn (position and size, in pixels), or None for
+	/// the whole texture.
+	pub tex_pos_size: Option<((f32, f32), (f32, f32))>,
+	/// The rotation around the centre, in radians.
+	pub angle: f32,
+}
+
+impl Quad {
+	pub fn new(x: f32, y: f32, size: Option<(f32, f32)>, tex_pos_size: Option<((f32, f32), (f32, f32))>, angle: f32) -> Quad {
+		Quad { x, y, size, tex_pos_size, angle }
+	}
+}
@@ -0,0 +1,148 @@
+use std::{cell::Cell, rc::Rc};
+
+/// The GPU resources that a Dvr and its textures are currently holding, and an estimate
+/// of how much memory they use. Get it with Dvr::resource_stats(). If the numbers keep
+/// growing while the same things are loaded and unloaded, something is leaking.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct ResourceStats {
+	pub textures: usize,
+	pub buffers: usize,
+	/// Shader programs. On Windows a vertex and pixel shader pair counts as one program.
+	pub programs: usize,
+	/// The estimated memory used by textures, in bytes.
+	pub texture_memory: usize,
+	/// The memory used by buffers, in bytes.
+	pub buffer_memory: usize,
+}
+
+impl ResourceStats {
+	/// The estimated memory used by all resources, in bytes.
+	pub fn memory(&self) -> usize {
+		self.texture_memory + self.buffer_memory
+	}
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum ResourceKind {
+	Texture,
+	Buffer,
+	Program,
+}
+
+/// Keeps count of the live resources. Clones share the same counts.
+#[derive(Clone, Default)]
+pub(crate) struct ResourceTracker {
+	stats: Rc<Cell<ResourceStats>>,
+}
+
+impl ResourceTracker {
+	pub fn stats(&self) -> ResourceStats {
+		self.stats.get()
+	}
+
+	/// Counts a new resource. It is counted until the returned value is dropped,
+	/// so the value should be stored next to the resource.
+	pub fn track(&self, kind: ResourceKind, memory: usize) -> TrackedResource {
+		self.update(kind, |count, total| {
+			*count += 1;
+			*total += memory;
+		});
+		TrackedResource {
+			tracker: self.clone(),
+			kind,
+			memory,
+		}
+	}
+
+	fn update(&self, kind: ResourceKind, f: impl FnOnce(&mut usize, &mut usize)) {
+		let mut stats = self.stats.get();
+		match kind {
+			ResourceKind::Texture => f(&mut stats.textures, &mut stats.texture_memory),
+			ResourceKind::Buffer => f(&mut stats.buffers, &mut stats.buffer_memory),
+			ResourceKind::Program => f(&mut stats.programs, &mut 0),
+		}
+		self.stats.set(stats);
+	}
+}
+
+/// A resource that is counted by a ResourceTracker.
+pub(crate) struct TrackedResource {
+	tracker: ResourceTracker,
+	kind: ResourceKind,
+	memory: usize,
+}
+
+impl TrackedResource {
+	pub fn memory(&self) -> usize {
+		self.memory
+	}
+}
+
+impl Drop for TrackedResource {
+	fn drop(&mut self) {
+		let memory = self.memory;
+		self.tracker.update(self.kind, |count, total| {
+			*count -= 1;
+			*total -= memory;
+		});
+	}
+}
+
+/// Estimates the memory used by an RGBA texture with 8 bits per channel.
+pub(crate) fn texture_memory(width: u32, height: u32, mipmaps: bool) -> usize {
+	let base = width as usize * height as usize * 4;
+	// A full mipmap chain adds about a third
+	match mipmaps {
+		true => base + base / 3,
+		false => base,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn counts_until_dropped() {
+		let tracker = ResourceTracker::default();
+		let texture = tracker.track(ResourceKind::Texture, 1000);
+		let buffer = tracker.track(ResourceKind::Buffer, 64);
+		let program = tracker.track(ResourceKind::Program, 0);
+		assert_eq!(tracker.stats(), ResourceStats {
+			textures: 1,
+			buffers: 1,
+			programs: 1,
+			texture_memory: 1000,
+			buffer_memory: 64,
+		});
+		assert_eq!(tracker.stats().memory(), 1064);
+		assert_eq!(texture.memory(), 1000);
+		drop(texture);
+		assert_eq!(tracker.stats().textures, 0);
+		assert_eq!(tracker.stats().texture_memory, 0);
+		drop((buffer, program));
+		assert_eq!(tracker.stats(), ResourceStats::default());
+	}
+
+	#[test]
+	fn clones_share_counts() {
+		let tracker = ResourceTracker::default();
+		let clone = tracker.clone();
+		let textures: Vec<TrackedResource> = (1..=10)
+			.map(|i| clone.track(ResourceKind::Texture, i * 100))
+			.collect();
+		assert_eq!(tracker.stats().textures, 10);
+		assert_eq!(tracker.stats().texture_memory, 5500);
+		// Resources that outlive the tracker they came from still count down
+		drop(tracker);
+		drop(textures);
+		assert_eq!(clone.stats(), ResourceStats::default());
+	}
+
+	#[test]
+	fn texture_memory_estimate() {
+		assert_eq!(texture_memory(16, 8, false), 512);
+		assert_eq!(texture_memory(16, 8, true), 682);
+		assert_eq!(texture_memory(0, 100, true), 0);
+	}
+}
@@ -0,0 +1,83 @@
+use crate::effects::TextEffects;
+
+/// How a distance field texture stores the distance to the edges of the shapes in it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DistanceFieldKind {
+	/// A single channel signed distance field. The distance is read from the red channel
+	/// or the alpha channel, whichever is smaller, so both grayscale textures and white
+	/// textures with the distance in the alpha channel work.
+	Sdf,
+	/// A multi-channel signed distance field (as generated by msdfgen), where the distance
+	/// is the median of the red, green and blue channels. This keeps corners sharp.
+	Msdf,
+}
+
+/// Describes a distance field texture, where 0.5 is the edge of the shapes and larger
+/// values are inside them. Distance field textures can be drawn at any size without
+/// getting blurry (see Dvr::draw_sdf()).
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct DistanceField {
+	pub kind: DistanceFieldKind,
+	/// The distance in texture pixels between the values 0 and 1 in the field. This is
+	/// the distance range (or pixel range) that the field was generated with.
+	pub range: f32,
+}
+
+/// How a distance field is drawn. Distances are in texture pixels, so they scale with
+/// what is drawn. Outlines and shadows can only reach as far from the shapes as the field
+/// does (half of its range) and as the padding around the shapes in the texture.
+///
+/// This is the part of TextEffects that distance fields had before there was a glow.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SdfStyle {
+	/// The colour inside the shapes (red, green, blue and alpha, from 0 to 1).
+	pub colour: (f32, f32, f32, f32),
+	pub outline_colour: (f32, f32, f32, f32),
+	/// The width of the outline around the shapes, or 0 for no outline.
+	pub outline_width: f32,
+	/// The colour of the shadow, which has no effect if the alpha is 0.
+	pub shadow_colour: (f32, f32, f32, f32),
+	/// How far the shadow is moved, where y goes up like in Dvr coordinates.
+	pub shadow_offset: (f32, f32),
+	/// How far the edge of the shadow is blurred.
+	pub shadow_softness: f32,
+}
+
+impl Default for SdfStyle {
+	fn default() -> Self {
+		SdfStyle::from_effects(&TextEffects::default())
+	}
+}
+
+impl SdfStyle {
+	/// The style with the colours multiplied by colour. The outline and shadow only get
+	/// the alpha, so that e.g. coloured text keeps a black outline but still fades out.
+	pub fn tinted(&self, colour: (f32, f32, f32, f32)) -> SdfStyle {
+		SdfStyle::from_effects(&self.with_glow(&TextEffects::default()).tinted(colour))
+	}
+
+	/// The style of effects, without the glow.
+	pub(crate) fn from_effects(effects: &TextEffects) -> SdfStyle {
+		SdfStyle {
+			colour: effects.colour,
+			outline_colour: effects.outline_colour,
+			outline_width: effects.outline_width,
+			shadow_colour: effects.shadow_colour,
+			shadow_offset: effects.shadow_offset,
+			shadow_softness: effects.shadow_blur,
+		}
+	}
+
+	/// The effects of the style, with the glow of effects.
+	pub(crate) fn with_glow(&self, effects: &TextEffects) -> TextEffects {
+		TextEffects {
+			colour: self.colour,
+			outline_colour: self.outline_colour,
+			outline_width: self.outline_width,
+			shadow_colour: self.shadow_colour,
+			shadow_offset: self.shadow_offset,
+			shadow_blur: self.shadow_softness,
+			..*effects
+		}
+	}
+}
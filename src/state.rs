@@ -13,6 +13,12 @@ pub use win_state::*;
 pub trait State<Glob> {
 	fn logic(&mut self, glob: &mut Glob) -> Result<LogicStatus<Glob>, String>;
 	fn draw(&self, dvr: &Dvr, glob: &Glob) -> Result<(), String>;
+
+	/// Called when the rendering context has been lost and then restored. By the time
+	/// this is called the Dvr has already recreated its own resources and textures.
+	fn context_restored(&mut self, _dvr: &Dvr, _glob: &mut Glob) -> Result<(), String> {
+		Ok(())
+	}
 }
 
 pub enum LogicStatus<Glob> {
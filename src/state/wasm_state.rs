@@ -32,6 +32,15 @@ impl<Glob: 'static> StateHandler<Glob> {
 			let res =  || -> Result<(), JsValue> {
 				let state_handler = state_handler_option.as_mut()
 					.expect("State handler callback called without state handler existing");
+				if state_handler.dvr.restore_if_needed()? {
+					if let Some(state) = state_handler.state.as_mut() {
+						state.context_restored(&state_handler.dvr, &mut state_handler.glob)?;
+					}
+				}
+				// Everything is paused while the context is lost
+				if state_handler.dvr.is_context_lost() {
+					return Ok(());
+				}
 				let mut state;
 				loop {
 					state = match state_handler.state.as_mut() {
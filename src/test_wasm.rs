@@ -2,9 +2,10 @@ use core::f32;
 use crate::{interface::Interface, *};
 use crate::font::FontSheet;
 use js_sys::Math::random;
-use crate::input::{Event, Input};
+use crate::input::Input;
 use wasm_bindgen::prelude::*;
 use crate::state::{LogicStatus, State, StateHandler};
+use crate::text_field::TextField;
 
 struct TestState {
     a: f32,
@@ -12,13 +13,15 @@ struct TestState {
     c: f32,
     font: font::Font,
     inp: Input,
-    s: String,
+    field: TextField,
     th: TextureHandler,
 }
 
 impl TestState {
     pub fn new(dvr: &Dvr, th: TextureHandler) -> Result<TestState, String> {
         let mut th = th;
+        let mut field = TextField::new(dvr, -300.0, 50.0, 600.0, 100.0)?;
+        field.set_focused(true);
         Ok(TestState {
             a: 0.0,
             b: 0.0,
@@ -56,7 +59,7 @@ impl TestState {
                 '?'
             ),
             inp: Input::new(&dvr, None)?,
-            s: String::new(),
+            field,
             th,
         })
     }
@@ -83,18 +86,9 @@ impl State<()> for TestState {
             }))
         }
         for e in &self.inp {
-            match e {
-                Event::Char(chars) => {
-                    self.s += &chars;
-                },
-                Event::KeyDown(e) => {
-                    if e.key_code == "Backspace" {
-                        self.s.pop();
-                    }
-                },
-                _ => {}
-            }
+            self.field.handle_event(&self.font, &e)?;
         }
+        self.inp.set_text_area(self.field.get_text_area())?;
         Ok(LogicStatus::Continue)
     }
 
@@ -138,30 +132,24 @@ impl State<()> for TestState {
             "AbcgÅä¤öe",
             0.0,
             0.0,
-            Some(100.0),
             100.0,
-            font::Align::Centre,
-            font::VAlign::Centre
+            &font::TextOptions::new(Some(100.0), font::Align::Centre, font::VAlign::Centre)
         );
         let _ = self.font.draw_text(
             &dvr,
             "Abcg åä¤öe\ndreagy\n!&(/|si",
             dvr.get_screen_width() as f32 * -0.5,
             dvr.get_screen_height() as f32 * 0.5,
-            None,
             100.0,
-            font::Align::Left,
-            font::VAlign::Top
+            &font::TextOptions::new(None, font::Align::Left, font::VAlign::Top)
         );
         let _ = self.font.draw_text(
             &dvr,
             "Abcgåä¤öe\ndÅreagn\n!&(/|si",
             dvr.get_screen_width() as f32 * 0.5,
             dvr.get_screen_height() as f32 * -0.5,
-            None,
             100.0,
-            font::Align::Right,
-            font::VAlign::Bottom
+            &font::TextOptions::new(None, font::Align::Right, font::VAlign::Bottom)
         );
         let _ = dvr.draw(
             &tex,
@@ -179,16 +167,7 @@ impl State<()> for TestState {
             Some(((25.0, 25.0), (50.0, 50.0))),
             0.0
         );
-        let _ = self.font.draw_text(
-            &dvr,
-            &self.s,
-            0.0,
-            0.0,
-            None,
-            100.0,
-            font::Align::Centre,
-            font::VAlign::Centre
-        );
+        let _ = self.field.draw(&dvr, &self.font);
         if let Some((x, y)) = self.inp.get_mouse_pos() {
             let (x, y) = dvr.native_mouse_coords_to_dvr((x, y));
             dvr.draw(
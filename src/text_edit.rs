@@ -0,0 +1,95 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+// Helpers for editing text one grapheme cluster (what the user sees as one character, e.g.
+// "e" with a combining accent or an emoji sequence) or word at a time. Indices are byte
+// indices into the text and are expected to be at the start of a cluster.
+
+/// The index after the grapheme cluster at index.
+pub fn next_grapheme(text: &str, index: usize) -> usize {
+	text[index..]
+		.graphemes(true)
+		.next()
+		.map(|cluster| index + cluster.len())
+		.unwrap_or(text.len())
+}
+
+/// The index of the grapheme cluster before index.
+pub fn prev_grapheme(text: &str, index: usize) -> usize {
+	text[..index]
+		.graphemes(true)
+		.next_back()
+		.map(|cluster| index - cluster.len())
+		.unwrap_or(0)
+}
+
+/// Removes the last grapheme cluster of the text and returns it. This is what a backspace
+/// key should do, unlike String::pop(), which may remove only a part of the last character.
+pub fn pop_grapheme(text: &mut String) -> Option<String> {
+	let start = text.grapheme_indices(true).next_back()?.0;
+	Some(text.split_off(start))
+}
+
+/// The start of the next word after index, or the end of the text.
+pub fn next_word(text: &str, index: usize) -> usize {
+	text.split_word_bound_indices()
+		.find(|(start, segment)| *start > index && is_word(segment))
+		.map(|(start, _)| start)
+		.unwrap_or(text.len())
+}
+
+/// The start of the word before index (or of the word that index is in), or 0.
+pub fn prev_word(text: &str, index: usize) -> usize {
+	text.split_word_bound_indices()
+		.take_while(|(start, _)| *start < index)
+		.filter(|(_, segment)| is_word(segment))
+		.last()
+		.map(|(start, _)| start)
+		.unwrap_or(0)
+}
+
+fn is_word(segment: &str) -> bool {
+	segment.chars().any(|c| c.is_alphanumeric())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// e with a combining acute, a family emoji joined with zero width joiners and the
+	// French flag (two regional indicators)
+	const ACCENT: &str = "e\u{301}";
+	const FAMILY: &str = "\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}";
+	const FLAG: &str = "\u{1f1eb}\u{1f1f7}";
+
+	#[test]
+	fn moves_over_whole_clusters() {
+		let text = format!("a{}{}{}b", ACCENT, FAMILY, FLAG);
+		let starts = [0, 1, 1 + ACCENT.len(), 1 + ACCENT.len() + FAMILY.len(), text.len() - 1, text.len()];
+		for pair in starts.windows(2) {
+			assert_eq!(next_grapheme(&text, pair[0]), pair[1]);
+			assert_eq!(prev_grapheme(&text, pair[1]), pair[0]);
+		}
+		assert_eq!(next_grapheme(&text, text.len()), text.len());
+		assert_eq!(prev_grapheme(&text, 0), 0);
+	}
+
+	#[test]
+	fn flags_are_pairs_of_regional_indicators() {
+		// Three regional indicators are a flag and a lone indicator
+		let text = format!("{}\u{1f1e9}", FLAG);
+		assert_eq!(next_grapheme(&text, 0), FLAG.len());
+		assert_eq!(prev_grapheme(&text, text.len()), FLAG.len());
+		assert_eq!(next_grapheme(&text, FLAG.len()), text.len());
+	}
+
+	#[test]
+	fn pops_whole_clusters() {
+		let mut text = format!("a{}{}{}", ACCENT, FAMILY, FLAG);
+		assert_eq!(pop_grapheme(&mut text).as_deref(), Some(FLAG));
+		assert_eq!(pop_grapheme(&mut text).as_deref(), Some(FAMILY));
+		assert_eq!(pop_grapheme(&mut text).as_deref(), Some(ACCENT));
+		assert_eq!(pop_grapheme(&mut text).as_deref(), Some("a"));
+		assert_eq!(pop_grapheme(&mut text), None);
+		assert_eq!(text, "");
+	}
+}
@@ -0,0 +1,434 @@
+use std::ops::Range;
+
+use crate::{effects::EffectPass, font::{Font, PositionedGlyph, TextOptions}, image::Image, input::Event, quad::Quad, text_edit, Dvr, Texture};
+
+/// A single line of editable text. Feed it the events from Input with handle_event() and the
+/// mouse with mouse_down(), mouse_drag() and mouse_up(), and draw it with draw(). It has a caret, selection (with Shift and the mouse), keyboard navigation
+/// (Ctrl moves by words), undo and redo, an optional length limit and scrolls horizontally
+/// when the text does not fit. On the web, input methods can be used when get_text_area() is
+/// passed to Input::set_text_area() every frame, and their text is inserted like typed text.
+///
+/// The font is not stored in the field, so the same font has to be passed to every method.
+pub struct TextField {
+	text: String,
+	/// The byte index of the caret.
+	caret: usize,
+	/// The other end of the selection. Nothing is selected when this is the caret.
+	anchor: usize,
+	max_chars: Option<usize>,
+	/// The left edge and the top of the field.
+	x: f32,
+	y: f32,
+	w: f32,
+	/// The height of the text.
+	h: f32,
+	/// How far the text is scrolled to the left.
+	scroll: f32,
+	focused: bool,
+	dragging: bool,
+	/// Whether Ctrl is held, so that shortcuts are not also typed as characters.
+	ctrl_down: bool,
+	undo: Vec<Snapshot>,
+	redo: Vec<Snapshot>,
+	last_edit: Option<EditKind>,
+	text_colour: (f32, f32, f32, f32),
+	selection_colour: (f32, f32, f32, f32),
+	caret_colour: (f32, f32, f32, f32),
+	/// A white pixel that the caret and the selection are drawn with.
+	pixel: Texture,
+}
+
+#[derive(Clone)]
+struct Snapshot {
+	text: String,
+	caret: usize,
+	anchor: usize,
+}
+
+/// Edits of the same kind that follow each other are undone together.
+#[derive(Clone, Copy, PartialEq)]
+enum EditKind {
+	Typing,
+	Deleting,
+	Other,
+}
+
+const MAX_UNDO: usize = 100;
+
+impl TextField {
+	/// Creates an empty field. (x, y) is its top left corner, w is its width and h is the
+	/// height of the text.
+	pub fn new(dvr: &Dvr, x: f32, y: f32, w: f32, h: f32) -> Result<TextField, String> {
+		Ok(TextField {
+			text: String::new(),
+			caret: 0,
+			anchor: 0,
+			max_chars: None,
+			x,
+			y,
+			w,
+			h,
+			scroll: 0.0,
+			focused: false,
+			dragging: false,
+			ctrl_down: false,
+			undo: Vec::new(),
+			redo: Vec::new(),
+			last_edit: None,
+			text_colour: (1.0, 1.0, 1.0, 1.0),
+			selection_colour: (0.2, 0.4, 0.9, 0.6),
+			caret_colour: (1.0, 1.0, 1.0, 1.0),
+			pixel: dvr.load_texture_image(Image::new(1, 1, vec![255; 4])?)?,
+		})
+	}
+
+	pub fn get_text(&self) -> &str {
+		&self.text
+	}
+
+	/// Replaces the text, which can be undone. Line breaks are removed, and the text is cut
+	/// off if it is longer than the limit.
+	pub fn set_text(&mut self, text: &str) {
+		self.save_undo(EditKind::Other);
+		self.text.clear();
+		self.caret = 0;
+		self.anchor = 0;
+		self.scroll = 0.0;
+		self.insert(text);
+	}
+
+	/// Sets the maximum number of characters, or None for no limit. Text that is too long
+	/// already is not cut off.
+	pub fn set_max_chars(&mut self, max_chars: Option<usize>) {
+		self.max_chars = max_chars;
+	}
+
+	pub fn set_position(&mut self, x: f32, y: f32) {
+		self.x = x;
+		self.y = y;
+	}
+
+	pub fn set_size(&mut self, w: f32, h: f32) {
+		self.w = w;
+		self.h = h;
+	}
+
+	pub fn is_focused(&self) -> bool {
+		self.focused
+	}
+
+	/// Only a focused field reacts to keys and shows the caret. Clicking the field focuses
+	/// it and clicking somewhere else unfocuses it.
+	pub fn set_focused(&mut self, focused: bool) {
+		self.focused = focused;
+		self.dragging &= focused;
+	}
+
+	/// Returns the field as (x, y, w, h) while it is focused, for Input::set_text_area().
+	pub fn get_text_area(&self) -> Option<(f32, f32, f32, f32)> {
+		self.focused.then_some((self.x, self.y, self.w, self.h))
+	}
+
+	pub fn set_colours(&mut self, text: (f32, f32, f32, f32), selection: (f32, f32, f32, f32), caret: (f32, f32, f32, f32)) {
+		self.text_colour = text;
+		self.selection_colour = selection;
+		self.caret_colour = caret;
+	}
+
+	/// The byte index of the caret.
+	pub fn get_caret(&self) -> usize {
+		self.caret
+	}
+
+	/// The selected bytes of the text, which is empty if nothing is selected.
+	pub fn get_selection(&self) -> Range<usize> {
+		self.caret.min(self.anchor)..self.caret.max(self.anchor)
+	}
+
+	pub fn get_selected_text(&self) -> &str {
+		&self.text[self.get_selection()]
+	}
+
+	/// Selects a range of bytes, with the caret at the end. The range is clamped to the text.
+	pub fn select(&mut self, range: Range<usize>) {
+		self.anchor = self.clamp(range.start);
+		self.caret = self.clamp(range.end);
+		self.last_edit = None;
+	}
+
+	/// Handles a key or character event from Input. Returns true if the text was changed.
+	/// Mouse events are not handled here, since their coordinates have to be converted with
+	/// Dvr::native_mouse_coords_to_dvr() first (see mouse_down()).
+	pub fn handle_event(&mut self, font: &Font, event: &Event) -> Result<bool, String> {
+		let changed = match event {
+			Event::KeyDown(e) => {
+				self.ctrl_down = e.ctrl_down && !e.alt_down;
+				if !self.focused {
+					return Ok(false);
+				}
+				self.handle_key(&e.key_code, self.ctrl_down, e.shift_down)
+			},
+			Event::KeyUp(e) => {
+				self.ctrl_down = e.ctrl_down && !e.alt_down;
+				false
+			},
+			Event::Char(chars) => {
+				if !self.focused || self.ctrl_down {
+					return Ok(false);
+				}
+				let chars: String = chars.chars().filter(|c| !c.is_control()).collect();
+				if chars.is_empty() {
+					return Ok(false);
+				}
+				self.save_undo(EditKind::Typing);
+				self.insert(&chars)
+			},
+			_ => return Ok(false),
+		};
+		self.scroll_to_caret(font)?;
+		Ok(changed)
+	}
+
+	/// Places the caret where the mouse was pressed (or extends the selection if shift is
+	/// held) and starts selecting with the mouse. Pos is in the coordinates of the Dvr.
+	/// Clicking outside the field unfocuses it.
+	pub fn mouse_down(&mut self, font: &Font, (x, y): (f32, f32), shift: bool) -> Result<(), String> {
+		let inside = x >= self.x && x <= self.x + self.w && y <= self.y && y >= self.y - self.h;
+		self.set_focused(inside);
+		if !inside {
+			return Ok(());
+		}
+		self.dragging = true;
+		self.caret = self.hit_test(font, (x, y))?;
+		if !shift {
+			self.anchor = self.caret;
+		}
+		self.last_edit = None;
+		self.scroll_to_caret(font)
+	}
+
+	/// Moves the end of the selection to the mouse while the mouse button is held. Call this
+	/// every frame with the position of the mouse (e.g. from Input::get_mouse_pos()), and the
+	/// text scrolls when the mouse is outside the field.
+	pub fn mouse_drag(&mut self, font: &Font, pos: (f32, f32)) -> Result<(), String> {
+		if !self.dragging {
+			return Ok(());
+		}
+		self.caret = self.hit_test(font, pos)?;
+		self.scroll_to_caret(font)
+	}
+
+	pub fn mouse_up(&mut self) {
+		self.dragging = false;
+	}
+
+	pub fn undo(&mut self) -> bool {
+		let Some(snapshot) = self.undo.pop() else {
+			return false;
+		};
+		self.redo.push(self.snapshot());
+		self.restore(snapshot);
+		true
+	}
+
+	pub fn redo(&mut self) -> bool {
+		let Some(snapshot) = self.redo.pop() else {
+			return false;
+		};
+		self.undo.push(self.snapshot());
+		self.restore(snapshot);
+		true
+	}
+
+	pub fn draw(&self, dvr: &Dvr, font: &Font) -> Result<(), String> {
+		let left = self.x - self.scroll;
+		let right = self.x + self.w;
+		let layout = font.layout_text(dvr, &self.text, left, self.y, self.h, &TextOptions::default())?;
+
+		if self.focused {
+			for ((rx, ry), (rw, rh)) in layout.selection_rects(self.get_selection()) {
+				let (rx0, rx1) = (rx.max(self.x), (rx + rw).min(right));
+				if rx1 > rx0 {
+					dvr.draw_tinted(&self.pixel, &Quad::new(0.5 * (rx0 + rx1), ry - 0.5 * rh, Some((rx1 - rx0, rh)), None, 0.0), self.selection_colour)?;
+				}
+			}
+		}
+
+		// Glyphs are cut off at the edges of the field, and their effects are drawn before them
+		let effects = font.get_effects();
+		for pass in EffectPass::for_text(effects) {
+			for glyph in layout.get_glyphs() {
+				let (gw, gh) = glyph.size;
+				let (gx0, gx1) = (glyph.pos.0 - 0.5 * gw, glyph.pos.0 + 0.5 * gw);
+				let (x0, x1) = (gx0.max(self.x), gx1.min(right));
+				if x1 <= x0 {
+					continue;
+				}
+				let ((tx, ty), (tw, th)) = glyph.tex_rect;
+				let clipped = PositionedGlyph {
+					pos: (0.5 * (x0 + x1), glyph.pos.1),
+					size: (x1 - x0, gh),
+					tex_rect: ((tx + tw * (x0 - gx0) / gw, ty), (tw * (x1 - x0) / gw, th)),
+					..*glyph
+				};
+				clipped.draw_pass(dvr, self.text_colour, effects, *pass)?;
+			}
+		}
+
+		if self.focused {
+			let ((cx, cy), ch) = layout.caret_position(self.caret);
+			let cw = (0.06 * self.h).max(1.0);
+			if cx >= self.x - cw && cx <= right + cw {
+				dvr.draw_tinted(&self.pixel, &Quad::new(cx, cy - 0.5 * ch, Some((cw, ch)), None, 0.0), self.caret_colour)?;
+			}
+		}
+		Ok(())
+	}
+
+	/// Returns true if the text was changed.
+	fn handle_key(&mut self, key_code: &str, ctrl: bool, shift: bool) -> bool {
+		match key_code {
+			"ArrowLeft" | "ArrowRight" | "Home" | "End" => {
+				let forward = matches!(key_code, "ArrowRight" | "End");
+				let selection = self.get_selection();
+				self.caret = match key_code {
+					"Home" => 0,
+					"End" => self.text.len(),
+					// Without shift, the arrows go to the edge of the selection
+					_ if !shift && !selection.is_empty() => match forward {
+						true => selection.end,
+						false => selection.start,
+					},
+					_ if ctrl => self.word_boundary(self.caret, forward),
+					_ => self.grapheme_boundary(self.caret, forward),
+				};
+				if !shift {
+					self.anchor = self.caret;
+				}
+				self.last_edit = None;
+				false
+			},
+			"Backspace" | "Delete" => {
+				if self.get_selection().is_empty() {
+					let forward = key_code == "Delete";
+					self.caret = match ctrl {
+						true => self.word_boundary(self.caret, forward),
+						false => self.grapheme_boundary(self.caret, forward),
+					};
+				}
+				if self.get_selection().is_empty() {
+					return false;
+				}
+				self.save_undo(EditKind::Deleting);
+				self.insert("")
+			},
+			"KeyA" if ctrl => {
+				self.select(0..self.text.len());
+				false
+			},
+			"KeyZ" if ctrl && shift => self.redo(),
+			"KeyZ" if ctrl => self.undo(),
+			"KeyY" if ctrl => self.redo(),
+			_ => false,
+		}
+	}
+
+	/// Replaces the selection with text, as much of it as the limit allows, and puts the
+	/// caret after it. Returns true if the text was changed.
+	fn insert(&mut self, text: &str) -> bool {
+		let selection = self.get_selection();
+		let text: String = text.chars().filter(|c| *c != '\n' && *c != '\r').collect();
+		let text = match self.max_chars {
+			Some(max_chars) => {
+				let kept = self.text.chars().count() - self.text[selection.clone()].chars().count();
+				let room = max_chars.saturating_sub(kept);
+				match text.char_indices().nth(room) {
+					Some((end, _)) => text[..end].to_string(),
+					None => text,
+				}
+			},
+			None => text,
+		};
+		if selection.is_empty() && text.is_empty() {
+			return false;
+		}
+		self.text.replace_range(selection.clone(), &text);
+		self.caret = selection.start + text.len();
+		self.anchor = self.caret;
+		true
+	}
+
+	/// Saves the text so that the next edit can be undone. Edits of the same kind in a row
+	/// (e.g. typing a word) are undone together.
+	fn save_undo(&mut self, kind: EditKind) {
+		self.redo.clear();
+		if kind != EditKind::Other && self.last_edit == Some(kind) {
+			return;
+		}
+		self.last_edit = Some(kind);
+		self.undo.push(self.snapshot());
+		if self.undo.len() > MAX_UNDO {
+			self.undo.remove(0);
+		}
+	}
+
+	fn snapshot(&self) -> Snapshot {
+		Snapshot {
+			text: self.text.clone(),
+			caret: self.caret,
+			anchor: self.anchor,
+		}
+	}
+
+	fn restore(&mut self, snapshot: Snapshot) {
+		self.text = snapshot.text;
+		self.caret = snapshot.caret;
+		self.anchor = snapshot.anchor;
+		self.last_edit = None;
+	}
+
+	fn clamp(&self, index: usize) -> usize {
+		let mut index = index.min(self.text.len());
+		while !self.text.is_char_boundary(index) {
+			index -= 1;
+		}
+		index
+	}
+
+	/// The index of the next or previous grapheme cluster, so that e.g. an accent that is
+	/// a separate code point is never split from its letter.
+	fn grapheme_boundary(&self, index: usize, forward: bool) -> usize {
+		match forward {
+			true => text_edit::next_grapheme(&self.text, index),
+			false => text_edit::prev_grapheme(&self.text, index),
+		}
+	}
+
+	/// The start of the next or previous word.
+	fn word_boundary(&self, index: usize, forward: bool) -> usize {
+		match forward {
+			true => text_edit::next_word(&self.text, index),
+			false => text_edit::prev_word(&self.text, index),
+		}
+	}
+
+	/// The text is a single line, so only x matters.
+	fn hit_test(&self, font: &Font, (x, _): (f32, f32)) -> Result<usize, String> {
+		let layout = font.measure_text(&self.text, self.x - self.scroll, self.y, self.h, &TextOptions::default())?;
+		Ok(layout.hit_test(x, self.y - 0.5 * self.h))
+	}
+
+	/// Scrolls so that the caret is inside the field, without scrolling past the end of the text.
+	fn scroll_to_caret(&mut self, font: &Font) -> Result<(), String> {
+		let layout = font.measure_text(&self.text, 0.0, self.y, self.h, &TextOptions::default())?;
+		let ((caret_x, _), _) = layout.caret_position(self.caret);
+		let text_w = layout.get_lines()[0].width;
+		self.scroll = self.scroll.min(text_w - self.w).max(0.0);
+		if caret_x < self.scroll {
+			self.scroll = caret_x;
+		} else if caret_x > self.scroll + self.w {
+			self.scroll = caret_x - self.w;
+		}
+		Ok(())
+	}
+}
@@ -0,0 +1,404 @@
+use crate::image::Image;
+
+/// A TrueType or OpenType font. The glyphs are rasterized in pure Rust (without hinting),
+/// so they look the same on all platforms. Use font::FontSheet::from_ttf() to draw text with it.
+#[derive(Clone)]
+pub struct TtfFont {
+	font: fontdue::Font,
+}
+
+/// The vertical metrics of a font at a pixel size.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct LineMetrics {
+	/// The distance from the baseline to the top of the highest glyphs.
+	pub ascent: f32,
+	/// The distance from the baseline to the bottom of the lowest glyphs. This is positive.
+	pub descent: f32,
+	/// The extra space the font wants between lines.
+	pub line_gap: f32,
+}
+
+impl LineMetrics {
+	/// The distance between the tops of two lines.
+	pub fn line_height(&self) -> f32 {
+		self.ascent + self.descent + self.line_gap
+	}
+}
+
+/// A rasterized glyph. The coverage has one byte per pixel, from top to bottom.
+pub struct RasterizedGlyph {
+	pub width: u32,
+	pub height: u32,
+	pub coverage: Vec<u8>,
+	/// The distance from the cursor to the left edge of the glyph.
+	pub left: f32,
+	/// The distance from the baseline up to the top edge of the glyph.
+	pub top: f32,
+	/// How far the cursor moves after the glyph.
+	pub advance: f32,
+}
+
+impl TtfFont {
+	pub fn parse(data: &[u8]) -> Result<TtfFont, String> {
+		let font = fontdue::Font::from_bytes(data, fontdue::FontSettings::default())
+			.map_err(|e| format!("Failed to parse font ({})", e))?;
+		Ok(TtfFont { font })
+	}
+
+	pub fn get_line_metrics(&self, px: f32) -> Result<LineMetrics, String> {
+		let metrics = self.font.horizontal_line_metrics(px)
+			.ok_or("The font has no horizontal metrics")?;
+		Ok(LineMetrics {
+			ascent: metrics.ascent,
+			descent: -metrics.descent,
+			line_gap: metrics.line_gap,
+		})
+	}
+
+	pub fn has_glyph(&self, c: char) -> bool {
+		self.font.has_glyph(c)
+	}
+
+	/// How much the cursor should be moved between two characters at a pixel size,
+	/// according to the kerning table of the font.
+	pub fn get_kerning(&self, first: char, second: char, px: f32) -> f32 {
+		self.font.horizontal_kern(first, second, px).unwrap_or(0.0)
+	}
+
+	pub fn rasterize(&self, c: char, px: f32) -> RasterizedGlyph {
+		let (metrics, coverage) = self.font.rasterize(c, px);
+		RasterizedGlyph {
+			width: metrics.width as u32,
+			height: metrics.height as u32,
+			coverage,
+			left: metrics.xmin as f32,
+			top: (metrics.ymin + metrics.height as i32) as f32,
+			advance: metrics.advance_width,
+		}
+	}
+}
+
+/// An RGBA image that glyphs are packed into. The glyphs are white, with the coverage in the
+/// alpha channel. Glyphs are placed on shelves (rows of glyphs with similar heights), and the
+/// atlas doubles in size when it is full.
+pub struct GlyphAtlas {
+	width: u32,
+	height: u32,
+	pixels: Vec<u8>,
+	shelves: Vec<Shelf>,
+	/// Set when the atlas is new or has grown, so all of it has to be uploaded.
+	resized: bool,
+	/// The parts that have changed since the last upload, at most one per shelf.
+	dirty: Vec<AtlasRect>,
+}
+
+/// A rectangle of a GlyphAtlas in pixels.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct AtlasRect {
+	pub x: u32,
+	pub y: u32,
+	pub width: u32,
+	pub height: u32,
+}
+
+/// What has changed in a GlyphAtlas since GlyphAtlas::take_changes() was last called.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum AtlasChanges {
+	None,
+	/// The atlas is new or has grown, so all of it has to be uploaded again.
+	Resized,
+	/// Glyphs have been added in these rectangles.
+	Rects(Vec<AtlasRect>),
+}
+
+struct Shelf {
+	y: u32,
+	height: u32,
+	/// Where the next glyph on the shelf goes.
+	x: u32,
+}
+
+/// Empty pixels between glyphs so that they do not bleed into each other when filtered.
+const PADDING: u32 = 1;
+const MAX_SIZE: u32 = 8192;
+
+impl GlyphAtlas {
+	pub fn new(width: u32, height: u32) -> GlyphAtlas {
+		let (width, height) = (width.max(1), height.max(1));
+		GlyphAtlas {
+			width,
+			height,
+			pixels: vec![0; width as usize * height as usize * 4],
+			shelves: Vec::new(),
+			resized: true,
+			dirty: Vec::new(),
+		}
+	}
+
+	pub fn get_size(&self) -> (u32, u32) {
+		(self.width, self.height)
+	}
+
+	/// Copies a glyph into the atlas and returns the position of its top left corner.
+	/// Coverage has one byte per pixel. Glyphs that are already in the atlas keep their
+	/// positions when it grows.
+	pub fn insert(&mut self, width: u32, height: u32, coverage: &[u8]) -> Result<(u32, u32), String> {
+		if coverage.len() != width as usize * height as usize {
+			return Err("The size of the coverage does not match the size of the glyph".to_string());
+		}
+		if width == 0 || height == 0 {
+			return Ok((0, 0));
+		}
+		let (x, y) = loop {
+			if let Some(pos) = self.allocate(width + PADDING, height + PADDING) {
+				break pos;
+			}
+			if !self.grow() {
+				return Err("The glyph atlas is full".to_string());
+			}
+		};
+		for row in 0..height as usize {
+			for col in 0..width as usize {
+				let i = ((y as usize + row) * self.width as usize + x as usize + col) * 4;
+				self.pixels[i..i + 4].copy_from_slice(&[255, 255, 255, coverage[row * width as usize + col]]);
+			}
+		}
+		if !self.resized {
+			self.add_dirty(AtlasRect { x, y, width, height });
+		}
+		Ok((x, y))
+	}
+
+	/// Returns what has changed since the last call, so that only that has to be uploaded.
+	pub fn take_changes(&mut self) -> AtlasChanges {
+		let dirty = std::mem::take(&mut self.dirty);
+		match std::mem::replace(&mut self.resized, false) {
+			true => AtlasChanges::Resized,
+			false if dirty.is_empty() => AtlasChanges::None,
+			false => AtlasChanges::Rects(dirty),
+		}
+	}
+
+	/// Makes the next call to take_changes() return AtlasChanges::Resized, e.g. because
+	/// the texture that the atlas was uploaded to has lost its contents.
+	pub fn invalidate(&mut self) {
+		self.resized = true;
+		self.dirty.clear();
+	}
+
+	pub fn to_image(&self) -> Image {
+		Image::new(self.width, self.height, self.pixels.clone())
+			.expect("The atlas has the wrong number of pixels")
+	}
+
+	/// Copies a part of the atlas, e.g. one of the rectangles from take_changes().
+	pub fn copy_rect(&self, rect: AtlasRect) -> Result<Image, String> {
+		if rect.x.checked_add(rect.width).is_none_or(|right| right > self.width)
+			|| rect.y.checked_add(rect.height).is_none_or(|bottom| bottom > self.height) {
+			return Err("The rectangle is outside the glyph atlas".to_string());
+		}
+		let mut pixels = Vec::with_capacity(rect.width as usize * rect.height as usize * 4);
+		for row in rect.y..rect.y + rect.height {
+			let start = (row as usize * self.width as usize + rect.x as usize) * 4;
+			pixels.extend_from_slice(&self.pixels[start..start + rect.width as usize * 4]);
+		}
+		Image::new(rect.width, rect.height, pixels)
+	}
+
+	/// Glyphs on the same shelf share a rectangle, so that they are uploaded together.
+	fn add_dirty(&mut self, rect: AtlasRect) {
+		match self.dirty.iter_mut().find(|dirty| dirty.y == rect.y) {
+			Some(dirty) => {
+				let right = (dirty.x + dirty.width).max(rect.x + rect.width);
+				dirty.x = dirty.x.min(rect.x);
+				dirty.width = right - dirty.x;
+				dirty.height = dirty.height.max(rect.height);
+			},
+			None => self.dirty.push(rect),
+		}
+	}
+
+	fn allocate(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+		if width > self.width {
+			return None;
+		}
+		// The lowest shelf that fits wastes the least space
+		let best = self.shelves
+			.iter_mut()
+			.filter(|shelf| shelf.height >= height && shelf.height <= height * 2 && shelf.x + width <= self.width)
+			.min_by_key(|shelf| shelf.height);
+		if let Some(shelf) = best {
+			let pos = (shelf.x, shelf.y);
+			shelf.x += width;
+			return Some(pos);
+		}
+		let y = self.shelves.last().map(|shelf| shelf.y + shelf.height).unwrap_or(0);
+		if y + height > self.height {
+			return None;
+		}
+		self.shelves.push(Shelf {
+			y,
+			height,
+			x: width,
+		});
+		Some((0, y))
+	}
+
+	/// Doubles the width or height. Returns false if the atlas is already at its largest.
+	fn grow(&mut self) -> bool {
+		let Some((width, height)) = grown_size(self.width, self.height) else {
+			return false;
+		};
+		let mut pixels = vec![0; width as usize * height as usize * 4];
+		let old_row = self.width as usize * 4;
+		for (row, old) in self.pixels.chunks_exact(old_row).enumerate() {
+			let start = row * width as usize * 4;
+			pixels[start..start + old_row].copy_from_slice(old);
+		}
+		self.width = width;
+		self.height = height;
+		self.pixels = pixels;
+		self.resized = true;
+		self.dirty.clear();
+		true
+	}
+}
+
+/// The size of an atlas after it has grown once, or None if it would be larger than MAX_SIZE.
+fn grown_size(width: u32, height: u32) -> Option<(u32, u32)> {
+	let (width, height) = match height <= width {
+		true => (width, height * 2),
+		false => (width * 2, height),
+	};
+	(width <= MAX_SIZE && height <= MAX_SIZE).then_some((width, height))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const TEST_FONT: &[u8] = include_bytes!("../test_data/test_font.ttf");
+
+	fn alpha_at(atlas: &GlyphAtlas, x: u32, y: u32) -> u8 {
+		atlas.pixels[(y as usize * atlas.width as usize + x as usize) * 4 + 3]
+	}
+
+	#[test]
+	fn shelf_packing() {
+		let mut atlas = GlyphAtlas::new(64, 64);
+		assert_eq!(atlas.insert(10, 10, &[255; 100]), Ok((0, 0)));
+		assert_eq!(atlas.insert(10, 10, &[128; 100]), Ok((11, 0)));
+		// Too tall for the first shelf, so it starts a new one
+		assert_eq!(atlas.insert(5, 20, &[255; 100]), Ok((0, 11)));
+		// Short enough to go on the first shelf without wasting more than half of it
+		assert_eq!(atlas.insert(6, 6, &[255; 36]), Ok((22, 0)));
+		assert_eq!(atlas.insert(2, 2, &[255; 4]), Ok((0, 32)));
+		assert_eq!(atlas.insert(0, 0, &[]), Ok((0, 0)));
+		assert!(atlas.insert(2, 2, &[255; 3]).is_err());
+		assert_eq!(atlas.get_size(), (64, 64));
+		assert_eq!(alpha_at(&atlas, 9, 9), 255);
+		assert_eq!(alpha_at(&atlas, 10, 9), 0);
+		assert_eq!(alpha_at(&atlas, 11, 0), 128);
+		assert_eq!(&atlas.pixels[..4], &[255, 255, 255, 255]);
+	}
+
+	#[test]
+	fn doubles_and_keeps_glyphs() {
+		let mut atlas = GlyphAtlas::new(16, 16);
+		assert_eq!(atlas.insert(15, 15, &[200; 225]), Ok((0, 0)));
+		assert_eq!(atlas.insert(15, 15, &[100; 225]), Ok((0, 16)));
+		assert_eq!(atlas.get_size(), (16, 32));
+		assert_eq!(atlas.insert(15, 15, &[50; 225]), Ok((16, 0)));
+		assert_eq!(atlas.get_size(), (32, 32));
+		assert_eq!(alpha_at(&atlas, 14, 14), 200);
+		assert_eq!(alpha_at(&atlas, 14, 30), 100);
+		assert_eq!(alpha_at(&atlas, 30, 14), 50);
+		assert_eq!(atlas.to_image().pixels().len(), 32 * 32 * 4);
+	}
+
+	#[test]
+	fn size_limit() {
+		assert_eq!(grown_size(256, 256), Some((256, 512)));
+		assert_eq!(grown_size(256, 512), Some((512, 512)));
+		assert_eq!(grown_size(MAX_SIZE, MAX_SIZE / 2), Some((MAX_SIZE, MAX_SIZE)));
+		assert_eq!(grown_size(MAX_SIZE, MAX_SIZE), None);
+		assert_eq!(grown_size(5000, 6000), None);
+		// A glyph that doesn't fit in an atlas that can't grow
+		let mut atlas = GlyphAtlas::new(MAX_SIZE + 1, 1);
+		assert!(atlas.insert(4, 4, &[255; 16]).is_err());
+		assert_eq!(atlas.get_size(), (MAX_SIZE + 1, 1));
+	}
+
+	#[test]
+	fn changes() {
+		let mut atlas = GlyphAtlas::new(32, 32);
+		assert_eq!(atlas.take_changes(), AtlasChanges::Resized);
+		assert_eq!(atlas.take_changes(), AtlasChanges::None);
+		atlas.insert(4, 4, &[255; 16]).unwrap();
+		atlas.insert(2, 3, &[255; 6]).unwrap();
+		atlas.insert(3, 8, &[255; 24]).unwrap();
+		// The first two glyphs are on the same shelf, so they share a rectangle
+		assert_eq!(atlas.take_changes(), AtlasChanges::Rects(vec![
+			AtlasRect { x: 0, y: 0, width: 7, height: 4 },
+			AtlasRect { x: 0, y: 5, width: 3, height: 8 },
+		]));
+		assert_eq!(atlas.take_changes(), AtlasChanges::None);
+		atlas.insert(32, 4, &[255; 128]).unwrap();
+		assert_eq!(atlas.take_changes(), AtlasChanges::Resized);
+		atlas.insert(1, 1, &[255]).unwrap();
+		atlas.invalidate();
+		assert_eq!(atlas.take_changes(), AtlasChanges::Resized);
+	}
+
+	#[test]
+	fn copy_rect() {
+		let mut atlas = GlyphAtlas::new(8, 8);
+		atlas.insert(2, 2, &[1, 2, 3, 4]).unwrap();
+		let image = atlas.copy_rect(AtlasRect { x: 1, y: 0, width: 2, height: 2 }).unwrap();
+		assert_eq!(image.get_size(), (2, 2));
+		assert_eq!(image.pixels(), &[255, 255, 255, 2, 0, 0, 0, 0, 255, 255, 255, 4, 0, 0, 0, 0]);
+		assert!(atlas.copy_rect(AtlasRect { x: 7, y: 0, width: 2, height: 1 }).is_err());
+		assert!(atlas.copy_rect(AtlasRect { x: 0, y: u32::MAX, width: 1, height: 1 }).is_err());
+	}
+
+	#[test]
+	fn font_metrics() {
+		let font = TtfFont::parse(TEST_FONT).unwrap();
+		let metrics = font.get_line_metrics(100.0).unwrap();
+		assert_eq!(metrics, LineMetrics { ascent: 80.0, descent: 20.0, line_gap: 0.0 });
+		assert_eq!(metrics.line_height(), 100.0);
+		assert!(font.has_glyph('A'));
+		assert!(font.has_glyph(' '));
+		assert!(!font.has_glyph('B'));
+	}
+
+	#[test]
+	fn font_kerning() {
+		let font = TtfFont::parse(TEST_FONT).unwrap();
+		assert_eq!(font.get_kerning('A', 'V', 100.0), -8.0);
+		assert_eq!(font.get_kerning('V', 'A', 100.0), -6.0);
+		assert_eq!(font.get_kerning('V', 'o', 50.0), -2.0);
+		assert_eq!(font.get_kerning('A', 'A', 100.0), 0.0);
+		assert_eq!(font.get_kerning('A', 'B', 100.0), 0.0);
+	}
+
+	#[test]
+	fn rasterize() {
+		let font = TtfFont::parse(TEST_FONT).unwrap();
+		let glyph = font.rasterize('A', 100.0);
+		assert_eq!((glyph.width, glyph.height), (40, 70));
+		assert_eq!((glyph.left, glyph.top, glyph.advance), (10.0, 70.0, 60.0));
+		assert_eq!(glyph.coverage.len(), 40 * 70);
+		assert!(glyph.coverage.iter().all(|&coverage| coverage == 255));
+		let glyph = font.rasterize('o', 100.0);
+		assert_eq!((glyph.width, glyph.height, glyph.advance), (30, 40, 50.0));
+		let glyph = font.rasterize(' ', 100.0);
+		assert_eq!((glyph.width, glyph.height, glyph.advance), (0, 0, 25.0));
+	}
+
+	#[test]
+	fn invalid_font() {
+		assert!(TtfFont::parse(&[]).is_err());
+		assert!(TtfFont::parse(&TEST_FONT[..100]).is_err());
+	}
+}
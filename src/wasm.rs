@@ -1,7 +1,7 @@
-use std::{cell::RefCell, collections::HashMap, future::{poll_fn, Future}, rc::Rc, task::{Poll, Waker}};
+use std::{cell::{Cell, RefCell}, collections::HashMap, future::{poll_fn, Future}, rc::{Rc, Weak}, task::{Poll, Waker}};
 use wasm_bindgen::prelude::*;
-use web_sys::{Event, HtmlCanvasElement, HtmlImageElement, WebGl2RenderingContext, WebGlBuffer, WebGlProgram, WebGlShader, WebGlTexture, WebGlUniformLocation};
-use crate::{wasm_utils::log_errors, DvrCtx};
+use web_sys::{Event, HtmlCanvasElement, HtmlImageElement, WebGl2RenderingContext, WebGlBuffer, WebGlProgram, WebGlShader, WebGlTexture, WebGlUniformLocation, WebglLoseContext};
+use crate::{batch::{batch_scale, batch_vertices, BatchQuad, VERTEX_FLOATS}, bundle::{AssetKind, Bundle}, cache::Asset, context::ContextState, image::Image, resources::{texture_memory, ResourceKind, ResourceStats, ResourceTracker, TrackedResource}, effects::{EffectParams, EffectPass, EffectQuad, TextEffects}, quad::Quad, sdf::DistanceField, wasm_utils::{add_event_listener, js_val_err_to_string, log_errors}, DvrCtx};
 
 #[wasm_bindgen]
 extern "C" {
@@ -9,51 +9,287 @@ extern "C" {
 }
 
 pub struct Dvr {
+	ctx: WebGl2RenderingContext,
+	canvas: HtmlCanvasElement,
+	gl: RefCell<GlResources>,
+	textures: Rc<RefCell<Vec<Weak<RefCell<TextureData>>>>>,
+	resources: ResourceTracker,
+	context_state: ContextState,
+	/// How many times the resources have been recreated, so that batches know when their
+	/// buffers have to be created again.
+	restores: Cell<u32>,
+	lose_context_ext: Option<WebglLoseContext>,
+	contextlost_closure: Closure<dyn FnMut(Event)>,
+	contextrestored_closure: Closure<dyn FnMut(Event)>,
+	max_pixel_ratio: Cell<Option<f32>>,
+	pixel_ratio: Cell<f32>,
+	logical_size: Cell<(i32, i32)>,
+}
+
+/// The parts of the Dvr that belong to the WebGL context and that have to be
+/// created again if the context is lost. They are deleted when this is dropped.
+struct GlResources {
 	ctx: WebGl2RenderingContext,
 	program: WebGlProgram,
 	vertex_position: i32,
 	texture_coord: i32,
+	tex_rect: i32,
 	position_matrix_location: WebGlUniformLocation,
 	texture_offset_location: WebGlUniformLocation,
 	texture_size_location: WebGlUniformLocation,
 	sampler_location: WebGlUniformLocation,
+	colour_location: WebGlUniformLocation,
+	effects: EffectLocations,
 	position_buffer: WebGlBuffer,
 	texture_buffer: WebGlBuffer,
+	_tracked: [TrackedResource; 3],
+}
+
+/// The locations of the uniforms that are only used when drawing with effects.
+struct EffectLocations {
+	mode: WebGlUniformLocation,
+	pass: WebGlUniformLocation,
+	tex_rect: WebGlUniformLocation,
+	texel_size: WebGlUniformLocation,
+	multi_channel: WebGlUniformLocation,
+	px_range: WebGlUniformLocation,
+	outline_colour: WebGlUniformLocation,
+	outline_width: WebGlUniformLocation,
+	shadow_colour: WebGlUniformLocation,
+	shadow_offset: WebGlUniformLocation,
+	shadow_blur: WebGlUniformLocation,
+	glow_colour: WebGlUniformLocation,
+	glow_width: WebGlUniformLocation,
+}
+
+/// How the fragments of a quad are coloured.
+enum Fragment<'a> {
+	Tinted((f32, f32, f32, f32)),
+	/// Effects around the alpha of the texture, or around a distance field if there is one.
+	Effects(Option<DistanceField>, &'a TextEffects, EffectPass),
+}
+
+impl<'a> Fragment<'a> {
+	/// The cheapest way to draw a pass of effects that gives the same result.
+	fn new(field: Option<DistanceField>, effects: &'a TextEffects, pass: EffectPass) -> Fragment<'a> {
+		match field {
+			None if effects.is_plain() => Fragment::Tinted(effects.colour),
+			field => Fragment::Effects(field, effects, pass),
+		}
+	}
+}
+
+impl Drop for GlResources {
+	fn drop(&mut self) {
+		self.ctx.delete_program(Some(&self.program));
+		self.ctx.delete_buffer(Some(&self.position_buffer));
+		self.ctx.delete_buffer(Some(&self.texture_buffer));
+	}
 }
 
+/// The size in bytes of the position and texture coordinate buffers, which each hold four 2D vertices.
+const QUAD_BUFFER_SIZE: usize = 8 * size_of::<f32>();
+
 impl Dvr {
     pub fn new(ctx: DvrCtx) -> Result<Dvr, String> {
+		let canvas = Self::get_canvas(&ctx)?;
+		let resources = ResourceTracker::default();
+		let gl = Self::create_gl_resources(&ctx, &resources)?;
+
+		Self::set_ctx_state(&ctx);
+
+		// The extension has to be fetched while the context is still alive
+		let lose_context_ext = ctx.get_extension("WEBGL_lose_context")
+			.ok()
+			.flatten()
+			.map(|ext| ext.unchecked_into::<WebglLoseContext>());
+
+		let context_state = ContextState::new();
+		let contextlost_closure;
+		{
+			let context_state = context_state.clone();
+			contextlost_closure = add_event_listener(
+				canvas.as_ref(),
+				"webglcontextlost",
+				move |e: Event| {
+					// This tells the browser that we want the context back once it is available
+					e.prevent_default();
+					context_state.lost();
+				}
+			).map_err(js_val_err_to_string)?;
+		}
+		let contextrestored_closure;
+		{
+			let context_state = context_state.clone();
+			contextrestored_closure = add_event_listener(
+				canvas.as_ref(),
+				"webglcontextrestored",
+				move |_: Event| {
+					context_state.restored();
+				}
+			).map_err(js_val_err_to_string)?;
+		}
+
+		let dvr = Dvr {
+			ctx,
+			canvas,
+			gl: RefCell::new(gl),
+			textures: Rc::new(RefCell::new(Vec::new())),
+			resources,
+			context_state,
+			restores: Cell::new(0),
+			lose_context_ext,
+			contextlost_closure,
+			contextrestored_closure,
+			max_pixel_ratio: Cell::new(None),
+			pixel_ratio: Cell::new(1.0),
+			logical_size: Cell::new((0, 0)),
+		};
+		dvr.resize_canvas_if_needed()?;
+		Ok(dvr)
+	}
+
+	fn create_gl_resources(ctx: &WebGl2RenderingContext, resources: &ResourceTracker) -> Result<GlResources, String> {
 		let vs_source =
 		r##"
 		attribute vec4 aVertexPosition;
 		attribute vec2 aTextureCoord;
+		// The part of the texture that is sampled by the effects (min and max), in the same
+		// units as aTextureCoord. Quads that are not in a batch get all of their rectangle.
+		attribute vec4 aTexRect;
 
 		uniform mat4 uPositionMatrix;
 		uniform vec2 uTextureOffset;
 		uniform vec2 uTextureSize;
 
 		varying highp vec2 vTextureCoord;
+		varying highp vec4 vTexRect;
 		
 		void main() {
 			gl_Position = uPositionMatrix * aVertexPosition;
 			vTextureCoord = vec2(uTextureSize.x * aTextureCoord.x, uTextureSize.y * aTextureCoord.y) + uTextureOffset;
+			highp vec2 rectMin = uTextureSize * aTexRect.xy + uTextureOffset;
+			highp vec2 rectMax = uTextureSize * aTexRect.zw + uTextureOffset;
+			vTexRect = vec4(min(rectMin, rectMax), max(rectMin, rectMax));
 		}
 		"##;
 		let fs_source =
 		r##"
 		varying highp vec2 vTextureCoord;
+		varying highp vec4 vTexRect;
 
 		uniform sampler2D uSampler;
+		uniform highp vec4 uColour;
+
+		// 0 draws the texture, 1 draws effects around its alpha and 2 around a distance field
+		uniform int uMode;
+		// 0 draws everything, 1 only the effects (also behind the fill) and 2 only the fill
+		uniform int uPass;
+		// Everything outside this part of the texture (min and max) and vTexRect is treated as empty
+		uniform highp vec4 uTexRect;
+		uniform highp vec2 uTexelSize;
+		uniform highp float uMultiChannel;
+		uniform highp float uPxRange;
+		// The widths are in units of the field for distance fields and in texels otherwise
+		uniform highp vec4 uOutlineColour;
+		uniform highp float uOutlineWidth;
+		uniform highp vec4 uShadowColour;
+		uniform highp vec2 uShadowOffset;
+		uniform highp float uShadowBlur;
+		uniform highp vec4 uGlowColour;
+		uniform highp float uGlowWidth;
+
+		highp vec4 sampleRect(highp vec2 coord) {
+			highp vec4 rect = vec4(max(uTexRect.xy, vTexRect.xy), min(uTexRect.zw, vTexRect.zw));
+			if (any(lessThan(coord, rect.xy)) || any(greaterThan(coord, rect.zw))) {
+				return vec4(0.0);
+			}
+			return texture2D(uSampler, coord);
+		}
+
+		// The signed distance to the edge, in units of the field
+		highp float fieldDistance(highp vec2 coord) {
+			highp vec4 s = sampleRect(coord);
+			highp float median = max(min(s.r, s.g), min(max(s.r, s.g), s.b));
+			return mix(min(s.r, s.a), median, uMultiChannel) - 0.5;
+		}
+
+		// The alpha of the texture around coord, where the offsets are multiplied by radius
+		highp float maxAround(highp vec2 coord, highp float radius) {
+			highp float alpha = 0.0;
+			for (int i = 0; i < 12; i++) {
+				highp float angle = 0.5235988 * float(i);
+				alpha = max(alpha, sampleRect(coord + vec2(cos(angle), sin(angle)) * radius * uTexelSize).a);
+			}
+			return alpha;
+		}
+
+		highp float meanAround(highp vec2 coord, highp float radius) {
+			highp float alpha = 0.0;
+			for (int i = 0; i < 12; i++) {
+				highp float angle = 0.5235988 * float(i);
+				alpha += sampleRect(coord + vec2(cos(angle), sin(angle)) * radius * uTexelSize).a;
+			}
+			return alpha / 12.0;
+		}
 
 		void main() {
-			gl_FragColor = texture2D(uSampler, vTextureCoord);
+			if (uMode == 0) {
+				gl_FragColor = texture2D(uSampler, vTextureCoord) * uColour;
+				return;
+			}
+			highp vec4 fillColour;
+			highp float fill, outline, glow, shadow;
+			if (uMode == 2) {
+				highp float d = fieldDistance(vTextureCoord);
+				fill = clamp(d * uPxRange + 0.5, 0.0, 1.0);
+				fillColour = uColour * fill;
+				outline = clamp((d + uOutlineWidth) * uPxRange + 0.5, 0.0, 1.0);
+				glow = uGlowWidth > 0.0 ? 1.0 - smoothstep(0.0, uGlowWidth, -(d + uOutlineWidth)) : 0.0;
+				highp float edge = uShadowBlur + 0.5 / uPxRange;
+				shadow = smoothstep(-edge, edge, fieldDistance(vTextureCoord - uShadowOffset) + uOutlineWidth);
+			} else {
+				fillColour = sampleRect(vTextureCoord) * uColour;
+				fill = fillColour.a / max(uColour.a, 0.00001);
+				outline = fill;
+				if (uOutlineWidth > 0.0) {
+					outline = max(outline, max(maxAround(vTextureCoord, uOutlineWidth), maxAround(vTextureCoord, 0.5 * uOutlineWidth)));
+				}
+				glow = 0.0;
+				if (uGlowWidth > 0.0) {
+					highp float inner = meanAround(vTextureCoord, uOutlineWidth + 0.5 * uGlowWidth);
+					highp float outer = meanAround(vTextureCoord, uOutlineWidth + uGlowWidth);
+					glow = min(inner + outer, 1.0);
+				}
+				highp vec2 shadowCoord = vTextureCoord - uShadowOffset;
+				shadow = sampleRect(shadowCoord).a;
+				if (uShadowBlur > 0.0) {
+					shadow = 0.2 * shadow + 0.8 * meanAround(shadowCoord, uShadowBlur);
+				}
+				if (uOutlineWidth > 0.0) {
+					shadow = max(shadow, maxAround(shadowCoord, uOutlineWidth));
+				}
+			}
+			if (uPass == 1) {
+				fillColour = vec4(0.0);
+				fill = 0.0;
+			} else if (uPass == 2) {
+				outline = 0.0;
+				glow = 0.0;
+				shadow = 0.0;
+			}
+			highp vec4 colour = fillColour + uOutlineColour * outline * (1.0 - fill);
+			colour += uGlowColour * glow * (1.0 - colour.a);
+			colour += uShadowColour * shadow * (1.0 - colour.a);
+			gl_FragColor = colour;
 		}
 		"##;
 
-		let program = Self::create_shader_program(&ctx, vs_source, fs_source)?;
+		let program = Self::create_shader_program(ctx, vs_source, fs_source)?;
 
 		let vertex_position = ctx.get_attrib_location(&program, "aVertexPosition");
 		let texture_coord = ctx.get_attrib_location(&program, "aTextureCoord");
+		let tex_rect = ctx.get_attrib_location(&program, "aTexRect");
 
 		let position_matrix_location =
 			ctx.get_uniform_location(&program, "uPositionMatrix")
@@ -67,40 +303,177 @@ impl Dvr {
 		let sampler_location =
 			ctx.get_uniform_location(&program, "uSampler")
 			.ok_or("Unable to get sampler location")?;
+		let colour_location =
+			ctx.get_uniform_location(&program, "uColour")
+			.ok_or("Unable to get colour location")?;
+		let effect_location = |name: &str| ctx.get_uniform_location(&program, name)
+			.ok_or(format!("Unable to get {} location", name));
+		let effects = EffectLocations {
+			mode: effect_location("uMode")?,
+			pass: effect_location("uPass")?,
+			tex_rect: effect_location("uTexRect")?,
+			texel_size: effect_location("uTexelSize")?,
+			multi_channel: effect_location("uMultiChannel")?,
+			px_range: effect_location("uPxRange")?,
+			outline_colour: effect_location("uOutlineColour")?,
+			outline_width: effect_location("uOutlineWidth")?,
+			shadow_colour: effect_location("uShadowColour")?,
+			shadow_offset: effect_location("uShadowOffset")?,
+			shadow_blur: effect_location("uShadowBlur")?,
+			glow_colour: effect_location("uGlowColour")?,
+			glow_width: effect_location("uGlowWidth")?,
+		};
 
-		let position_buffer = Self::create_position_buffer(&ctx)?;
-		let texture_buffer = Self::create_texture_buffer(&ctx)?;
-
-		Self::resize_canvas_if_needed(&ctx)?;
-
-		ctx.pixel_storei(WebGl2RenderingContext::UNPACK_FLIP_Y_WEBGL, 1);
-		ctx.enable(WebGl2RenderingContext::BLEND);
-		ctx.blend_func(WebGl2RenderingContext::ONE, WebGl2RenderingContext::ONE_MINUS_SRC_ALPHA);
+		let position_buffer = Self::create_position_buffer(ctx)?;
+		let texture_buffer = Self::create_texture_buffer(ctx)?;
 
-		Ok(Dvr {
-			ctx,
+		Ok(GlResources {
+			ctx: ctx.clone(),
 			program,
 			vertex_position,
 			texture_coord,
+			tex_rect,
 			position_matrix_location,
 			texture_offset_location,
 			texture_size_location,
 			sampler_location,
+			colour_location,
+			effects,
 			position_buffer,
 			texture_buffer,
+			_tracked: [
+				resources.track(ResourceKind::Program, 0),
+				resources.track(ResourceKind::Buffer, QUAD_BUFFER_SIZE),
+				resources.track(ResourceKind::Buffer, QUAD_BUFFER_SIZE),
+			],
 		})
 	}
 
+	fn set_ctx_state(ctx: &WebGl2RenderingContext) {
+		ctx.pixel_storei(WebGl2RenderingContext::UNPACK_FLIP_Y_WEBGL, 1);
+		// The blend function expects premultiplied alpha, which is set for each upload
+		// (see upload_pixels()) since distance fields must not be premultiplied
+		ctx.enable(WebGl2RenderingContext::BLEND);
+		ctx.blend_func(WebGl2RenderingContext::ONE, WebGl2RenderingContext::ONE_MINUS_SRC_ALPHA);
+	}
+
+	/// Returns true if the WebGL context is lost, or if it has been restored but
+	/// restore_if_needed() has not been called yet. Nothing is drawn while this is the case.
+	pub fn is_context_lost(&self) -> bool {
+		!self.context_state.is_ok() || self.ctx.is_context_lost()
+	}
+
+	/// If the WebGL context has been restored after being lost, this recreates the shader
+	/// program and buffers and uploads all live textures again. Returns true if the
+	/// resources were recreated. This is called by the StateHandler every frame.
+	pub fn restore_if_needed(&self) -> Result<bool, String> {
+		self.context_state.restore_with(!self.ctx.is_context_lost(), || {
+			*self.gl.borrow_mut() = Self::create_gl_resources(&self.ctx, &self.resources)?;
+			Self::set_ctx_state(&self.ctx);
+			self.ctx.viewport(0, 0, self.canvas.width() as i32, self.canvas.height() as i32);
+			self.restores.set(self.restores.get() + 1);
+			self.reupload_textures()
+		})
+	}
+
+	/// Returns the number of live GPU resources and an estimate of their memory usage.
+	/// Textures are deleted when the last Texture using them is dropped, and the
+	/// shader program and buffers are deleted when the Dvr is dropped.
+	pub fn resource_stats(&self) -> ResourceStats {
+		self.resources.stats()
+	}
+
+	/// Loses the WebGL context using the WEBGL_lose_context extension. This fires the same
+	/// events as a real context loss, so it can be used to test the restore logic.
+	pub fn lose_context(&self) -> Result<(), String> {
+		self.lose_context_ext.as_ref()
+			.ok_or("WEBGL_lose_context is not supported")?
+			.lose_context();
+		Ok(())
+	}
+
+	/// Restores a context that was lost with lose_context().
+	pub fn restore_context(&self) -> Result<(), String> {
+		self.lose_context_ext.as_ref()
+			.ok_or("WEBGL_lose_context is not supported")?
+			.restore_context();
+		Ok(())
+	}
+
+	fn reupload_textures(&self) -> Result<(), String> {
+		let mut textures = self.textures.borrow_mut();
+		textures.retain(|texture| texture.strong_count() > 0);
+		for texture in textures.iter() {
+			let Some(texture) = texture.upgrade() else {
+				continue;
+			};
+			let mut texture = texture.borrow_mut();
+			texture.texture = self.ctx.create_texture()
+				.ok_or("Unable to create texture")?;
+			match &texture.source {
+				TextureSource::Image(img) => {
+					Self::upload_image(&self.ctx, &texture.texture, img, texture.premultiplied)
+						.map_err(js_val_err_to_string)?;
+				},
+				TextureSource::Pixels(image) => {
+					Self::upload_pixels(&self.ctx, &texture.texture, image, texture.premultiplied)
+						.map_err(js_val_err_to_string)?;
+				},
+				TextureSource::Owner { .. } => {
+					let (width, height) = texture.size;
+					self.ctx.bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(&texture.texture));
+					// Without data the texture is transparent black
+					self.ctx.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+						WebGl2RenderingContext::TEXTURE_2D,
+						0,
+						WebGl2RenderingContext::RGBA as i32,
+						width as i32,
+						height as i32,
+						0,
+						WebGl2RenderingContext::RGBA,
+						WebGl2RenderingContext::UNSIGNED_BYTE,
+						None
+					).map_err(js_val_err_to_string)?;
+					Self::set_texture_parameters(&self.ctx, width, height);
+					texture.source = TextureSource::Owner { contents_lost: true };
+				},
+			}
+		}
+		Ok(())
+	}
+
+	/// Returns the size of the screen in Dvr units, which are CSS pixels. See also
+	/// get_physical_screen_size() for the size of the canvas backing store.
 	pub fn get_screen_size(&self) -> (i32, i32) {
-		(self.ctx.drawing_buffer_width(), self.ctx.drawing_buffer_height())
+		self.logical_size.get()
 	}
 
 	pub fn get_screen_width(&self) -> i32 {
-		self.ctx.drawing_buffer_width()
+		self.get_screen_size().0
 	}
 
 	pub fn get_screen_height(&self) -> i32 {
-		self.ctx.drawing_buffer_height()
+		self.get_screen_size().1
+	}
+
+	/// Returns the size of the canvas backing store in device pixels.
+	pub fn get_physical_screen_size(&self) -> (i32, i32) {
+		(self.ctx.drawing_buffer_width(), self.ctx.drawing_buffer_height())
+	}
+
+	/// Returns the number of device pixels per Dvr unit that is currently used.
+	pub fn get_pixel_ratio(&self) -> f32 {
+		self.pixel_ratio.get()
+	}
+
+	/// Enables or disables scaling the canvas backing store by window.devicePixelRatio.
+	/// If max_pixel_ratio is Some, the backing store is scaled by devicePixelRatio, but
+	/// never by more than the given value. If it is None the backing store has the same size
+	/// as the canvas in CSS pixels, which is the default. Changes to devicePixelRatio (e.g.
+	/// when the window is moved to another screen) are picked up in start_draw().
+	pub fn set_max_pixel_ratio(&self, max_pixel_ratio: Option<f32>) -> Result<(), String> {
+		self.max_pixel_ratio.set(max_pixel_ratio);
+		self.resize_canvas_if_needed()
 	}
 
 	pub fn native_mouse_x_to_dvr(&self, x: i32) -> f32 {
@@ -111,12 +484,14 @@ impl Dvr {
 		self.get_screen_height() as f32 * 0.5 - y as f32
 	}
 
+	/// Converts mouse coordinates from Input, which are in CSS pixels relative to the top left
+	/// corner of the canvas, to Dvr coordinates. This does not depend on the pixel ratio.
 	pub fn native_mouse_coords_to_dvr(&self, (x, y): (i32, i32)) -> (f32, f32) {
 		(self.native_mouse_x_to_dvr(x), self.native_mouse_y_to_dvr(y))
 	}
 
 	pub fn start_draw(&self) -> Result<(), String> {
-		Self::resize_canvas_if_needed(&self.ctx)?;
+		self.resize_canvas_if_needed()?;
 		Ok(())
 	}
 
@@ -131,215 +506,423 @@ impl Dvr {
 	}
 
 	pub fn draw(&self, texture: &Texture, x: f32, y: f32, size: Option<(f32, f32)>, tex_pos_size: Option<((f32, f32), (f32, f32))>, angle: f32) -> Result<(), String> {
-		let screen_width = self.get_screen_width();
-		let screen_height = self.get_screen_height();
+		self.draw_quad(texture, &Quad::new(x, y, size, tex_pos_size, angle), Fragment::Tinted((1.0, 1.0, 1.0, 1.0)))
+	}
+
+	/// Like draw() but the colour of the texture is multiplied by colour (red, green, blue
+	/// and alpha, from 0 to 1).
+	pub fn draw_tinted(&self, texture: &Texture, quad: &Quad, colour: (f32, f32, f32, f32)) -> Result<(), String> {
+		self.draw_quad(texture, quad, Fragment::Tinted(colour))
+	}
 
-		self.ctx.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(&self.position_buffer));
+	/// Like draw() but the texture is a distance field, which is drawn with effects. This
+	/// stays sharp at any size, so e.g. text of any size can be drawn from the same texture.
+	pub fn draw_sdf(&self, texture: &Texture, quad: &Quad, field: DistanceField, effects: &TextEffects) -> Result<(), String> {
+		self.draw_quad(texture, quad, Fragment::Effects(Some(field), effects, EffectPass::All))
+	}
+
+	/// Like draw() but with an outline, shadow and glow around the alpha of the texture
+	/// (see TextEffects), which are drawn in the same draw call.
+	pub fn draw_with_effects(&self, texture: &Texture, quad: &Quad, effects: &TextEffects) -> Result<(), String> {
+		self.draw_quad(texture, quad, Fragment::Effects(None, effects, EffectPass::All))
+	}
+
+	fn draw_quad(&self, texture: &Texture, quad: &Quad, fragment: Fragment) -> Result<(), String> {
+		let Quad { x, y, size, tex_pos_size, angle } = *quad;
+		if self.is_context_lost() {
+			return Ok(());
+		}
+		let gl = self.gl.borrow();
+
+		self.ctx.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(&gl.position_buffer));
 		self.ctx.vertex_attrib_pointer_with_i32(
-			self.vertex_position as u32,
+			gl.vertex_position as u32,
 			2,
 			WebGl2RenderingContext::FLOAT,
 			false,
 			0,
 			0
 		);
-		self.ctx.enable_vertex_attrib_array(self.vertex_position as u32);
+		self.ctx.enable_vertex_attrib_array(gl.vertex_position as u32);
+
+		self.set_texture_attribute(&gl);
 
-		self.set_texture_attribute();
+		// The whole quad, which is limited to the clamp rectangle by uTexRect
+		self.ctx.disable_vertex_attrib_array(gl.tex_rect as u32);
+		self.ctx.vertex_attrib4f(gl.tex_rect as u32, 0.0, 0.0, 1.0, 1.0);
 
-		self.ctx.use_program(Some(&self.program));
+		self.ctx.use_program(Some(&gl.program));
+
+		let (tw, th) = texture.get_size();
+		let (size, tex_pos_size, clamp_rect) = match fragment {
+			Fragment::Tinted(_) => (size, tex_pos_size, None),
+			Fragment::Effects(_, effects, _) => {
+				// The quad is made larger so that there is room for the effects
+				let rect = tex_pos_size.unwrap_or(((0.0, 0.0), (tw as f32, th as f32)));
+				let quad = EffectQuad::new(effects, size.unwrap_or((1.0, 1.0)), rect);
+				(Some(quad.size), Some(quad.tex_rect), Some(quad.clamp_rect))
+			},
+		};
 
-		let ws = 1.0 / screen_width as f32;
-		let hs = 1.0 / screen_height as f32;
 		let (w, h) = match size {
 			Some((w, h)) => (w, h),
 			None => (1.0, 1.0)
 		};
-		let mtx: [f32; 4 * 4] = [
-			ws * w * angle.cos(), ws * w * -angle.sin(), 0.0, ws * x * 2.0,
-			hs * h * angle.sin(), hs * h *  angle.cos(), 0.0, hs * y * 2.0,
-			                 0.0,                   0.0, 1.0,          0.0,
-			                 0.0,                   0.0, 0.0,          1.0,
-		];
-		self.ctx.uniform_matrix4fv_with_f32_array(
-			Some(&self.position_matrix_location),
-			true,
-			&mtx
-		);
+		self.set_position_matrix(&gl, (x, y), (w, h), angle);
 
 		let (pos, size) = match tex_pos_size {
-			Some(((x, y), (w, h))) => {
-				let (tw, th) = texture.get_size();
-				([x / tw as f32, 1.0 - (h + y) / th as f32], [w / tw as f32, h / th as f32])
-			},
+			Some(((x, y), (w, h))) => ([x / tw as f32, 1.0 - (h + y) / th as f32], [w / tw as f32, h / th as f32]),
 			None => ([0.0, 0.0], [1.0, 1.0]),
 		};
 		self.ctx.uniform2fv_with_f32_array(
-			Some(&self.texture_offset_location),
+			Some(&gl.texture_offset_location),
 			&pos
 		);
 		self.ctx.uniform2fv_with_f32_array(
-			Some(&self.texture_size_location),
+			Some(&gl.texture_size_location),
 			&size
 		);
 
-		self.ctx.active_texture(WebGl2RenderingContext::TEXTURE0);
-		self.ctx.bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(&texture.texture));
-		self.ctx.uniform1i(Some(&self.sampler_location), 0);
+		let ((_, (quad_w, quad_h)), ((cx, cy), (cw, ch))) = (tex_pos_size.unwrap_or_default(), clamp_rect.unwrap_or_default());
+		let (tw, th) = (tw as f32, th as f32);
+		let scale = 0.5 * (w / quad_w + h / quad_h);
+		let tex_rect = [cx / tw, 1.0 - (cy + ch) / th, (cx + cw) / tw, 1.0 - cy / th];
+		self.set_fragment_uniforms(&gl, texture, fragment, scale, tex_rect);
 
 		self.ctx.draw_arrays(WebGl2RenderingContext::TRIANGLE_STRIP, 0, 4);
 
 		Ok(())
 	}
 
-	fn load_texture_internal(&self, url: &str) -> Result<impl Future<Output = Result<Texture, String>>, String> {
-		enum TextureLoadStatus {
-			Loading,
-			Loaded,
-			Error,
+	/// Makes a batch of quads that are drawn from a texture with one draw call. The vertices
+	/// are kept so that they can be uploaded again if the context is lost.
+	pub(crate) fn create_quad_batch(&self, texture: &Texture, quads: &[BatchQuad]) -> Result<QuadBatch, String> {
+		let vertices = batch_vertices(quads);
+		if vertices.is_empty() {
+			return Err("A batch needs at least one quad".to_string());
 		}
+		let buffer = self.create_batch_buffer(&vertices)?;
+		Ok(QuadBatch {
+			texture: texture.share(),
+			vertices,
+			scale: batch_scale(quads),
+			buffer: RefCell::new(buffer),
+		})
+	}
 
-		let texture = self.ctx.create_texture()
-			.ok_or("Unable to create texture")?;
-		let status: Rc<RefCell<TextureLoadStatus>> = Rc::new(RefCell::new(TextureLoadStatus::Loading));
-		let waker: Rc<RefCell<Option<Waker>>> = Rc::new(RefCell::new(None));
+	fn create_batch_buffer(&self, vertices: &[f32]) -> Result<BatchBuffer, String> {
+		let buffer = self.ctx.create_buffer().ok_or("Unable to create buffer")?;
+		self.ctx.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(&buffer));
+		unsafe {
+			let vertices_view = js_sys::Float32Array::view(vertices);
+			self.ctx.buffer_data_with_array_buffer_view(
+				WebGl2RenderingContext::ARRAY_BUFFER,
+				&vertices_view,
+				WebGl2RenderingContext::STATIC_DRAW
+			);
+		}
+		Ok(BatchBuffer {
+			ctx: self.ctx.clone(),
+			buffer,
+			restores: self.restores.get(),
+			_tracked: self.resources.track(ResourceKind::Buffer, size_of_val(vertices)),
+		})
+	}
 
-		self.ctx.bind_texture(
+	/// Draws a part of a texture at a quad (centre and size) like draw_sdf() if there is a
+	/// distance field, and otherwise like draw_tinted() or draw_with_effects(), but only the
+	/// parts of the effects of the pass.
+	pub(crate) fn draw_effect_pass(&self, texture: &Texture, (pos, size): ((f32, f32), (f32, f32)), tex_rect: ((f32, f32), (f32, f32)), field: Option<DistanceField>, effects: &TextEffects, pass: EffectPass) -> Result<(), String> {
+		self.draw_quad(texture, &Quad::new(pos.0, pos.1, Some(size), Some(tex_rect), 0.0), Fragment::new(field, effects, pass))
+	}
+
+	/// Draws all quads of a batch at (x, y) like draw_effect_pass() draws a quad.
+	pub(crate) fn draw_quad_batch(&self, batch: &QuadBatch, (x, y): (f32, f32), field: Option<DistanceField>, effects: &TextEffects, pass: EffectPass) -> Result<(), String> {
+		if self.is_context_lost() {
+			return Ok(());
+		}
+		if batch.buffer.borrow().restores != self.restores.get() {
+			*batch.buffer.borrow_mut() = self.create_batch_buffer(&batch.vertices)?;
+		}
+		let gl = self.gl.borrow();
+
+		self.ctx.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(&batch.buffer.borrow().buffer));
+		let stride = (VERTEX_FLOATS * size_of::<f32>()) as i32;
+		for (attribute, size, offset) in [(gl.vertex_position, 2, 0), (gl.texture_coord, 2, 2), (gl.tex_rect, 4, 4)] {
+			self.ctx.vertex_attrib_pointer_with_i32(
+				attribute as u32,
+				size,
+				WebGl2RenderingContext::FLOAT,
+				false,
+				stride,
+				offset * size_of::<f32>() as i32
+			);
+			self.ctx.enable_vertex_attrib_array(attribute as u32);
+		}
+
+		self.ctx.use_program(Some(&gl.program));
+
+		// The vertices are in Dvr units relative to (x, y), where the quads above are 2 units wide
+		self.set_position_matrix(&gl, (x, y), (2.0, 2.0), 0.0);
+
+		// The texture coordinates are in texture pixels, and the textures are flipped
+		let (tw, th) = batch.texture.get_size();
+		self.ctx.uniform2f(Some(&gl.texture_offset_location), 0.0, 1.0);
+		self.ctx.uniform2f(Some(&gl.texture_size_location), 1.0 / tw as f32, -1.0 / th as f32);
+
+		self.set_fragment_uniforms(&gl, &batch.texture, Fragment::new(field, effects, pass), batch.scale, [0.0, 0.0, 1.0, 1.0]);
+
+		let count = (batch.vertices.len() / VERTEX_FLOATS) as i32;
+		self.ctx.draw_arrays(WebGl2RenderingContext::TRIANGLES, 0, count);
+
+		Ok(())
+	}
+
+	/// Sets the matrix that moves the quad of the position buffer to (x, y) with a size,
+	/// rotated by angle.
+	fn set_position_matrix(&self, gl: &GlResources, (x, y): (f32, f32), (w, h): (f32, f32), angle: f32) {
+		let ws = 1.0 / self.get_screen_width() as f32;
+		let hs = 1.0 / self.get_screen_height() as f32;
+		let mtx: [f32; 4 * 4] = [
+			ws * w * angle.cos(), ws * w * -angle.sin(), 0.0, ws * x * 2.0,
+			hs * h * angle.sin(), hs * h *  angle.cos(), 0.0, hs * y * 2.0,
+			                 0.0,                   0.0, 1.0,          0.0,
+			                 0.0,                   0.0, 0.0,          1.0,
+		];
+		self.ctx.uniform_matrix4fv_with_f32_array(
+			Some(&gl.position_matrix_location),
+			true,
+			&mtx
+		);
+	}
+
+	/// Binds the texture and sets the uniforms of the fragment shader. Scale is the number
+	/// of Dvr units per texture pixel and tex_rect is the part of the texture that the effects
+	/// sample, in texture coordinates (min and max).
+	fn set_fragment_uniforms(&self, gl: &GlResources, texture: &Texture, fragment: Fragment, scale: f32, tex_rect: [f32; 4]) {
+		self.ctx.active_texture(WebGl2RenderingContext::TEXTURE0);
+		self.ctx.bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(&texture.data.borrow().texture));
+		self.ctx.uniform1i(Some(&gl.sampler_location), 0);
+
+		match fragment {
+			Fragment::Tinted((r, g, b, a)) => {
+				self.ctx.uniform1i(Some(&gl.effects.mode), 0);
+				// The textures have premultiplied alpha
+				self.ctx.uniform4f(Some(&gl.colour_location), r * a, g * a, b * a, a);
+			},
+			Fragment::Effects(field, effects, pass) => {
+				let params = EffectParams::new(effects, field, scale * self.get_pixel_ratio(), pass);
+				let (tw, th) = texture.get_size();
+				let (tw, th) = (tw as f32, th as f32);
+				let locations = &gl.effects;
+				self.ctx.uniform1i(Some(&locations.mode), params.mode);
+				self.ctx.uniform1i(Some(&locations.pass), params.pass);
+				self.ctx.uniform4fv_with_f32_array(Some(&locations.tex_rect), &tex_rect);
+				self.ctx.uniform2f(Some(&locations.texel_size), 1.0 / tw, 1.0 / th);
+				self.ctx.uniform4fv_with_f32_array(Some(&gl.colour_location), &params.colour);
+				self.ctx.uniform1f(Some(&locations.multi_channel), params.multi_channel);
+				self.ctx.uniform1f(Some(&locations.px_range), params.px_range);
+				self.ctx.uniform4fv_with_f32_array(Some(&locations.outline_colour), &params.outline_colour);
+				self.ctx.uniform1f(Some(&locations.outline_width), params.outline_width);
+				self.ctx.uniform4fv_with_f32_array(Some(&locations.shadow_colour), &params.shadow_colour);
+				// The texture coordinates go up like Dvr coordinates since the textures are flipped
+				let (ox, oy) = effects.shadow_offset;
+				self.ctx.uniform2f(Some(&locations.shadow_offset), ox / tw, oy / th);
+				self.ctx.uniform1f(Some(&locations.shadow_blur), params.shadow_blur);
+				self.ctx.uniform4fv_with_f32_array(Some(&locations.glow_colour), &params.glow_colour);
+				self.ctx.uniform1f(Some(&locations.glow_width), params.glow_width);
+			},
+		}
+	}
+
+	pub(crate) fn load_texture_internal(&self, url: &str, premultiply: bool) -> Result<impl Future<Output = Result<Texture, String>>, String> {
+		self.texture_factory().load_texture(url, premultiply)
+	}
+
+	pub(crate) fn texture_factory(&self) -> TextureFactory {
+		TextureFactory {
+			ctx: self.ctx.clone(),
+			textures: self.textures.clone(),
+			resources: self.resources.clone(),
+		}
+	}
+
+	fn upload_image(ctx: &WebGl2RenderingContext, texture: &WebGlTexture, img: &HtmlImageElement, premultiply: bool) -> Result<(), JsValue> {
+		ctx.bind_texture(
 			WebGl2RenderingContext::TEXTURE_2D,
-			Some(&texture)
+			Some(texture)
 		);
+		Self::set_premultiply(ctx, premultiply);
+		ctx.tex_image_2d_with_u32_and_u32_and_html_image_element(
+			WebGl2RenderingContext::TEXTURE_2D,
+			0,
+			WebGl2RenderingContext::RGBA as i32,
+			WebGl2RenderingContext::RGBA,
+			WebGl2RenderingContext::UNSIGNED_BYTE,
+			img
+		)?;
 
-		self.ctx.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_u8_array_and_src_offset(
+		Self::set_texture_parameters(ctx, img.width(), img.height());
+		Ok(())
+	}
+
+	fn upload_pixels(ctx: &WebGl2RenderingContext, texture: &WebGlTexture, image: &Image, premultiply: bool) -> Result<(), JsValue> {
+		ctx.bind_texture(
+			WebGl2RenderingContext::TEXTURE_2D,
+			Some(texture)
+		);
+		Self::set_premultiply(ctx, premultiply);
+		ctx.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_u8_array_and_src_offset(
 			WebGl2RenderingContext::TEXTURE_2D,
 			0,
 			WebGl2RenderingContext::RGBA as i32,
-			1,
-			1,
+			image.get_width() as i32,
+			image.get_height() as i32,
 			0,
 			WebGl2RenderingContext::RGBA,
 			WebGl2RenderingContext::UNSIGNED_BYTE,
-			&[255, 255, 255, 255],
+			image.pixels(),
 			0
-		).ok().ok_or("Failed to create default texture")?;
-
-		let load_error_closures: Rc<RefCell<Option<(Closure<dyn FnMut()>, Closure<dyn FnMut(Event)>)>>>
-			= Rc::new(RefCell::new(None));
-		let image = HtmlImageElement::new()
-			.ok().ok_or("Failed to create image element")?;
-		let load_closure;
-		{
-			let ctx = self.ctx.clone();
-			let texture = texture.clone();
-			let img = image.clone();
-			let load_error_closures = load_error_closures.clone();
-			let status = status.clone();
-			let waker = waker.clone();
-			load_closure = Closure::<dyn FnMut()>::new(log_errors(move || -> Result<(), JsValue> {
-				*status.borrow_mut() = TextureLoadStatus::Error;
-
-				ctx.bind_texture(
-					WebGl2RenderingContext::TEXTURE_2D,
-					Some(&texture)
-				);
-				ctx.tex_image_2d_with_u32_and_u32_and_html_image_element(
-					WebGl2RenderingContext::TEXTURE_2D,
-					0,
-					WebGl2RenderingContext::RGBA as i32,
-					WebGl2RenderingContext::RGBA,
-					WebGl2RenderingContext::UNSIGNED_BYTE,
-					&img
-				)?;
-
-				let is_power_of_2 = |x: u32| (x & (x - 1)) == 0;
-				if is_power_of_2(img.width()) && is_power_of_2(img.height()) {
-					ctx.generate_mipmap(WebGl2RenderingContext::TEXTURE_2D);
-				} else {
-					ctx.tex_parameteri(
-						WebGl2RenderingContext::TEXTURE_2D,
-						WebGl2RenderingContext::TEXTURE_WRAP_S,
-						WebGl2RenderingContext::CLAMP_TO_EDGE as i32
-					);
-					ctx.tex_parameteri(
-						WebGl2RenderingContext::TEXTURE_2D,
-						WebGl2RenderingContext::TEXTURE_WRAP_T,
-						WebGl2RenderingContext::CLAMP_TO_EDGE as i32
-					);
-					ctx.tex_parameteri(
-						WebGl2RenderingContext::TEXTURE_2D,
-						WebGl2RenderingContext::TEXTURE_MIN_FILTER,
-						WebGl2RenderingContext::LINEAR as i32
-					);
-				}
+		)?;
+		Self::set_texture_parameters(ctx, image.get_width(), image.get_height());
+		Ok(())
+	}
 
-				*status.borrow_mut() = TextureLoadStatus::Loaded;
-				if let Some(waker) = waker.borrow_mut().take() {
-					waker.wake();
-				}
-				drop((*load_error_closures.borrow_mut()).take());
+	/// Sets whether the colours of the next uploads are multiplied by their alpha. Colour
+	/// textures are premultiplied for the blend function, but textures whose channels are
+	/// data (e.g. the distances of an MTSDF, which has a distance in alpha as well) are not.
+	fn set_premultiply(ctx: &WebGl2RenderingContext, premultiply: bool) {
+		ctx.pixel_storei(WebGl2RenderingContext::UNPACK_PREMULTIPLY_ALPHA_WEBGL, premultiply as i32);
+	}
 
-				Ok(())
-			}));
-			image.set_onload(Some(load_closure.as_ref().unchecked_ref()));
-		}
-		let error_closure;
-		{
-			let load_error_closures = load_error_closures.clone();
-			let status = status.clone();
-			let waker = waker.clone();
-			error_closure = Closure::<dyn FnMut(_)>::new(move |_: Event|{
-				web_sys::console::error_1(&JsValue::from_str("Unable to load texture"));
-				*status.borrow_mut() = TextureLoadStatus::Error;
-				if let Some(waker) = waker.borrow_mut().take() {
-					waker.wake();
-				}
-				drop((*load_error_closures.borrow_mut()).take());
-			});
-			image.set_onerror(Some(error_closure.as_ref().unchecked_ref()));
+	/// Sets the parameters of the currently bound texture.
+	fn set_texture_parameters(ctx: &WebGl2RenderingContext, width: u32, height: u32) {
+		if Self::has_mipmaps(width, height) {
+			ctx.generate_mipmap(WebGl2RenderingContext::TEXTURE_2D);
+		} else {
+			ctx.tex_parameteri(
+				WebGl2RenderingContext::TEXTURE_2D,
+				WebGl2RenderingContext::TEXTURE_WRAP_S,
+				WebGl2RenderingContext::CLAMP_TO_EDGE as i32
+			);
+			ctx.tex_parameteri(
+				WebGl2RenderingContext::TEXTURE_2D,
+				WebGl2RenderingContext::TEXTURE_WRAP_T,
+				WebGl2RenderingContext::CLAMP_TO_EDGE as i32
+			);
+			ctx.tex_parameteri(
+				WebGl2RenderingContext::TEXTURE_2D,
+				WebGl2RenderingContext::TEXTURE_MIN_FILTER,
+				WebGl2RenderingContext::LINEAR as i32
+			);
 		}
-		*load_error_closures.borrow_mut() = Some((load_closure, error_closure));
-		image.set_src(url);
+	}
 
-		Ok(poll_fn(move |cx| -> Poll<Result<Texture, String>> {
-			match *status.borrow_mut() {
-				TextureLoadStatus::Loading => {
-					if waker.borrow().is_none() {
-						*waker.borrow_mut() = Some(cx.waker().clone());
-					}
-					Poll::Pending
-				},
-				TextureLoadStatus::Loaded => Poll::Ready(Ok(Texture {
-					texture: texture.clone(), // Can't move for some reason
-					size: (image.width(), image.height()),
-				})),
-				TextureLoadStatus::Error => Poll::Ready(Err("Error when loading texture".to_string())),
-			}
-		}))
+	/// Mipmaps are only generated for textures whose sides are powers of two.
+	fn has_mipmaps(width: u32, height: u32) -> bool {
+		let is_power_of_2 = |x: u32| x != 0 && (x & (x - 1)) == 0;
+		is_power_of_2(width) && is_power_of_2(height)
 	}
-	
+
 	pub async fn load_texture(&self, url: &str) -> Result<Texture, String> {
-		match self.load_texture_internal(url) {
+		match self.load_texture_internal(url, true) {
 			Ok(future) => future.await,
 			Err(e) => Err(e),
 		}
 	}
 
-	fn resize_canvas_if_needed(ctx: &WebGl2RenderingContext) -> Result<(), String> {
-		let canvas = Self::get_canvas(&ctx)?;
-		let cw = canvas.client_width();
-		let ch = canvas.client_height();
-		let w = canvas.width();
-		let h = canvas.height();
-		if w != cw as u32 || h != ch as u32 {
-			canvas.set_width(cw as u32);
-			canvas.set_height(ch as u32);
-			ctx.viewport(0, 0, cw, ch);
+	/// Like load_texture(), but the colours are not multiplied by alpha. This is for
+	/// textures whose channels are data rather than colours, such as distance fields.
+	pub async fn load_data_texture(&self, url: &str) -> Result<Texture, String> {
+		match self.load_texture_internal(url, false) {
+			Ok(future) => future.await,
+			Err(e) => Err(e),
+		}
+	}
+
+	/// Loads a texture from the bytes of an image file using the built-in decoder.
+	/// See image::ImageFormat for the supported formats.
+	pub fn load_texture_raw(&self, data: &[u8]) -> Result<Texture, String> {
+		self.load_texture_image(Image::decode(data)?)
+	}
+
+	/// Like load_texture_raw(), but the colours are not multiplied by alpha (see
+	/// load_data_texture()).
+	pub fn load_data_texture_raw(&self, data: &[u8]) -> Result<Texture, String> {
+		self.texture_factory().load_texture_image(Image::decode(data)?, false)
+	}
+
+	/// Creates a texture from an already decoded image.
+	pub fn load_texture_image(&self, image: Image) -> Result<Texture, String> {
+		self.texture_factory().load_texture_image(image, true)
+	}
+
+	/// Creates a texture that is changed with update_texture(). The pixels are not kept,
+	/// so the owner has to upload them again when Texture::take_contents_lost() is true.
+	pub(crate) fn load_texture_for_updates(&self, image: &Image) -> Result<Texture, String> {
+		let texture = self.ctx.create_texture()
+			.ok_or("Unable to create texture")?;
+		if let Err(e) = Self::upload_pixels(&self.ctx, &texture, image, true) {
+			self.ctx.delete_texture(Some(&texture));
+			return Err(js_val_err_to_string(e));
+		}
+		Ok(self.texture_factory().register(texture, image.get_size(), TextureSource::Owner { contents_lost: false }, true))
+	}
+
+	/// Replaces a part of a texture, with its top left corner at (x, y).
+	pub(crate) fn update_texture(&self, texture: &Texture, x: u32, y: u32, image: &Image) -> Result<(), String> {
+		let data = texture.data.borrow();
+		let (width, height) = image.get_size();
+		if x + width > data.size.0 || y + height > data.size.1 {
+			return Err("The image does not fit in the texture".to_string());
+		}
+		self.ctx.bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(&data.texture));
+		Self::set_premultiply(&self.ctx, data.premultiplied);
+		// The rows are flipped when uploaded, so the texture starts at the bottom
+		self.ctx.tex_sub_image_2d_with_i32_and_i32_and_u32_and_type_and_opt_u8_array(
+			WebGl2RenderingContext::TEXTURE_2D,
+			0,
+			x as i32,
+			(data.size.1 - y - height) as i32,
+			width as i32,
+			height as i32,
+			WebGl2RenderingContext::RGBA,
+			WebGl2RenderingContext::UNSIGNED_BYTE,
+			Some(image.pixels())
+		).map_err(js_val_err_to_string)?;
+		if Self::has_mipmaps(data.size.0, data.size.1) {
+			self.ctx.generate_mipmap(WebGl2RenderingContext::TEXTURE_2D);
+		}
+		Ok(())
+	}
+
+	fn resize_canvas_if_needed(&self) -> Result<(), String> {
+		let pixel_ratio = match self.max_pixel_ratio.get() {
+			Some(max_pixel_ratio) => web_sys::window()
+				.ok_or("Unable to get window")?
+				.device_pixel_ratio()
+				.min(max_pixel_ratio as f64) as f32,
+			None => 1.0,
+		};
+		let cw = self.canvas.client_width();
+		let ch = self.canvas.client_height();
+		let pw = (cw as f32 * pixel_ratio).round() as u32;
+		let ph = (ch as f32 * pixel_ratio).round() as u32;
+		let w = self.canvas.width();
+		let h = self.canvas.height();
+		if w != pw || h != ph {
+			self.canvas.set_width(pw);
+			self.canvas.set_height(ph);
+			self.ctx.viewport(0, 0, pw as i32, ph as i32);
 		}
+		self.pixel_ratio.set(pixel_ratio);
+		self.logical_size.set((cw, ch));
 		Ok(())
 	}
 
 	// TODO: bör den här finnas?
 	pub fn resize(&self) -> Result<(), String> {
-		Self::resize_canvas_if_needed(&self.ctx)
+		self.resize_canvas_if_needed()
 	}
 
 	fn create_shader_program(ctx: &WebGl2RenderingContext, vs_source: &str, fs_source: &str) -> Result<WebGlProgram, String> {
@@ -351,12 +934,17 @@ impl Dvr {
 		ctx.attach_shader(&shader_program, &vs_shader);
 		ctx.attach_shader(&shader_program, &fs_shader);
 		ctx.link_program(&shader_program);
+		// The shaders are only marked for deletion, they are deleted along with the program
+		ctx.delete_shader(Some(&vs_shader));
+		ctx.delete_shader(Some(&fs_shader));
 
 		let link_status = ctx.get_program_parameter(&shader_program, WebGl2RenderingContext::LINK_STATUS);
 		if !link_status.as_bool().ok_or("Link status is not boolean")? {
-			return Err(ctx.get_program_info_log(&shader_program)
+			let err_text = ctx.get_program_info_log(&shader_program)
 				.filter(|s| s != "")
-				.unwrap_or_else(|| String::from("Unable to link shader program")));
+				.unwrap_or_else(|| String::from("Unable to link shader program"));
+			ctx.delete_program(Some(&shader_program));
+			return Err(err_text);
 		}
 
 		Ok(shader_program)
@@ -408,17 +996,17 @@ impl Dvr {
 		Ok(buffer)
 	}
 
-	fn set_texture_attribute(&self) {
-		self.ctx.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(&self.texture_buffer));
+	fn set_texture_attribute(&self, gl: &GlResources) {
+		self.ctx.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(&gl.texture_buffer));
 		self.ctx.vertex_attrib_pointer_with_i32(
-			self.texture_coord as u32,
+			gl.texture_coord as u32,
 			2,
 			WebGl2RenderingContext::FLOAT,
 			false,
 			0,
 			0
 		);
-		self.ctx.enable_vertex_attrib_array(self.texture_coord as u32);
+		self.ctx.enable_vertex_attrib_array(gl.texture_coord as u32);
 	}
 
 	fn get_canvas(ctx: &WebGl2RenderingContext) -> Result<HtmlCanvasElement, String> {
@@ -430,7 +1018,7 @@ impl Dvr {
 	}
 
 	pub fn canvas(&self) -> Result<HtmlCanvasElement, String> {
-		Self::get_canvas(&self.ctx)
+		Ok(self.canvas.clone())
 	}
 
 	// fn get_error(&self) -> Result<(), u32> {
@@ -441,27 +1029,283 @@ impl Dvr {
 	// }
 }
 
+impl Drop for Dvr {
+	fn drop(&mut self) {
+		let _ = self.canvas.remove_event_listener_with_callback(
+			"webglcontextrestored",
+			self.contextrestored_closure.as_ref().unchecked_ref()
+		);
+		let _ = self.canvas.remove_event_listener_with_callback(
+			"webglcontextlost",
+			self.contextlost_closure.as_ref().unchecked_ref()
+		);
+	}
+}
+
+/// The onload and onerror closures of an image that is loading.
+type ImageClosures = (Closure<dyn FnMut()>, Closure<dyn FnMut(Event)>);
+
+/// A texture that is loading.
+type TextureFuture = Box<dyn Future<Output = Result<Texture, String>> + Unpin>;
+
+/// The parts of the Dvr that are needed to create textures. This can be cloned so that
+/// textures can be created without access to the Dvr, e.g. when hot reloading.
+#[derive(Clone)]
+pub(crate) struct TextureFactory {
+	ctx: WebGl2RenderingContext,
+	textures: Rc<RefCell<Vec<Weak<RefCell<TextureData>>>>>,
+	resources: ResourceTracker,
+}
+
+impl TextureFactory {
+	pub(crate) fn load_texture_image(&self, image: Image, premultiply: bool) -> Result<Texture, String> {
+		let texture = self.ctx.create_texture()
+			.ok_or("Unable to create texture")?;
+		if let Err(e) = Dvr::upload_pixels(&self.ctx, &texture, &image, premultiply) {
+			self.ctx.delete_texture(Some(&texture));
+			return Err(js_val_err_to_string(e));
+		}
+		Ok(self.register(texture, image.get_size(), TextureSource::Pixels(image), premultiply))
+	}
+
+	/// Creates a texture from an uploaded WebGlTexture and adds it to the list of
+	/// textures that the Dvr uploads again when the context is restored.
+	fn register(&self, texture: WebGlTexture, (width, height): (u32, u32), source: TextureSource, premultiplied: bool) -> Texture {
+		let memory = texture_memory(width, height, Dvr::has_mipmaps(width, height));
+		let data = Rc::new(RefCell::new(TextureData {
+			ctx: self.ctx.clone(),
+			texture,
+			size: (width, height),
+			source,
+			premultiplied,
+			tracked: self.resources.track(ResourceKind::Texture, memory),
+		}));
+		self.textures.borrow_mut().push(Rc::downgrade(&data));
+		Texture { data }
+	}
+
+	pub(crate) fn load_texture(&self, url: &str, premultiply: bool) -> Result<impl Future<Output = Result<Texture, String>>, String> {
+		enum TextureLoadStatus {
+			Loading,
+			Loaded,
+			Error,
+		}
+
+		let texture = self.ctx.create_texture()
+			.ok_or("Unable to create texture")?;
+		let status: Rc<RefCell<TextureLoadStatus>> = Rc::new(RefCell::new(TextureLoadStatus::Loading));
+		let waker: Rc<RefCell<Option<Waker>>> = Rc::new(RefCell::new(None));
+
+		self.ctx.bind_texture(
+			WebGl2RenderingContext::TEXTURE_2D,
+			Some(&texture)
+		);
+
+		self.ctx.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_u8_array_and_src_offset(
+			WebGl2RenderingContext::TEXTURE_2D,
+			0,
+			WebGl2RenderingContext::RGBA as i32,
+			1,
+			1,
+			0,
+			WebGl2RenderingContext::RGBA,
+			WebGl2RenderingContext::UNSIGNED_BYTE,
+			&[255, 255, 255, 255],
+			0
+		).ok().ok_or("Failed to create default texture")?;
+
+		let factory = self.clone();
+		let load_error_closures: Rc<RefCell<Option<ImageClosures>>> = Rc::new(RefCell::new(None));
+		let image = HtmlImageElement::new()
+			.ok().ok_or("Failed to create image element")?;
+		let load_closure;
+		{
+			let ctx = self.ctx.clone();
+			let texture = texture.clone();
+			let img = image.clone();
+			let load_error_closures = load_error_closures.clone();
+			let status = status.clone();
+			let waker = waker.clone();
+			load_closure = Closure::<dyn FnMut()>::new(log_errors(move || -> Result<(), JsValue> {
+				*status.borrow_mut() = TextureLoadStatus::Error;
+
+				Dvr::upload_image(&ctx, &texture, &img, premultiply)?;
+
+				*status.borrow_mut() = TextureLoadStatus::Loaded;
+				if let Some(waker) = waker.borrow_mut().take() {
+					waker.wake();
+				}
+				drop((*load_error_closures.borrow_mut()).take());
+
+				Ok(())
+			}));
+			image.set_onload(Some(load_closure.as_ref().unchecked_ref()));
+		}
+		let error_closure;
+		{
+			let load_error_closures = load_error_closures.clone();
+			let status = status.clone();
+			let waker = waker.clone();
+			error_closure = Closure::<dyn FnMut(_)>::new(move |_: Event|{
+				web_sys::console::error_1(&JsValue::from_str("Unable to load texture"));
+				*status.borrow_mut() = TextureLoadStatus::Error;
+				if let Some(waker) = waker.borrow_mut().take() {
+					waker.wake();
+				}
+				drop((*load_error_closures.borrow_mut()).take());
+			});
+			image.set_onerror(Some(error_closure.as_ref().unchecked_ref()));
+		}
+		*load_error_closures.borrow_mut() = Some((load_closure, error_closure));
+		image.set_src(url);
+
+		Ok(poll_fn(move |cx| -> Poll<Result<Texture, String>> {
+			match *status.borrow_mut() {
+				TextureLoadStatus::Loading => {
+					if waker.borrow().is_none() {
+						*waker.borrow_mut() = Some(cx.waker().clone());
+					}
+					Poll::Pending
+				},
+				TextureLoadStatus::Loaded => Poll::Ready(Ok(factory.register(
+					texture.clone(), // Can't move for some reason
+					(image.width(), image.height()),
+					TextureSource::Image(image.clone()),
+					premultiply,
+				))),
+				TextureLoadStatus::Error => {
+					factory.ctx.delete_texture(Some(&texture));
+					Poll::Ready(Err("Error when loading texture".to_string()))
+				},
+			}
+		}))
+	}
+	
+}
+
 pub struct Texture {
+	data: Rc<RefCell<TextureData>>,
+}
+
+struct TextureData {
+	ctx: WebGl2RenderingContext,
 	texture: WebGlTexture,
 	size: (u32, u32),
+	source: TextureSource,
+	/// Whether the colours were multiplied by alpha when uploaded (see Dvr::set_premultiply()).
+	premultiplied: bool,
+	tracked: TrackedResource,
+}
+
+impl Drop for TextureData {
+	fn drop(&mut self) {
+		self.ctx.delete_texture(Some(&self.texture));
+	}
+}
+
+/// What a texture was created from. This is kept so that the texture can be
+/// uploaded again if the WebGL context is lost.
+enum TextureSource {
+	Image(HtmlImageElement),
+	Pixels(Image),
+	/// The owner of the texture keeps the pixels (e.g. the glyph atlas of a FontSheet), so
+	/// the texture is only cleared when the context is restored.
+	Owner { contents_lost: bool },
+}
+
+/// Quads that are drawn from the same texture with one draw call (see Dvr::create_quad_batch()).
+pub(crate) struct QuadBatch {
+	texture: Texture,
+	vertices: Vec<f32>,
+	/// The number of Dvr units per texture pixel.
+	scale: f32,
+	buffer: RefCell<BatchBuffer>,
+}
+
+/// The vertex buffer of a QuadBatch, which is created again after the context is restored.
+struct BatchBuffer {
+	ctx: WebGl2RenderingContext,
+	buffer: WebGlBuffer,
+	/// The value of Dvr::restores when the buffer was created.
+	restores: u32,
+	_tracked: TrackedResource,
+}
+
+impl Drop for BatchBuffer {
+	fn drop(&mut self) {
+		self.ctx.delete_buffer(Some(&self.buffer));
+	}
 }
 
 impl Texture {
+	/// Replaces the contents of target with this texture, so that everything that uses the
+	/// target texture gets this texture instead.
+	fn replace(self, target: &RefCell<TextureData>) {
+		std::mem::swap(&mut *target.borrow_mut(), &mut *self.data.borrow_mut());
+	}
+
+	/// Replaces the contents of this texture with another texture.
+	pub(crate) fn set(&self, other: Texture) {
+		other.replace(&self.data);
+	}
+
+	/// Another handle to the same texture, which sees it being replaced as well.
+	pub(crate) fn share(&self) -> Texture {
+		Texture {
+			data: self.data.clone(),
+		}
+	}
+
+	/// Returns true (once) if the texture was created with Dvr::load_texture_for_updates()
+	/// and has been cleared because the WebGL context was restored.
+	pub(crate) fn take_contents_lost(&self) -> bool {
+		match &mut self.data.borrow_mut().source {
+			TextureSource::Owner { contents_lost } => std::mem::replace(contents_lost, false),
+			_ => false,
+		}
+	}
+
+	/// Returns false if the texture was loaded with Dvr::load_data_texture() or
+	/// Dvr::load_data_texture_raw().
+	pub(crate) fn is_premultiplied(&self) -> bool {
+		self.data.borrow().premultiplied
+	}
+
 	pub fn get_size(&self) -> (u32, u32) {
-		self.size
+		self.data.borrow().size
 	}
 
 	pub fn get_width(&self) -> u32 {
-		self.size.0
+		self.get_size().0
 	}
 
 	pub fn get_height(&self) -> u32 {
-		self.size.1
+		self.get_size().1
+	}
+
+	/// The estimated memory used by the texture in bytes.
+	pub fn get_memory(&self) -> usize {
+		self.data.borrow().tracked.memory()
+	}
+}
+
+impl Asset for Texture {
+	fn memory(&self) -> usize {
+		self.get_memory()
 	}
 }
 
 pub struct TextureHandler {
 	textures: HashMap<String, Texture>,
+	origins: Vec<TextureOrigin>,
+	hot_reload: Option<TextureFactory>,
+}
+
+/// Where a texture was loaded from. This is kept for textures that have been
+/// taken from the handler as well, so that they can also be hot reloaded.
+struct TextureOrigin {
+	url: String,
+	texture: Weak<RefCell<TextureData>>,
 }
 
 impl TextureHandler {
@@ -472,15 +1316,90 @@ impl TextureHandler {
 	/// is the one that has not been passed through the transform, so if the tranform adds
 	/// ".png" to the name you should not include ".png" when calling get().
 	pub async fn new(dvr: &Dvr, names: &[&str], name_transform: impl Fn(&str) -> String) -> Result<TextureHandler, String> {
-		let mut texture_futures: Vec<(String, Box<dyn Future<Output = Result<Texture, String>> + Unpin>)> = Vec::new();
+		let mut texture_futures: Vec<(String, String, TextureFuture)> = Vec::new();
 		for name in names {
-			texture_futures.push((name.to_string(), Box::new(dvr.load_texture_internal(&name_transform(name))?)));
+			let url = name_transform(name);
+			let future = Box::new(dvr.load_texture_internal(&url, true)?);
+			texture_futures.push((name.to_string(), url, future));
+		}
+		let mut textures: Vec<(String, String, Texture)> = Vec::new();
+		for (name, url, future) in texture_futures {
+			textures.push((name, url, future.await?));
+		}
+		Ok(TextureHandler::from_textures(textures))
+	}
+
+	/// Creates a texture handler from a list of names, URLs and textures.
+	pub(crate) fn from_textures(textures: Vec<(String, String, Texture)>) -> TextureHandler {
+		let origins = textures
+			.iter()
+			.map(|(_, url, texture)| TextureOrigin {
+				url: url.clone(),
+				texture: Rc::downgrade(&texture.data),
+			})
+			.collect();
+		TextureHandler {
+			textures: textures
+				.into_iter()
+				.map(|(name, _, texture)| (name, texture))
+				.collect(),
+			origins,
+			hot_reload: None,
 		}
+	}
+
+	/// Create a texture handler containing all textures in an asset bundle (see Bundle::fetch()).
+	/// The textures have the same names as in the bundle.
+	pub fn from_bundle(dvr: &Dvr, bundle: &Bundle) -> Result<TextureHandler, String> {
 		let mut textures: HashMap<String, Texture> = HashMap::new();
-		for (name, future) in texture_futures {
-			textures.insert(name, future.await?);
+		for name in bundle.names_of_kind(AssetKind::Texture) {
+			let texture = dvr.load_texture_raw(&bundle.get(name)?)
+				.map_err(|e| format!("Failed to load texture \"{}\" ({})", name, e))?;
+			textures.insert(name.to_string(), texture);
 		}
-		Ok(TextureHandler { textures })
+		Ok(TextureHandler {
+			textures,
+			origins: Vec::new(),
+			hot_reload: None,
+		})
+	}
+
+	/// Turns on hot reloading, which is meant to be used during development.
+	/// After this has been called, reload() can be used.
+	///
+	/// Only the textures of the handler are reloaded. A Font or FontSheet that was created
+	/// from them draws the new pixels, but font files (BMFont descriptors and TrueType
+	/// fonts) are not reloaded, so glyph positions and metrics only change when the Font
+	/// is created again. Fonts that load their own pages (e.g. Font::fetch_bmfont()) are
+	/// not reloaded at all.
+	pub fn enable_hot_reload(&mut self, dvr: &Dvr) {
+		self.hot_reload = Some(dvr.texture_factory());
+	}
+
+	/// Fetches all textures that were loaded from URLs again and replaces them in place once
+	/// they have loaded, so that all users of the textures (including FontSheets) get the new
+	/// versions. This includes textures that have been taken from the handler. Textures from
+	/// bundles are not reloaded. Requires enable_hot_reload() to have been called.
+	pub fn reload(&mut self) -> Result<(), String> {
+		let factory = self.hot_reload.as_ref().ok_or("Hot reloading is not enabled")?;
+		self.origins.retain(|origin| origin.texture.strong_count() > 0);
+		// Makes the browser fetch the images again instead of using its cache
+		let cache_buster = format!("dvr_reload={}", js_sys::Date::now());
+		for origin in &self.origins {
+			let separator = if origin.url.contains('?') { '&' } else { '?' };
+			let future = factory.load_texture(&format!("{}{}{}", origin.url, separator, cache_buster), true)?;
+			let target = origin.texture.clone();
+			let url = origin.url.clone();
+			wasm_bindgen_futures::spawn_local(async move {
+				match future.await {
+					Ok(texture) => if let Some(target) = target.upgrade() {
+						texture.replace(&target);
+					},
+					Err(e) => web_sys::console::error_1(&JsValue::from_str(&format!("Failed to reload \"{}\" ({})", url, e))),
+				}
+			});
+		}
+		Ok(())
 	}
 
 	/// Returns a texture if it exists. See also getr() for a version that returns a Result.
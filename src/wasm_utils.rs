@@ -1,6 +1,7 @@
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::convert::FromWasmAbi;
-use web_sys::EventTarget;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{EventTarget, Response};
 
 // pub fn log_errors_arg<T: FromWasmAbi + 'static>(mut f: impl FnMut(T) -> Result<(), JsValue>) -> impl FnMut(T) {
 //     move |t: T| {
@@ -28,4 +29,20 @@ pub fn add_event_listener<T: FromWasmAbi + 'static>(val: &EventTarget, event: &s
 pub fn js_val_err_to_string(e: JsValue) -> String {
     e.as_string()
         .unwrap_or_else(|| "Unknown JS error".to_string())
-}
\ No newline at end of file
+}
+
+pub async fn fetch_bytes(url: &str) -> Result<Vec<u8>, String> {
+    let window = web_sys::window().ok_or("Unable to get window")?;
+    let response: Response = JsFuture::from(window.fetch_with_str(url))
+        .await
+        .map_err(js_val_err_to_string)?
+        .dyn_into()
+        .map_err(|_| "Fetch did not return a Response")?;
+    if !response.ok() {
+        return Err(format!("Failed to fetch \"{}\" (status {})", url, response.status()));
+    }
+    let buffer = JsFuture::from(response.array_buffer().map_err(js_val_err_to_string)?)
+        .await
+        .map_err(js_val_err_to_string)?;
+    Ok(js_sys::Uint8Array::new(&buffer).to_vec())
+}
@@ -1,18 +1,16 @@
-use windows::{core::{Interface, PCSTR}, Win32::{Foundation::{GENERIC_READ, HMODULE, HWND, RECT}, Graphics::{Direct3D::{D3D11_PRIMITIVE_TOPOLOGY_TRIANGLELIST, D3D11_SRV_DIMENSION_TEXTURE2D, D3D_DRIVER_TYPE_HARDWARE}, Direct3D11::{D3D11CreateDeviceAndSwapChain, ID3D11BlendState, ID3D11Buffer, ID3D11Device, ID3D11DeviceContext, ID3D11InputLayout, ID3D11PixelShader, ID3D11RasterizerState, ID3D11RenderTargetView, ID3D11Resource, ID3D11SamplerState, ID3D11ShaderResourceView, ID3D11Texture2D, ID3D11VertexShader, D3D11_BIND_CONSTANT_BUFFER, D3D11_BIND_SHADER_RESOURCE, D3D11_BIND_VERTEX_BUFFER, D3D11_BLEND_DESC, D3D11_BLEND_INV_DEST_ALPHA, D3D11_BLEND_INV_SRC_ALPHA, D3D11_BLEND_ONE, D3D11_BLEND_OP_ADD, D3D11_BLEND_SRC_ALPHA, D3D11_BUFFER_DESC, D3D11_COLOR_WRITE_ENABLE_ALL, D3D11_CPU_ACCESS_WRITE, D3D11_CREATE_DEVICE_FLAG, D3D11_CULL_BACK, D3D11_FILL_SOLID, D3D11_FILTER_MIN_MAG_MIP_LINEAR, D3D11_INPUT_ELEMENT_DESC, D3D11_INPUT_PER_VERTEX_DATA, D3D11_MAP_WRITE_DISCARD, D3D11_RASTERIZER_DESC, D3D11_RENDER_TARGET_BLEND_DESC, D3D11_SAMPLER_DESC, D3D11_SDK_VERSION, D3D11_SHADER_RESOURCE_VIEW_DESC, D3D11_SHADER_RESOURCE_VIEW_DESC_0, D3D11_SUBRESOURCE_DATA, D3D11_TEX2D_SRV, D3D11_TEXTURE2D_DESC, D3D11_TEXTURE_ADDRESS_CLAMP, D3D11_USAGE_DEFAULT, D3D11_USAGE_DYNAMIC, D3D11_VIEWPORT}, Dxgi::{Common::{DXGI_FORMAT_B8G8R8A8_UNORM, DXGI_FORMAT_R32G32_FLOAT, DXGI_MODE_DESC, DXGI_MODE_SCALING_UNSPECIFIED, DXGI_MODE_SCANLINE_ORDER_UNSPECIFIED, DXGI_RATIONAL, DXGI_SAMPLE_DESC}, IDXGIAdapter, IDXGIDevice, IDXGIFactory, IDXGISwapChain, DXGI_MWA_NO_ALT_ENTER, DXGI_MWA_NO_PRINT_SCREEN, DXGI_MWA_NO_WINDOW_CHANGES, DXGI_PRESENT, DXGI_SWAP_CHAIN_DESC, DXGI_SWAP_EFFECT_DISCARD, DXGI_USAGE_RENDER_TARGET_OUTPUT}, Imaging::{CLSID_WICImagingFactory, GUID_WICPixelFormat32bppBGRA, IWICBitmapDecoder, IWICImagingFactory, IWICPixelFormatInfo, WICBitmapDitherTypeNone, WICBitmapPaletteTypeCustom, WICDecodeMetadataCacheOnDemand}}, System::Com::{CoCreateInstance, CLSCTX_INPROC_SERVER}, UI::{Shell::SHCreateMemStream, WindowsAndMessaging::GetClientRect}}};
-use std::{ffi::{c_float, CString}, ptr::{null, null_mut}};
-use windows_strings::*;
+use windows::{core::{s, Interface, PCSTR}, Win32::{Foundation::{HMODULE, HWND, RECT}, Graphics::{Direct3D::{Fxc::{D3DCompile, D3DCOMPILE_OPTIMIZATION_LEVEL3}, ID3DBlob, D3D11_PRIMITIVE_TOPOLOGY_TRIANGLELIST, D3D11_SRV_DIMENSION_TEXTURE2D, D3D_DRIVER_TYPE_HARDWARE}, Direct3D11::{D3D11CreateDeviceAndSwapChain, ID3D11BlendState, ID3D11Buffer, ID3D11Device, ID3D11DeviceContext, ID3D11InputLayout, ID3D11PixelShader, ID3D11RasterizerState, ID3D11RenderTargetView, ID3D11Resource, ID3D11SamplerState, ID3D11ShaderResourceView, ID3D11Texture2D, ID3D11VertexShader, D3D11_BIND_CONSTANT_BUFFER, D3D11_BIND_SHADER_RESOURCE, D3D11_BIND_VERTEX_BUFFER, D3D11_BLEND_DESC, D3D11_BLEND_INV_DEST_ALPHA, D3D11_BLEND_INV_SRC_ALPHA, D3D11_BLEND_ONE, D3D11_BLEND_OP_ADD, D3D11_BLEND_SRC_ALPHA, D3D11_BOX, D3D11_BUFFER_DESC, D3D11_COLOR_WRITE_ENABLE_ALL, D3D11_CPU_ACCESS_WRITE, D3D11_CREATE_DEVICE_FLAG, D3D11_CULL_BACK, D3D11_FILL_SOLID, D3D11_FILTER_MIN_MAG_MIP_LINEAR, D3D11_INPUT_ELEMENT_DESC, D3D11_INPUT_PER_VERTEX_DATA, D3D11_MAP_WRITE_DISCARD, D3D11_RASTERIZER_DESC, D3D11_RENDER_TARGET_BLEND_DESC, D3D11_SAMPLER_DESC, D3D11_SDK_VERSION, D3D11_SHADER_RESOURCE_VIEW_DESC, D3D11_SHADER_RESOURCE_VIEW_DESC_0, D3D11_SUBRESOURCE_DATA, D3D11_TEX2D_SRV, D3D11_TEXTURE2D_DESC, D3D11_TEXTURE_ADDRESS_CLAMP, D3D11_USAGE_DEFAULT, D3D11_USAGE_DYNAMIC, D3D11_VIEWPORT}, Dxgi::{Common::{DXGI_FORMAT_B8G8R8A8_UNORM, DXGI_FORMAT_R8G8B8A8_UNORM, DXGI_FORMAT_R32G32_FLOAT, DXGI_FORMAT_R32G32B32A32_FLOAT, DXGI_MODE_DESC, DXGI_MODE_SCALING_UNSPECIFIED, DXGI_MODE_SCANLINE_ORDER_UNSPECIFIED, DXGI_RATIONAL, DXGI_SAMPLE_DESC}, IDXGIAdapter, IDXGIDevice, IDXGIFactory, IDXGISwapChain, DXGI_MWA_NO_ALT_ENTER, DXGI_MWA_NO_PRINT_SCREEN, DXGI_MWA_NO_WINDOW_CHANGES, DXGI_PRESENT, DXGI_SWAP_CHAIN_DESC, DXGI_SWAP_EFFECT_DISCARD, DXGI_USAGE_RENDER_TARGET_OUTPUT}, Imaging::{CLSID_WICImagingFactory, GUID_WICPixelFormat32bppRGBA, IWICBitmapDecoder, IWICImagingFactory, IWICPixelFormatInfo, WICBitmapDitherTypeNone, WICBitmapPaletteTypeCustom, WICDecodeMetadataCacheOnDemand}}, System::Com::{CoCreateInstance, CLSCTX_INPROC_SERVER}, UI::{Shell::SHCreateMemStream, WindowsAndMessaging::GetClientRect}}};
+use std::{cell::RefCell, collections::HashMap, ffi::{c_float, c_void, CString}, ptr::{null, null_mut}, rc::{Rc, Weak}, time::{Duration, Instant, SystemTime}};
 use directx_math::*;
-use crate::{win_utils::*, DvrCtx};
+use crate::{batch::{batch_scale, batch_vertices, BatchQuad, VERTEX_FLOATS}, bundle::{AssetKind, Bundle}, cache::Asset, image::{Image, ImageFormat}, resources::{texture_memory, ResourceKind, ResourceStats, ResourceTracker, TrackedResource}, effects::{EffectParams, EffectPass, EffectQuad, TextEffects}, quad::Quad, sdf::DistanceField, win_utils::*, DvrCtx};
 
 mod shader_data;
 
 pub struct Dvr {
 	_com_init: ComInit,
 	swap: IDXGISwapChain,
-	device: ID3D11Device,
 	context: ID3D11DeviceContext,
 	swapchain: Option<SwapChain>,
-	wic_factory: IWICImagingFactory,
+	texture_factory: TextureFactory,
 	hwnd: HWND,
 }
 
@@ -83,10 +81,12 @@ impl Dvr {
 					.map_err(winerr_map("Failed to make window associations"))?;
 			}
 
+			let resources = ResourceTracker::default();
 			let swapchain = SwapChain::new(
 				&swap,
 				&device,
 				&context,
+				&resources,
 				ctx,
 				500.0,
 				250.0
@@ -98,13 +98,18 @@ impl Dvr {
 				CLSCTX_INPROC_SERVER
 			).map_err(winerr_map("Failed to create WIC factory"))?;
 
+			let texture_factory = TextureFactory {
+				device,
+				wic_factory,
+				resources,
+			};
+
 			Ok(Dvr {
 				_com_init: com_init,
 				swap: swap,
-				device: device,
 				context: context,
 				swapchain: Some(swapchain),
-				wic_factory,
+				texture_factory,
 				hwnd: ctx,
 			})
 		}
@@ -139,6 +144,29 @@ impl Dvr {
 	}
 
 	pub fn draw(&self, texture: &Texture, x: f32, y: f32, size: Option<(f32, f32)>, tex_pos_size: Option<((f32, f32), (f32, f32))>, angle: f32) -> Result<(), String> {
+		self.draw_quad(texture, &Quad::new(x, y, size, tex_pos_size, angle), Fragment::Tinted((1.0, 1.0, 1.0, 1.0)))
+	}
+
+	/// Like draw() but the colour of the texture is multiplied by colour (red, green, blue
+	/// and alpha, from 0 to 1).
+	pub fn draw_tinted(&self, texture: &Texture, quad: &Quad, colour: (f32, f32, f32, f32)) -> Result<(), String> {
+		self.draw_quad(texture, quad, Fragment::Tinted(colour))
+	}
+
+	/// Like draw() but the texture is a distance field, which is drawn with effects. This
+	/// stays sharp at any size, so e.g. text of any size can be drawn from the same texture.
+	pub fn draw_sdf(&self, texture: &Texture, quad: &Quad, field: DistanceField, effects: &TextEffects) -> Result<(), String> {
+		self.draw_quad(texture, quad, Fragment::Effects(Some(field), effects, EffectPass::All))
+	}
+
+	/// Like draw() but with an outline, shadow and glow around the alpha of the texture
+	/// (see TextEffects), which are drawn in the same draw call.
+	pub fn draw_with_effects(&self, texture: &Texture, quad: &Quad, effects: &TextEffects) -> Result<(), String> {
+		self.draw_quad(texture, quad, Fragment::Effects(None, effects, EffectPass::All))
+	}
+
+	fn draw_quad(&self, texture: &Texture, quad: &Quad, fragment: Fragment) -> Result<(), String> {
+		let Quad { x, y, size, tex_pos_size, angle } = *quad;
 		let (width, height): (f32, f32) = match size {
 			Some(size) => size,
 			None => (texture.get_width() as f32, texture.get_height() as f32),
@@ -147,109 +175,298 @@ impl Dvr {
 			Some(pos_size) => pos_size,
 			None => ((0.0, 0.0), (texture.get_width() as f32, texture.get_height() as f32)),
 		};
+		// The quad is made larger so that there is room for the effects
+		let effect_quad = match fragment {
+			Fragment::Tinted(_) => None,
+			Fragment::Effects(_, effects, _) => Some(EffectQuad::new(effects, (width, height), ((src_x, src_y), (src_width, src_height)))),
+		};
+		let ((width, height), ((src_x, src_y), (src_width, src_height))) = match &effect_quad {
+			Some(quad) => (quad.size, quad.tex_rect),
+			None => ((width, height), ((src_x, src_y), (src_width, src_height))),
+		};
 		unsafe {
-			self.context.PSSetShaderResources(0, Some(&texture.tex_view_arr));
-
 			let swapchain = self.get_swapchain()?;
 
-			let mut msr_ps = Default::default();
-			self.context.Map(
-				&swapchain.colour_shift_buffer,
-				0,
-				D3D11_MAP_WRITE_DISCARD,
-				0,
-				Some(&mut msr_ps)
-			).map_err(winerr_map("Failed to map colour shift buffer"))?;
-
-			// TODO: Replace with actual colour shift
-			let clr_shift: [c_float; 4] = [1.0, 1.0, 1.0, 1.0];
-			std::ptr::copy_nonoverlapping(
-				clr_shift.as_ptr(),
-				msr_ps.pData as *mut c_float,
-				clr_shift.len()
-			);
-			self.context.Unmap(&swapchain.colour_shift_buffer, 0);
+			let scale = 0.5 * (width / src_width + height / src_height);
+			let (tw, th) = (texture.get_width() as f32, texture.get_height() as f32);
+			let ((cx, cy), (cw, ch)) = effect_quad.as_ref().map(|quad| quad.clamp_rect).unwrap_or_default();
+			self.set_fragment(swapchain, texture, fragment, scale, [cx / tw, cy / th, (cx + cw) / tw, (cy + ch) / th])?;
+
+			let scaling_factor = Self::get_scaling_factor(swapchain);
+			self.set_matrices(
+				swapchain,
+				XMMatrix(XMMatrixScaling(width, height, 1.0)) *
+				XMMatrix(XMMatrixRotationZ(angle)) *
+				XMMatrix(XMMatrixTranslation(x * 2.0, y * 2.0, 0.0)) *
+				XMMatrix(XMMatrixScaling(
+					1.0 / scaling_factor / swapchain.width,
+					1.0 / scaling_factor / swapchain.height,
+					1.0
+				)),
+				XMMatrix(XMMatrixScaling(
+					src_width / tw,
+					src_height / th,
+					1.0
+				)) *
+				XMMatrix(XMMatrixTranslation(
+					src_x / tw,
+					src_y / th,
+					0.0
+				))
+			)?;
 
-			// TODO: actual desired size
-			let desired_width = 500.0;
-			let desired_height = 250.0;
+			self.context.Draw(6, 0);
+		}
+		Ok(())
+	}
 
-			let scaling_factor = f32::min(desired_width / swapchain.width, desired_height / swapchain.height);
+	/// Makes a batch of quads that are drawn from a texture with one draw call.
+	pub(crate) fn create_quad_batch(&self, texture: &Texture, quads: &[BatchQuad]) -> Result<QuadBatch, String> {
+		let vertices = batch_vertices(quads);
+		if vertices.is_empty() {
+			return Err("A batch needs at least one quad".to_string());
+		}
+		let bd = D3D11_BUFFER_DESC {
+			ByteWidth: size_of_val(vertices.as_slice()) as u32,
+			Usage: D3D11_USAGE_DEFAULT,
+			BindFlags: D3D11_BIND_VERTEX_BUFFER.0 as u32,
+			StructureByteStride: (VERTEX_FLOATS * size_of::<c_float>()) as u32,
+			..Default::default()
+		};
+		let srd = D3D11_SUBRESOURCE_DATA {
+			pSysMem: vertices.as_ptr() as *const c_void,
+			..Default::default()
+		};
+		let mut buffer: Option<ID3D11Buffer> = None;
+		unsafe {
+			self.texture_factory.device.CreateBuffer(&bd, Some(&srd), Some(&mut buffer))
+				.map_err(winerr_map("Failed to create vertex buffer"))?;
+		}
+		Ok(QuadBatch {
+			texture: texture.share(),
+			buffer: buffer.ok_or("Vertex buffer was not created")?,
+			vertex_count: (vertices.len() / VERTEX_FLOATS) as u32,
+			scale: batch_scale(quads),
+			_tracked: self.texture_factory.resources.track(ResourceKind::Buffer, bd.ByteWidth as usize),
+		})
+	}
 
-			#[repr(C)]
-			struct Mtcs {
-				mtx: XMMATRIX,
-				tex_mtx: XMMATRIX,
-			}
-			let mtcs = Mtcs {
-				mtx: XMMatrixTranspose(
-					*(XMMatrix(XMMatrixScaling(width, height, 1.0)) *
-					XMMatrix(XMMatrixRotationZ(angle)) *
-					XMMatrix(XMMatrixTranslation(x * 2.0, y * 2.0, 0.0)) *
-					XMMatrix(XMMatrixScaling(
-						1.0 / scaling_factor / swapchain.width,
-						1.0 / scaling_factor / swapchain.height,
-						1.0
-					)))
-				),
-				tex_mtx: XMMatrixTranspose(
-					*(XMMatrix(XMMatrixScaling(
-						src_width / texture.get_width() as f32,
-						src_height / texture.get_height() as f32,
-						1.0
-					)) *
-					XMMatrix(XMMatrixTranslation(
-						src_x / texture.get_width() as f32,
-						src_y / texture.get_height() as f32,
-						0.0
-					)))
-				)
-			};
+	/// Draws a part of a texture at a quad (centre and size) like draw_sdf() if there is a
+	/// distance field, and otherwise like draw_tinted() or draw_with_effects(), but only the
+	/// parts of the effects of the pass.
+	pub(crate) fn draw_effect_pass(&self, texture: &Texture, (pos, size): ((f32, f32), (f32, f32)), tex_rect: ((f32, f32), (f32, f32)), field: Option<DistanceField>, effects: &TextEffects, pass: EffectPass) -> Result<(), String> {
+		self.draw_quad(texture, &Quad::new(pos.0, pos.1, Some(size), Some(tex_rect), 0.0), Fragment::new(field, effects, pass))
+	}
 
-			let mut msr = Default::default();
-			self.context.Map(
-				&swapchain.matrix_buffer,
-				0,
-				D3D11_MAP_WRITE_DISCARD,
-				0,
-				Some(&mut msr)
-			).map_err(winerr_map("Failed to map matrix buffer"))?;
+	/// Draws all quads of a batch at (x, y) like draw_effect_pass() draws a quad.
+	pub(crate) fn draw_quad_batch(&self, batch: &QuadBatch, (x, y): (f32, f32), field: Option<DistanceField>, effects: &TextEffects, pass: EffectPass) -> Result<(), String> {
+		let fragment = Fragment::new(field, effects, pass);
+		unsafe {
+			let swapchain = self.get_swapchain()?;
+			self.set_fragment(swapchain, &batch.texture, fragment, batch.scale, [0.0, 0.0, 1.0, 1.0])?;
+
+			// The vertices are in Dvr units relative to (x, y) and the texture coordinates are
+			// in texture pixels
+			let scaling_factor = Self::get_scaling_factor(swapchain);
+			let (tw, th) = batch.texture.get_size();
+			self.set_matrices(
+				swapchain,
+				XMMatrix(XMMatrixScaling(2.0, 2.0, 1.0)) *
+				XMMatrix(XMMatrixTranslation(x * 2.0, y * 2.0, 0.0)) *
+				XMMatrix(XMMatrixScaling(
+					1.0 / scaling_factor / swapchain.width,
+					1.0 / scaling_factor / swapchain.height,
+					1.0
+				)),
+				XMMatrix(XMMatrixScaling(1.0 / tw as f32, 1.0 / th as f32, 1.0))
+			)?;
 
-			std::ptr::copy_nonoverlapping(
-				&mtcs as *const Mtcs as *const u8,
-				msr.pData as *mut u8,
-				std::mem::size_of::<Mtcs>()
-			);
-			self.context.Unmap(&swapchain.matrix_buffer, 0);
+			let stride = (VERTEX_FLOATS * size_of::<c_float>()) as u32;
+			let offset = 0;
+			self.context.IASetVertexBuffers(0, 1, Some(&Some(batch.buffer.clone())), Some(&stride), Some(&offset));
+			self.context.Draw(batch.vertex_count, 0);
+			// Quads that are not in a batch use the vertex buffer of the swap chain
+			let stride = swapchain.vertex_stride;
+			self.context.IASetVertexBuffers(0, 1, Some(&Some(swapchain.vertex_buffer.clone())), Some(&stride), Some(&offset));
+		}
+		Ok(())
+	}
 
-			self.context.Draw(6, 0);
+	// TODO: actual desired size
+	fn get_scaling_factor(swapchain: &SwapChain) -> f32 {
+		let desired_width = 500.0;
+		let desired_height = 250.0;
+		f32::min(desired_width / swapchain.width, desired_height / swapchain.height)
+	}
+
+	/// Binds the texture and sets the pixel shader and its constant buffers. Scale is the
+	/// number of Dvr units per texture pixel and tex_rect is the part of the texture that
+	/// the effects sample, in texture coordinates (min and max).
+	unsafe fn set_fragment(&self, swapchain: &SwapChain, texture: &Texture, fragment: Fragment, scale: f32, tex_rect: [c_float; 4]) -> Result<(), String> {
+		self.context.PSSetShaderResources(0, Some(&texture.data.borrow().tex_view_arr));
+
+		let mut msr_ps = Default::default();
+		self.context.Map(
+			&swapchain.colour_shift_buffer,
+			0,
+			D3D11_MAP_WRITE_DISCARD,
+			0,
+			Some(&mut msr_ps)
+		).map_err(winerr_map("Failed to map colour shift buffer"))?;
+
+		let clr_shift: [c_float; 4] = match fragment {
+			Fragment::Tinted(colour) => {
+				self.context.PSSetShader(&swapchain.pixel_shader, None);
+				[colour.0, colour.1, colour.2, colour.3]
+			},
+			Fragment::Effects(field, effects, pass) => {
+				self.context.PSSetShader(&swapchain.effects_pixel_shader, None);
+				// A Dvr unit is 1 / scaling_factor pixels
+				let params = EffectParams::new(effects, field, scale / Self::get_scaling_factor(swapchain), pass);
+				let (tw, th) = (texture.get_width() as f32, texture.get_height() as f32);
+				// The texture coordinates go down, unlike Dvr coordinates
+				let (ox, oy) = effects.shadow_offset;
+				let effect_data = EffectData {
+					outline_colour: params.outline_colour,
+					shadow_colour: params.shadow_colour,
+					glow_colour: params.glow_colour,
+					tex_rect,
+					shadow_offset: [ox / tw, -oy / th],
+					texel_size: [1.0 / tw, 1.0 / th],
+					px_range: params.px_range,
+					outline_width: params.outline_width,
+					shadow_blur: params.shadow_blur,
+					glow_width: params.glow_width,
+					multi_channel: params.multi_channel,
+					mode: params.mode,
+					pass: params.pass,
+					_padding: [0.0; 1],
+				};
+				let mut msr_effects = Default::default();
+				self.context.Map(
+					&swapchain.effects_buffer,
+					0,
+					D3D11_MAP_WRITE_DISCARD,
+					0,
+					Some(&mut msr_effects)
+				).map_err(winerr_map("Failed to map effects buffer"))?;
+				std::ptr::copy_nonoverlapping(
+					&effect_data as *const EffectData as *const u8,
+					msr_effects.pData as *mut u8,
+					size_of::<EffectData>()
+				);
+				self.context.Unmap(&swapchain.effects_buffer, 0);
+				params.colour
+			},
+		};
+		std::ptr::copy_nonoverlapping(
+			clr_shift.as_ptr(),
+			msr_ps.pData as *mut c_float,
+			clr_shift.len()
+		);
+		self.context.Unmap(&swapchain.colour_shift_buffer, 0);
+		Ok(())
+	}
+
+	/// Sets the matrices of the vertex shader, which are transposed for it.
+	unsafe fn set_matrices(&self, swapchain: &SwapChain, mtx: XMMatrix, tex_mtx: XMMatrix) -> Result<(), String> {
+		#[repr(C)]
+		struct Mtcs {
+			mtx: XMMATRIX,
+			tex_mtx: XMMATRIX,
 		}
+		let mtcs = Mtcs {
+			mtx: XMMatrixTranspose(*mtx),
+			tex_mtx: XMMatrixTranspose(*tex_mtx),
+		};
+
+		let mut msr = Default::default();
+		self.context.Map(
+			&swapchain.matrix_buffer,
+			0,
+			D3D11_MAP_WRITE_DISCARD,
+			0,
+			Some(&mut msr)
+		).map_err(winerr_map("Failed to map matrix buffer"))?;
+
+		std::ptr::copy_nonoverlapping(
+			&mtcs as *const Mtcs as *const u8,
+			msr.pData as *mut u8,
+			std::mem::size_of::<Mtcs>()
+		);
+		self.context.Unmap(&swapchain.matrix_buffer, 0);
 		Ok(())
 	}
 
 	pub /* async */ fn load_texture(&self, filename: &str) -> Result<Texture, String> {
-		unsafe {
-			let decoder = self.wic_factory.CreateDecoderFromFilename(
-				&HSTRING::from(filename),
-				None,
-				GENERIC_READ,
-				WICDecodeMetadataCacheOnDemand
-			).map_err(winerr_map("Failed to create decoder for image file"))?;
-			Texture::new(decoder, &self.device, &self.wic_factory)
-		}
+		self.texture_factory.load_texture(filename)
 	}
 
+	/// Loads a texture from the bytes of an image file. Formats supported by the built-in
+	/// decoder (see image::ImageFormat) are decoded by it so that they look the same as on
+	/// other platforms, and other formats are decoded with WIC.
 	pub /* async */ fn load_texture_raw(&self, data: &[u8]) -> Result<Texture, String> {
+		self.texture_factory.load_texture_raw(data)
+	}
+
+	/// Loads a texture whose channels are data rather than colours, such as a distance field.
+	/// Textures are never premultiplied on Windows, so this is the same as load_texture().
+	pub /* async */ fn load_data_texture(&self, filename: &str) -> Result<Texture, String> {
+		self.load_texture(filename)
+	}
+
+	/// Like load_data_texture(), from the bytes of an image file.
+	pub /* async */ fn load_data_texture_raw(&self, data: &[u8]) -> Result<Texture, String> {
+		self.load_texture_raw(data)
+	}
+
+	/// Creates a texture from an already decoded image.
+	pub fn load_texture_image(&self, image: Image) -> Result<Texture, String> {
+		self.texture_factory.load_texture_image(image)
+	}
+
+	/// Creates a texture that is changed with update_texture().
+	pub(crate) fn load_texture_for_updates(&self, image: &Image) -> Result<Texture, String> {
+		Texture::new(image, &self.texture_factory.device, &self.texture_factory.resources)
+	}
+
+	/// Replaces a part of a texture, with its top left corner at (x, y).
+	pub(crate) fn update_texture(&self, texture: &Texture, x: u32, y: u32, image: &Image) -> Result<(), String> {
+		let data = texture.data.borrow();
+		let (width, height) = image.get_size();
+		if x + width > data.size.0 || y + height > data.size.1 {
+			return Err("The image does not fit in the texture".to_string());
+		}
+		let dest = D3D11_BOX {
+			left: x,
+			top: y,
+			front: 0,
+			right: x + width,
+			bottom: y + height,
+			back: 1,
+		};
 		unsafe {
-			let stream = SHCreateMemStream(Some(data))
-				.ok_or("Failed to create IStream")?;
-			let decoder = self.wic_factory.CreateDecoderFromStream(
-				&stream,
-				null(),
-				WICDecodeMetadataCacheOnDemand
-			).map_err(winerr_map("Failed to create decoder for image"))?;
-			Texture::new(decoder, &self.device, &self.wic_factory)
+			self.context.UpdateSubresource(
+				&data.tex,
+				0,
+				Some(&dest),
+				image.pixels().as_ptr() as *const c_void,
+				width * 4,
+				0
+			);
 		}
+		Ok(())
+	}
+
+	pub(crate) fn texture_factory(&self) -> TextureFactory {
+		self.texture_factory.clone()
+	}
+
+	/// Returns the number of live GPU resources and an estimate of their memory usage.
+	/// Textures are released when the last Texture using them is dropped, and the
+	/// shaders and buffers are released when the Dvr is dropped.
+	pub fn resource_stats(&self) -> ResourceStats {
+		self.texture_factory.resources.stats()
 	}
 
 	fn get_swapchain(&self) -> Result<&SwapChain, String> {
@@ -261,23 +478,115 @@ impl Dvr {
 	}
 }
 
+/// The parts of the Dvr that are needed to create textures. This can be cloned so that
+/// textures can be created without access to the Dvr, e.g. by a Loader.
+#[derive(Clone)]
+pub(crate) struct TextureFactory {
+	device: ID3D11Device,
+	wic_factory: IWICImagingFactory,
+	resources: ResourceTracker,
+}
+
+impl TextureFactory {
+	pub fn load_texture(&self, filename: &str) -> Result<Texture, String> {
+		let data = std::fs::read(filename)
+			.map_err(|e| format!("Failed to read image file \"{}\" ({})", filename, e))?;
+		self.load_texture_raw(&data)
+	}
+
+	pub fn load_texture_raw(&self, data: &[u8]) -> Result<Texture, String> {
+		let image = match ImageFormat::detect(data) {
+			Some(_) => Image::decode(data)?,
+			None => self.decode_with_wic(data)?,
+		};
+		self.load_texture_image(image)
+	}
+
+	pub fn load_texture_image(&self, image: Image) -> Result<Texture, String> {
+		Texture::new(&image, &self.device, &self.resources)
+	}
+
+	fn decode_with_wic(&self, data: &[u8]) -> Result<Image, String> {
+		unsafe {
+			let stream = SHCreateMemStream(Some(data))
+				.ok_or("Failed to create IStream")?;
+			let decoder = self.wic_factory.CreateDecoderFromStream(
+				&stream,
+				null(),
+				WICDecodeMetadataCacheOnDemand
+			).map_err(winerr_map("Failed to create decoder for image"))?;
+			decode_wic_image(decoder, &self.wic_factory)
+		}
+	}
+}
+
 struct SwapChain {
 	width: c_float,
 	height: c_float,
 	target: ID3D11RenderTargetView,
-	_vertex_buffer: ID3D11Buffer,
+	vertex_buffer: ID3D11Buffer,
+	vertex_stride: u32,
 	colour_shift_buffer: ID3D11Buffer,
 	matrix_buffer: ID3D11Buffer,
-	_pixel_shader: ID3D11PixelShader,
+	effects_buffer: ID3D11Buffer,
+	pixel_shader: ID3D11PixelShader,
+	effects_pixel_shader: ID3D11PixelShader,
 	_vertex_shader: ID3D11VertexShader,
 	_input_layout: ID3D11InputLayout,
 	_blend_state: ID3D11BlendState,
 	_rasterizer_state: ID3D11RasterizerState,
 	_sampler_state: ID3D11SamplerState,
+	_tracked: [TrackedResource; 6],
+}
+
+/// How the fragments of a quad are coloured.
+enum Fragment<'a> {
+	Tinted((f32, f32, f32, f32)),
+	/// Effects around the alpha of the texture, or around a distance field if there is one.
+	Effects(Option<DistanceField>, &'a TextEffects, EffectPass),
+}
+
+impl<'a> Fragment<'a> {
+	/// The cheapest way to draw a pass of effects that gives the same result.
+	fn new(field: Option<DistanceField>, effects: &'a TextEffects, pass: EffectPass) -> Fragment<'a> {
+		match field {
+			None if effects.is_plain() => Fragment::Tinted(effects.colour),
+			field => Fragment::Effects(field, effects, pass),
+		}
+	}
+}
+
+/// Quads that are drawn from the same texture with one draw call (see Dvr::create_quad_batch()).
+pub(crate) struct QuadBatch {
+	texture: Texture,
+	buffer: ID3D11Buffer,
+	vertex_count: u32,
+	/// The number of Dvr units per texture pixel.
+	scale: f32,
+	_tracked: TrackedResource,
+}
+
+/// The constant buffer of the effects pixel shader (EffectBuf), with its padding.
+#[repr(C)]
+struct EffectData {
+	outline_colour: [c_float; 4],
+	shadow_colour: [c_float; 4],
+	glow_colour: [c_float; 4],
+	tex_rect: [c_float; 4],
+	shadow_offset: [c_float; 2],
+	texel_size: [c_float; 2],
+	px_range: c_float,
+	outline_width: c_float,
+	shadow_blur: c_float,
+	glow_width: c_float,
+	multi_channel: c_float,
+	mode: i32,
+	pass: i32,
+	_padding: [c_float; 1],
 }
 
 impl SwapChain {
-	fn new(swap: &IDXGISwapChain, device: &ID3D11Device, context: &ID3D11DeviceContext, hwnd: HWND, desired_width: c_float, desired_height: c_float) -> Result<SwapChain, String> {
+	fn new(swap: &IDXGISwapChain, device: &ID3D11Device, context: &ID3D11DeviceContext, resources: &ResourceTracker, hwnd: HWND, desired_width: c_float, desired_height: c_float) -> Result<SwapChain, String> {
 		unsafe {
 			let backbuffer: ID3D11Resource = swap.GetBuffer(0)
 				.map_err(winerr_map("Failed to get back buffer"))?;
@@ -286,20 +595,24 @@ impl SwapChain {
 			device.CreateRenderTargetView(&backbuffer, None, Some(&mut target))
 				.map_err(winerr_map("Failed to create render target view"))?;
 
+			// The same layout as the vertices of a batch (see batch_vertices()), where the quad
+			// gets all of its texture rectangle, which is limited by the effects constant buffer
 			#[repr(C)]
 			struct Vertex {
 				_x: c_float,
 				_y: c_float,
 				_u: c_float,
 				_v: c_float,
+				_rect: [c_float; 4],
 			}
+			const RECT: [c_float; 4] = [0.0, 0.0, 1.0, 1.0];
 			let vertices = [
-				Vertex { _x: -1.0, _y:  1.0, _u: 0.0, _v: 0.0 },
-				Vertex { _x:  1.0, _y:  1.0, _u: 1.0, _v: 0.0 },
-				Vertex { _x:  1.0, _y: -1.0, _u: 1.0, _v: 1.0 },
-				Vertex { _x: -1.0, _y:  1.0, _u: 0.0, _v: 0.0 },
-				Vertex { _x:  1.0, _y: -1.0, _u: 1.0, _v: 1.0 },
-				Vertex { _x: -1.0, _y: -1.0, _u: 0.0, _v: 1.0 },
+				Vertex { _x: -1.0, _y:  1.0, _u: 0.0, _v: 0.0, _rect: RECT },
+				Vertex { _x:  1.0, _y:  1.0, _u: 1.0, _v: 0.0, _rect: RECT },
+				Vertex { _x:  1.0, _y: -1.0, _u: 1.0, _v: 1.0, _rect: RECT },
+				Vertex { _x: -1.0, _y:  1.0, _u: 0.0, _v: 0.0, _rect: RECT },
+				Vertex { _x:  1.0, _y: -1.0, _u: 1.0, _v: 1.0, _rect: RECT },
+				Vertex { _x: -1.0, _y: -1.0, _u: 0.0, _v: 1.0, _rect: RECT },
 			];
 			let bd = D3D11_BUFFER_DESC {
 				ByteWidth: (size_of::<Vertex>() * vertices.len()) as u32,
@@ -391,11 +704,28 @@ impl SwapChain {
 				pixel_shader.as_ref().ok_or("Pixel shader was not created")?,
 				None
 			);
+			let effects_pixel_shader = Self::compile_pixel_shader(device, shader_data::EFFECTS_PIXEL_SHADER_SOURCE)?;
 
+			let sbd = D3D11_BUFFER_DESC {
+				BindFlags: D3D11_BIND_CONSTANT_BUFFER.0 as u32,
+				Usage: D3D11_USAGE_DYNAMIC,
+				CPUAccessFlags: D3D11_CPU_ACCESS_WRITE.0 as u32,
+				ByteWidth: size_of::<EffectData>() as u32,
+				..Default::default()
+			};
+			let mut effects_buffer: Option<ID3D11Buffer> = None;
+			device.CreateBuffer(&sbd, None, Some(&mut effects_buffer))
+				.map_err(winerr_map("Failed to create effects buffer"))?;
+			let mut sb_arr = [effects_buffer];
+			context.PSSetConstantBuffers(1, Some(&sb_arr));
+			let effects_buffer = sb_arr[0].take();
+
+			let vertex_shader_code = Self::compile_shader(shader_data::VERTEX_SHADER_SOURCE, s!("vs_4_0"))?;
+			let vertex_shader_code = blob_bytes(&vertex_shader_code);
 			let mut vertex_shader: Option<ID3D11VertexShader> = None;
 			device.CreateVertexShader(
-				&shader_data::VERTEX_SHADER_DATA, 
-				None, 
+				vertex_shader_code,
+				None,
 				Some(&mut vertex_shader)
 			).map_err(winerr_map("Failed to create vertex shader"))?;
 			context.VSSetShader(
@@ -407,6 +737,8 @@ impl SwapChain {
 				.map_err(|_| "Failed to create C string")?;
 			let texcoord_cstr = CString::new("TexCoord")
 				.map_err(|_| "Failed to create C string")?;
+			let texrect_cstr = CString::new("TexRect")
+				.map_err(|_| "Failed to create C string")?;
 			let ied = [
 				D3D11_INPUT_ELEMENT_DESC {
 					SemanticName: PCSTR(position_cstr.as_ptr() as *const u8),
@@ -426,9 +758,18 @@ impl SwapChain {
 					InputSlotClass: D3D11_INPUT_PER_VERTEX_DATA,
 					InstanceDataStepRate: 0,
 				},
+				D3D11_INPUT_ELEMENT_DESC {
+					SemanticName: PCSTR(texrect_cstr.as_ptr() as *const u8),
+					SemanticIndex: 0,
+					Format: DXGI_FORMAT_R32G32B32A32_FLOAT,
+					InputSlot: 0,
+					AlignedByteOffset: 16,
+					InputSlotClass: D3D11_INPUT_PER_VERTEX_DATA,
+					InstanceDataStepRate: 0,
+				},
 			];
 			let mut input_layout: Option<ID3D11InputLayout> = None;
-			device.CreateInputLayout(&ied, &shader_data::VERTEX_SHADER_DATA, Some(&mut input_layout))
+			device.CreateInputLayout(&ied, vertex_shader_code, Some(&mut input_layout))
 				.map_err(winerr_map("Failed to create input layout"))?;
 			context.IASetInputLayout(input_layout.as_ref().ok_or("Input layout was not created")?);
 
@@ -531,114 +872,92 @@ impl SwapChain {
 				width: width as f32,
 				height: height as f32,
 				target: target.ok_or("Target was not created")?,
-				_vertex_buffer: vertex_buffer.ok_or("Vertex buffer was not created")?,
+				vertex_buffer: vertex_buffer.ok_or("Vertex buffer was not created")?,
+				vertex_stride: stride,
 				colour_shift_buffer: colour_shift_buffer.ok_or("Colour shift buffer was not created")?,
 				matrix_buffer: matrix_buffer.ok_or("Matrix buffer was not created")?,
-				_pixel_shader: pixel_shader.ok_or("Pixel shader was not created")?,
+				effects_buffer: effects_buffer.ok_or("Effects buffer was not created")?,
+				pixel_shader: pixel_shader.ok_or("Pixel shader was not created")?,
+				effects_pixel_shader,
 				_vertex_shader: vertex_shader.ok_or("Vertex shader was not created")?,
 				_input_layout: input_layout.ok_or("Input layout was not created")?,
 				_blend_state: blend_state.ok_or("Blend state was not created")?,
 				_rasterizer_state: rasterizer_state.ok_or("Rasterizer state was not created")?,
 				_sampler_state: sampler_state.ok_or("Sampler state was not created")?,
+				_tracked: [
+					resources.track(ResourceKind::Program, 0),
+					resources.track(ResourceKind::Buffer, bd.ByteWidth as usize),
+					resources.track(ResourceKind::Buffer, mbd_ps.ByteWidth as usize),
+					resources.track(ResourceKind::Buffer, mbd.ByteWidth as usize),
+					resources.track(ResourceKind::Program, 0),
+					resources.track(ResourceKind::Buffer, sbd.ByteWidth as usize),
+				],
 			})
 		}
 	}
+
+	/// Compiles a pixel shader from HLSL source with the shader compiler that comes with Windows.
+	unsafe fn compile_pixel_shader(device: &ID3D11Device, source: &str) -> Result<ID3D11PixelShader, String> {
+		let code = Self::compile_shader(source, s!("ps_4_0"))?;
+		let mut pixel_shader: Option<ID3D11PixelShader> = None;
+		device.CreatePixelShader(blob_bytes(&code), None, Some(&mut pixel_shader))
+			.map_err(winerr_map("Failed to create pixel shader"))?;
+		pixel_shader.ok_or("Pixel shader was not created".to_string())
+	}
+
+	/// Compiles a shader from HLSL source for a target, e.g. "vs_4_0" for a vertex shader.
+	unsafe fn compile_shader(source: &str, target: PCSTR) -> Result<ID3DBlob, String> {
+		let mut code: Option<ID3DBlob> = None;
+		let mut errors: Option<ID3DBlob> = None;
+		let result = D3DCompile(
+			source.as_ptr() as *const c_void,
+			source.len(),
+			PCSTR::null(),
+			None,
+			None,
+			s!("main"),
+			target,
+			D3DCOMPILE_OPTIMIZATION_LEVEL3,
+			0,
+			&mut code,
+			Some(&mut errors)
+		);
+		if let Err(err) = result {
+			let message = match errors {
+				Some(errors) => String::from_utf8_lossy(blob_bytes(&errors)).into_owned(),
+				None => err.message(),
+			};
+			return Err(format!("Failed to compile shader: {}", message));
+		}
+		code.ok_or("Shader was not compiled".to_string())
+	}
 }
 
 pub struct Texture {
-	_tex: ID3D11Texture2D,
+	// This is shared so that hot reloading can replace the texture in place
+	data: Rc<RefCell<TextureData>>,
+}
+
+struct TextureData {
+	tex: ID3D11Texture2D,
 	// This is in an array due to the call to PSSetShaderResources() in draw()
 	tex_view_arr: [Option<ID3D11ShaderResourceView>; 1],
 	size: (u32, u32),
+	tracked: TrackedResource,
 }
 
 impl Texture {
-	fn new(decoder: IWICBitmapDecoder, device: &ID3D11Device, wic_factory: &IWICImagingFactory) -> Result<Texture, String> {
+	fn new(image: &Image, device: &ID3D11Device, resources: &ResourceTracker) -> Result<Texture, String> {
 		unsafe {
-			let frame = decoder.GetFrame(0)
-				.map_err(winerr_map("Failed to get image frame"))?;
-
-			let pixel_format = frame.GetPixelFormat()
-				.map_err(winerr_map("Failed to get pixel format"))?;
-
-			let mut width = 0u32;
-			let mut height = 0u32;
-			let row_pitch;
-			let buf_size;
-			let mut buf: Vec<u8>;
-
-			// Check if conversion is needed
-			if pixel_format != GUID_WICPixelFormat32bppBGRA {
-				// Conversion is needed
-
-				let format_converter = wic_factory.CreateFormatConverter()
-					.map_err(winerr_map("Failed to create WIC format converter"))?;
-
-				format_converter.Initialize(
-					&frame,
-					&GUID_WICPixelFormat32bppBGRA,
-					WICBitmapDitherTypeNone,
-					None,
-					0.0,
-					WICBitmapPaletteTypeCustom,
-				).map_err(winerr_map("Failed to initialise WIC format converter"))?;
-
-				format_converter.GetSize(&mut width, &mut height)
-					.map_err(winerr_map("Failed to get image size"))?;
-
-				let new_pixel_format = format_converter.GetPixelFormat()
-					.map_err(winerr_map("Failed to get pixel format"))?;
-				if new_pixel_format != GUID_WICPixelFormat32bppBGRA {
-					return Err("Failed to convert image format".to_string());
-				}
-
-				let compinfo = wic_factory.CreateComponentInfo(&new_pixel_format)
-					.map_err(winerr_map("Failed to get pixel format info"))?;
-
-				let pfi: IWICPixelFormatInfo = compinfo.cast()
-					.map_err(winerr_map("Failed to get pixel format info"))?;
-				let bpp = pfi.GetBitsPerPixel()
-					.map_err(winerr_map("Failed to get bits per pixel"))?;
-
-				row_pitch = ((width as usize * bpp as usize) + 7) / 8;
-				buf_size = row_pitch * height as usize;
-				buf = vec![0u8; buf_size];
-
-				format_converter.CopyPixels(
-					null(),
-					row_pitch as u32,
-					buf.as_mut_slice(),
-				).map_err(winerr_map("Failed to copy pixels"))?;
-			} else {
-				// Conversion is not needed
-
-				frame.GetSize(&mut width, &mut height)
-					.map_err(winerr_map("Failed to get image size"))?;
-
-				let compinfo = wic_factory.CreateComponentInfo(&pixel_format)
-					.map_err(winerr_map("Failed to get pixel format info"))?;
-
-				let pfi: IWICPixelFormatInfo = compinfo.cast()
-					.map_err(winerr_map("Failed to get pixel format info"))?;
-				let bpp = pfi.GetBitsPerPixel()
-					.map_err(winerr_map("Failed to get bits per pixel"))?;
-
-				row_pitch = ((width as usize * bpp as usize) + 7) / 8;
-				buf_size = row_pitch * height as usize;
-				buf = vec![0u8; buf_size];
-
-				frame.CopyPixels(
-					null(),
-					row_pitch as u32,
-					buf.as_mut_slice()
-				).map_err(winerr_map("Failed to copy pixels"))?;
-			}
+			let (width, height) = image.get_size();
+			let row_pitch = width as usize * 4;
+			let buf = image.pixels();
 
 			let tex_desc = D3D11_TEXTURE2D_DESC {
 				Width: width,
 				Height: height,
 				ArraySize: 1,
-				Format: DXGI_FORMAT_B8G8R8A8_UNORM,
+				Format: DXGI_FORMAT_R8G8B8A8_UNORM,
 				SampleDesc: DXGI_SAMPLE_DESC { Count: 1, Quality: 0 },
 				Usage: D3D11_USAGE_DEFAULT,
 				BindFlags: D3D11_BIND_SHADER_RESOURCE.0 as u32,
@@ -648,7 +967,7 @@ impl Texture {
 			let tex_sd = D3D11_SUBRESOURCE_DATA {
 				pSysMem: buf.as_ptr() as *const std::ffi::c_void,
 				SysMemPitch: row_pitch as u32,
-				SysMemSlicePitch: buf_size as u32,
+				SysMemSlicePitch: buf.len() as u32,
 			};
 
 			let mut tex = None;
@@ -656,7 +975,7 @@ impl Texture {
 				.map_err(winerr_map("Failed to create texture"))?;
 
 			let srv_desc = D3D11_SHADER_RESOURCE_VIEW_DESC {
-				Format: DXGI_FORMAT_B8G8R8A8_UNORM,
+				Format: DXGI_FORMAT_R8G8B8A8_UNORM,
 				ViewDimension: D3D11_SRV_DIMENSION_TEXTURE2D,
 				Anonymous: D3D11_SHADER_RESOURCE_VIEW_DESC_0 {
 					Texture2D: D3D11_TEX2D_SRV {
@@ -673,22 +992,309 @@ impl Texture {
 			).map_err(winerr_map("Failed to create texture view {} {}"))?;
 
 			Ok(Texture {
-				_tex: tex.ok_or("Texture was not created")?,
-				tex_view_arr: [Some(tex_view.ok_or("Texture view was not created")?)],
-				size: (width, height),
+				data: Rc::new(RefCell::new(TextureData {
+					tex: tex.ok_or("Texture was not created")?,
+					tex_view_arr: [Some(tex_view.ok_or("Texture view was not created")?)],
+					size: (width, height),
+					tracked: resources.track(ResourceKind::Texture, texture_memory(width, height, false)),
+				})),
 			})
 		}
 	}
 
+	/// Replaces the contents of target with this texture, so that everything that uses the
+	/// target texture gets this texture instead.
+	fn replace(self, target: &RefCell<TextureData>) {
+		std::mem::swap(&mut *target.borrow_mut(), &mut *self.data.borrow_mut());
+	}
+
+	/// Replaces the contents of this texture with another texture.
+	pub(crate) fn set(&self, other: Texture) {
+		other.replace(&self.data);
+	}
+
+	/// Another handle to the same texture, which sees it being replaced as well.
+	pub(crate) fn share(&self) -> Texture {
+		Texture {
+			data: self.data.clone(),
+		}
+	}
+
+	/// Textures keep their contents on Windows, so this is always false. See the WebGL
+	/// version.
+	pub(crate) fn take_contents_lost(&self) -> bool {
+		false
+	}
+
 	pub fn get_size(&self) -> (u32, u32) {
-		self.size
+		self.data.borrow().size
 	}
 
 	pub fn get_width(&self) -> u32 {
-		self.size.0
+		self.data.borrow().size.0
 	}
 
 	pub fn get_height(&self) -> u32 {
-		self.size.1
+		self.data.borrow().size.1
+	}
+
+	/// The estimated memory used by the texture in bytes.
+	pub fn get_memory(&self) -> usize {
+		self.data.borrow().tracked.memory()
+	}
+}
+
+impl Asset for Texture {
+	fn memory(&self) -> usize {
+		self.get_memory()
+	}
+}
+
+pub struct TextureHandler {
+	textures: HashMap<String, Texture>,
+	origins: Vec<TextureOrigin>,
+	hot_reload: Option<HotReload>,
+}
+
+/// Where a texture was loaded from. This is kept for textures that have been
+/// taken from the handler as well, so that they can also be hot reloaded.
+struct TextureOrigin {
+	filename: String,
+	modified: Option<SystemTime>,
+	texture: Weak<RefCell<TextureData>>,
+}
+
+struct HotReload {
+	factory: TextureFactory,
+	last_check: Option<Instant>,
+}
+
+/// How often reload_changed() looks at the files.
+const HOT_RELOAD_INTERVAL: Duration = Duration::from_millis(500);
+
+impl TextureHandler {
+	/// Create a new texture handler. Names is a list of the images that should be loaded,
+	/// and name transform specifies how to change the names before they are passed to
+	/// the function loading the texture (e.g. you might add a prefix for the folder where
+	/// the images are located). When using get(), the version of the name that should be used
+	/// is the one that has not been passed through the transform, so if the tranform adds
+	/// ".png" to the name you should not include ".png" when calling get().
+	pub fn new(dvr: &Dvr, names: &[&str], name_transform: impl Fn(&str) -> String) -> Result<TextureHandler, String> {
+		let mut textures: Vec<(String, String, Texture)> = Vec::new();
+		for name in names {
+			let filename = name_transform(name);
+			let texture = dvr.load_texture(&filename)?;
+			textures.push((name.to_string(), filename, texture));
+		}
+		Ok(TextureHandler::from_textures(textures))
+	}
+
+	/// Creates a texture handler from a list of names, file names and textures.
+	pub(crate) fn from_textures(textures: Vec<(String, String, Texture)>) -> TextureHandler {
+		let origins = textures
+			.iter()
+			.map(|(_, filename, texture)| TextureOrigin {
+				filename: filename.clone(),
+				modified: modified_time(filename),
+				texture: Rc::downgrade(&texture.data),
+			})
+			.collect();
+		TextureHandler {
+			textures: textures
+				.into_iter()
+				.map(|(name, _, texture)| (name, texture))
+				.collect(),
+			origins,
+			hot_reload: None,
+		}
+	}
+
+	/// Create a texture handler containing all textures in an asset bundle (see Bundle::load()).
+	/// The textures have the same names as in the bundle.
+	pub fn from_bundle(dvr: &Dvr, bundle: &Bundle) -> Result<TextureHandler, String> {
+		let mut textures: HashMap<String, Texture> = HashMap::new();
+		for name in bundle.names_of_kind(AssetKind::Texture) {
+			let texture = dvr.load_texture_raw(&bundle.get(name)?)
+				.map_err(|e| format!("Failed to load texture \"{}\" ({})", name, e))?;
+			textures.insert(name.to_string(), texture);
+		}
+		Ok(TextureHandler {
+			textures,
+			origins: Vec::new(),
+			hot_reload: None,
+		})
+	}
+
+	/// Turns on hot reloading, which is meant to be used during development.
+	/// After this has been called, reload() and reload_changed() can be used.
+	///
+	/// Only the textures of the handler are reloaded. A Font or FontSheet that was created
+	/// from them draws the new pixels, but font files (BMFont descriptors and TrueType
+	/// fonts) are not reloaded, so glyph positions and metrics only change when the Font
+	/// is created again. Fonts that load their own pages (e.g. Font::load_bmfont()) are
+	/// not reloaded at all.
+	pub fn enable_hot_reload(&mut self, dvr: &Dvr) {
+		self.hot_reload = Some(HotReload {
+			factory: dvr.texture_factory(),
+			last_check: None,
+		});
+	}
+
+	/// Loads all textures that were loaded from files again and replaces them in place,
+	/// so that all users of the textures (including FontSheets) get the new versions.
+	/// This includes textures that have been taken from the handler. Textures from
+	/// bundles are not reloaded. Requires enable_hot_reload() to have been called.
+	pub fn reload(&mut self) -> Result<(), String> {
+		self.reload_where(|_| true)?;
+		Ok(())
+	}
+
+	/// ### WINDOWS ONLY
+	/// Like reload(), but only reloads the textures whose files have been modified since
+	/// they were loaded. The files are checked at most twice per second, so this can be
+	/// called every frame (e.g. from State::logic()). Returns whether any texture was
+	/// reloaded. If a file fails to load (e.g. because it is still being written), the
+	/// old texture is kept and an error is returned.
+	pub fn reload_changed(&mut self) -> Result<bool, String> {
+		let hot_reload = self.hot_reload.as_mut().ok_or("Hot reloading is not enabled")?;
+		if hot_reload.last_check.is_some_and(|last_check| last_check.elapsed() < HOT_RELOAD_INTERVAL) {
+			return Ok(false);
+		}
+		hot_reload.last_check = Some(Instant::now());
+		self.reload_where(|origin| modified_time(&origin.filename) != origin.modified)
+	}
+
+	fn reload_where(&mut self, predicate: impl Fn(&TextureOrigin) -> bool) -> Result<bool, String> {
+		let factory = &self.hot_reload.as_ref().ok_or("Hot reloading is not enabled")?.factory;
+		self.origins.retain(|origin| origin.texture.strong_count() > 0);
+		let mut reloaded = false;
+		for origin in self.origins.iter_mut().filter(|origin| predicate(origin)) {
+			// Recorded first so that a broken file is not retried until it changes again
+			origin.modified = modified_time(&origin.filename);
+			let texture = factory.load_texture(&origin.filename)
+				.map_err(|e| format!("Failed to reload \"{}\" ({})", origin.filename, e))?;
+			if let Some(target) = origin.texture.upgrade() {
+				texture.replace(&target);
+				reloaded = true;
+			}
+		}
+		Ok(reloaded)
+	}
+
+	/// Returns a texture if it exists. See also getr() for a version that returns a Result.
+	pub fn get(&self, name: &str) -> Option<&Texture> {
+		self.textures.get(name)
+	}
+
+	/// Like get(), but it returns a string error if the texture is not found.
+	pub fn getr(&self, name: &str) -> Result<&Texture, String> {
+		self.get(name).ok_or(format!("Texture \"{}\" was not found", name))
+	}
+
+	/// Returns a texture if it exists, removing it from the handler.
+	/// See also taker() for a version that returns a Result.
+	pub fn take(&mut self, name: &str) -> Option<Texture> {
+		self.textures.remove(name)
+	}
+
+	/// Like take(), but it returns a string error if the texture is not found.
+	pub fn taker(&mut self, name: &str) -> Result<Texture, String> {
+		self.take(name).ok_or(format!("Texture \"{}\" was not found", name))
+	}
+}
+
+/// The contents of a blob, e.g. compiled shader code.
+unsafe fn blob_bytes(blob: &ID3DBlob) -> &[u8] {
+	std::slice::from_raw_parts(blob.GetBufferPointer() as *const u8, blob.GetBufferSize())
+}
+
+fn modified_time(filename: &str) -> Option<SystemTime> {
+	std::fs::metadata(filename).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// Decodes the first frame of an image using WIC. This is used for the formats
+/// that the built-in decoder does not support.
+fn decode_wic_image(decoder: IWICBitmapDecoder, wic_factory: &IWICImagingFactory) -> Result<Image, String> {
+	unsafe {
+		let frame = decoder.GetFrame(0)
+			.map_err(winerr_map("Failed to get image frame"))?;
+
+		let pixel_format = frame.GetPixelFormat()
+			.map_err(winerr_map("Failed to get pixel format"))?;
+
+		let mut width = 0u32;
+		let mut height = 0u32;
+		let row_pitch;
+		let buf_size;
+		let mut buf: Vec<u8>;
+
+		// Check if conversion is needed
+		if pixel_format != GUID_WICPixelFormat32bppRGBA {
+			// Conversion is needed
+
+			let format_converter = wic_factory.CreateFormatConverter()
+				.map_err(winerr_map("Failed to create WIC format converter"))?;
+
+			format_converter.Initialize(
+				&frame,
+				&GUID_WICPixelFormat32bppRGBA,
+				WICBitmapDitherTypeNone,
+				None,
+				0.0,
+				WICBitmapPaletteTypeCustom,
+			).map_err(winerr_map("Failed to initialise WIC format converter"))?;
+
+			format_converter.GetSize(&mut width, &mut height)
+				.map_err(winerr_map("Failed to get image size"))?;
+
+			let new_pixel_format = format_converter.GetPixelFormat()
+				.map_err(winerr_map("Failed to get pixel format"))?;
+			if new_pixel_format != GUID_WICPixelFormat32bppRGBA {
+				return Err("Failed to convert image format".to_string());
+			}
+
+			let compinfo = wic_factory.CreateComponentInfo(&new_pixel_format)
+				.map_err(winerr_map("Failed to get pixel format info"))?;
+
+			let pfi: IWICPixelFormatInfo = compinfo.cast()
+				.map_err(winerr_map("Failed to get pixel format info"))?;
+			let bpp = pfi.GetBitsPerPixel()
+				.map_err(winerr_map("Failed to get bits per pixel"))?;
+
+			row_pitch = ((width as usize * bpp as usize) + 7) / 8;
+			buf_size = row_pitch * height as usize;
+			buf = vec![0u8; buf_size];
+
+			format_converter.CopyPixels(
+				null(),
+				row_pitch as u32,
+				buf.as_mut_slice(),
+			).map_err(winerr_map("Failed to copy pixels"))?;
+		} else {
+			// Conversion is not needed
+
+			frame.GetSize(&mut width, &mut height)
+				.map_err(winerr_map("Failed to get image size"))?;
+
+			let compinfo = wic_factory.CreateComponentInfo(&pixel_format)
+				.map_err(winerr_map("Failed to get pixel format info"))?;
+
+			let pfi: IWICPixelFormatInfo = compinfo.cast()
+				.map_err(winerr_map("Failed to get pixel format info"))?;
+			let bpp = pfi.GetBitsPerPixel()
+				.map_err(winerr_map("Failed to get bits per pixel"))?;
+
+			row_pitch = ((width as usize * bpp as usize) + 7) / 8;
+			buf_size = row_pitch * height as usize;
+			buf = vec![0u8; buf_size];
+
+			frame.CopyPixels(
+				null(),
+				row_pitch as u32,
+				buf.as_mut_slice()
+			).map_err(winerr_map("Failed to copy pixels"))?;
+		}
+
+		Image::new(width, height, buf)
 	}
 }
@@ -1,210 +1,3 @@
-pub static VERTEX_SHADER_DATA: [u8; 1220] = [
-     68,  88,  66,  67,  32, 228, 
-    217, 167,   8,  78, 245, 245, 
-    210, 217, 153,  99,  97, 159, 
-     18,  54,   1,   0,   0,   0, 
-    196,   4,   0,   0,   6,   0, 
-      0,   0,  56,   0,   0,   0, 
-     84,   1,   0,   0, 196,   2, 
-      0,   0,  64,   3,   0,   0, 
-     24,   4,   0,   0, 108,   4, 
-      0,   0,  65, 111, 110,  57, 
-     20,   1,   0,   0,  20,   1, 
-      0,   0,   0,   2, 254, 255, 
-    224,   0,   0,   0,  52,   0, 
-      0,   0,   1,   0,  36,   0, 
-      0,   0,  48,   0,   0,   0, 
-     48,   0,   0,   0,  36,   0, 
-      1,   0,  48,   0,   0,   0, 
-      0,   0,   6,   0,   1,   0, 
-      0,   0,   0,   0,   0,   0, 
-      0,   0,   1,   2, 254, 255, 
-     81,   0,   0,   5,   7,   0, 
-     15, 160,   0,   0, 128,  63, 
-      0,   0,   0,   0,   0,   0, 
-      0,   0,   0,   0,   0,   0, 
-     31,   0,   0,   2,   5,   0, 
-      0, 128,   0,   0,  15, 144, 
-     31,   0,   0,   2,   5,   0, 
-      1, 128,   1,   0,  15, 144, 
-      4,   0,   0,   4,   0,   0, 
-      7, 128,   1,   0, 196, 144, 
-      7,   0, 208, 160,   7,   0, 
-    197, 160,   8,   0,   0,   3, 
-      0,   0,   1, 224,   0,   0, 
-    228, 128,   5,   0, 244, 160, 
-      8,   0,   0,   3,   0,   0, 
-      2, 224,   0,   0, 228, 128, 
-      6,   0, 244, 160,   4,   0, 
-      0,   4,   0,   0,   7, 128, 
-      0,   0, 196, 144,   7,   0, 
-    208, 160,   7,   0, 197, 160, 
-      8,   0,   0,   3,   0,   0, 
-      4, 192,   0,   0, 228, 128, 
-      3,   0, 244, 160,   8,   0, 
-      0,   3,   1,   0,   1, 128, 
-      0,   0, 228, 128,   1,   0, 
-    244, 160,   8,   0,   0,   3, 
-      1,   0,   2, 128,   0,   0, 
-    228, 128,   2,   0, 244, 160, 
-      8,   0,   0,   3,   0,   0, 
-      1, 128,   0,   0, 228, 128, 
-      4,   0, 244, 160,   4,   0, 
-      0,   4,   0,   0,   3, 192, 
-      0,   0,   0, 128,   0,   0, 
-    228, 160,   1,   0, 228, 128, 
-      1,   0,   0,   2,   0,   0, 
-      8, 192,   0,   0,   0, 128, 
-    255, 255,   0,   0,  83,  72, 
-     68,  82, 104,   1,   0,   0, 
-     64,   0,   1,   0,  90,   0, 
-      0,   0,  89,   0,   0,   4, 
-     70, 142,  32,   0,   0,   0, 
-      0,   0,   6,   0,   0,   0, 
-     95,   0,   0,   3,  50,  16, 
-     16,   0,   0,   0,   0,   0, 
-     95,   0,   0,   3,  50,  16, 
-     16,   0,   1,   0,   0,   0, 
-    101,   0,   0,   3,  50,  32, 
-     16,   0,   0,   0,   0,   0, 
-    103,   0,   0,   4, 242,  32, 
-     16,   0,   1,   0,   0,   0, 
-      1,   0,   0,   0, 104,   0, 
-      0,   2,   1,   0,   0,   0, 
-     54,   0,   0,   5,  50,   0, 
-     16,   0,   0,   0,   0,   0, 
-     70,  16,  16,   0,   1,   0, 
-      0,   0,  54,   0,   0,   5, 
-     66,   0,  16,   0,   0,   0, 
-      0,   0,   1,  64,   0,   0, 
-      0,   0, 128,  63,  16,   0, 
-      0,   8,  18,  32,  16,   0, 
-      0,   0,   0,   0,  70,   2, 
-     16,   0,   0,   0,   0,   0, 
-     70, 131,  32,   0,   0,   0, 
-      0,   0,   4,   0,   0,   0, 
-     16,   0,   0,   8,  34,  32, 
-     16,   0,   0,   0,   0,   0, 
-     70,   2,  16,   0,   0,   0, 
-      0,   0,  70, 131,  32,   0, 
-      0,   0,   0,   0,   5,   0, 
-      0,   0,  54,   0,   0,   5, 
-     50,   0,  16,   0,   0,   0, 
-      0,   0,  70,  16,  16,   0, 
-      0,   0,   0,   0,  54,   0, 
-      0,   5,  66,   0,  16,   0, 
-      0,   0,   0,   0,   1,  64, 
-      0,   0,   0,   0, 128,  63, 
-     16,   0,   0,   8,  18,  32, 
-     16,   0,   1,   0,   0,   0, 
-     70,   2,  16,   0,   0,   0, 
-      0,   0,  70, 131,  32,   0, 
-      0,   0,   0,   0,   0,   0, 
-      0,   0,  16,   0,   0,   8, 
-     34,  32,  16,   0,   1,   0, 
-      0,   0,  70,   2,  16,   0, 
-      0,   0,   0,   0,  70, 131, 
-     32,   0,   0,   0,   0,   0, 
-      1,   0,   0,   0,  16,   0, 
-      0,   8,  66,  32,  16,   0, 
-      1,   0,   0,   0,  70,   2, 
-     16,   0,   0,   0,   0,   0, 
-     70, 131,  32,   0,   0,   0, 
-      0,   0,   2,   0,   0,   0, 
-     16,   0,   0,   8, 130,  32, 
-     16,   0,   1,   0,   0,   0, 
-     70,   2,  16,   0,   0,   0, 
-      0,   0,  70, 131,  32,   0, 
-      0,   0,   0,   0,   3,   0, 
-      0,   0,  62,   0,   0,   1, 
-     83,  84,  65,  84, 116,   0, 
-      0,   0,  11,   0,   0,   0, 
-      1,   0,   0,   0,   0,   0, 
-      0,   0,   4,   0,   0,   0, 
-      6,   0,   0,   0,   0,   0, 
-      0,   0,   0,   0,   0,   0, 
-      1,   0,   0,   0,   0,   0, 
-      0,   0,   0,   0,   0,   0, 
-      0,   0,   0,   0,   0,   0, 
-      0,   0,   0,   0,   0,   0, 
-      0,   0,   0,   0,   0,   0, 
-      0,   0,   0,   0,   0,   0, 
-      0,   0,   0,   0,   0,   0, 
-      0,   0,   0,   0,   0,   0, 
-      4,   0,   0,   0,   0,   0, 
-      0,   0,   0,   0,   0,   0, 
-      0,   0,   0,   0,   0,   0, 
-      0,   0,   0,   0,   0,   0, 
-      0,   0,   0,   0,   0,   0, 
-      0,   0,   0,   0,   0,   0, 
-      0,   0,   0,   0,  82,  68, 
-     69,  70, 208,   0,   0,   0, 
-      1,   0,   0,   0,  68,   0, 
-      0,   0,   1,   0,   0,   0, 
-     28,   0,   0,   0,   0,   4, 
-    254, 255,   0,   1,   0,   0, 
-    167,   0,   0,   0,  60,   0, 
-      0,   0,   0,   0,   0,   0, 
-      0,   0,   0,   0,   0,   0, 
-      0,   0,   0,   0,   0,   0, 
-      0,   0,   0,   0,   1,   0, 
-      0,   0,   0,   0,   0,   0, 
-     67,  66, 117, 102,   0, 171, 
-    171, 171,  60,   0,   0,   0, 
-      2,   0,   0,   0,  92,   0, 
-      0,   0, 128,   0,   0,   0, 
-      0,   0,   0,   0,   0,   0, 
-      0,   0, 140,   0,   0,   0, 
-      0,   0,   0,   0,  64,   0, 
-      0,   0,   2,   0,   0,   0, 
-    144,   0,   0,   0,   0,   0, 
-      0,   0, 160,   0,   0,   0, 
-     64,   0,   0,   0,  64,   0, 
-      0,   0,   2,   0,   0,   0, 
-    144,   0,   0,   0,   0,   0, 
-      0,   0, 109, 116, 120,   0, 
-      3,   0,   3,   0,   4,   0, 
-      4,   0,   0,   0,   0,   0, 
-      0,   0,   0,   0, 116, 101, 
-    120, 109, 116, 120,   0,  77, 
-    105,  99, 114, 111, 115, 111, 
-    102, 116,  32,  40,  82,  41, 
-     32,  72,  76,  83,  76,  32, 
-     83, 104,  97, 100, 101, 114, 
-     32,  67, 111, 109, 112, 105, 
-    108, 101, 114,  32,  49,  48, 
-     46,  49,   0, 171,  73,  83, 
-     71,  78,  76,   0,   0,   0, 
-      2,   0,   0,   0,   8,   0, 
-      0,   0,  56,   0,   0,   0, 
-      0,   0,   0,   0,   0,   0, 
-      0,   0,   3,   0,   0,   0, 
-      0,   0,   0,   0,   3,   3, 
-      0,   0,  65,   0,   0,   0, 
-      0,   0,   0,   0,   0,   0, 
-      0,   0,   3,   0,   0,   0, 
-      1,   0,   0,   0,   3,   3, 
-      0,   0,  80, 111, 115, 105, 
-    116, 105, 111, 110,   0,  84, 
-    101, 120,  67, 111, 111, 114, 
-    100,   0, 171, 171,  79,  83, 
-     71,  78,  80,   0,   0,   0, 
-      2,   0,   0,   0,   8,   0, 
-      0,   0,  56,   0,   0,   0, 
-      0,   0,   0,   0,   0,   0, 
-      0,   0,   3,   0,   0,   0, 
-      0,   0,   0,   0,   3,  12, 
-      0,   0,  65,   0,   0,   0, 
-      0,   0,   0,   0,   1,   0, 
-      0,   0,   3,   0,   0,   0, 
-      1,   0,   0,   0,  15,   0, 
-      0,   0,  84, 101, 120,  67, 
-    111, 111, 114, 100,   0,  83, 
-     86,  95,  80, 111, 115, 105, 
-    116, 105, 111, 110,   0, 171, 
-    171, 171
-];
-
 pub static PIXEL_SHADER_DATA: [u8; 704] = [
      68,  88,  66,  67, 238,  26, 
     157, 166, 236, 106, 157, 192, 
@@ -325,3 +118,148 @@ pub static PIXEL_SHADER_DATA: [u8; 704] = [
       0,   0,   0,   0,   0,   0, 
       0,   0
 ];
+
+// The vertex shader is compiled when the swap chain is created. The pixel shader above
+// only reads the texture coordinates, which come first.
+pub static VERTEX_SHADER_SOURCE: &str = r#"
+cbuffer CBuf : register(b0) {
+	matrix mtx;
+	matrix texmtx;
+};
+
+struct VsOut {
+	float2 tc : TexCoord;
+	float4 pos : SV_Position;
+	// The part of the texture that the effects sample (min and max)
+	float4 texRect : TexRect;
+};
+
+VsOut main(float2 pos : Position, float2 tc : TexCoord, float4 texRect : TexRect) {
+	VsOut output;
+	output.tc = mul(float4(tc, 0.0, 1.0), texmtx).xy;
+	output.pos = mul(float4(pos, 0.0, 1.0), mtx);
+	float2 rectMin = mul(float4(texRect.xy, 0.0, 1.0), texmtx).xy;
+	float2 rectMax = mul(float4(texRect.zw, 0.0, 1.0), texmtx).xy;
+	output.texRect = float4(min(rectMin, rectMax), max(rectMin, rectMax));
+	return output;
+}
+"#;
+
+// The effects pixel shader is compiled when the swap chain is created. It gets the
+// colour from the same constant buffer as the pixel shader above, with premultiplied alpha.
+pub static EFFECTS_PIXEL_SHADER_SOURCE: &str = r#"
+Texture2D tex : register(t0);
+SamplerState smp : register(s0);
+
+cbuffer CBuf : register(b0) {
+	float4 colour;
+};
+
+// The widths are in units of the field for distance fields and in texels otherwise
+cbuffer EffectBuf : register(b1) {
+	float4 outlineColour;
+	float4 shadowColour;
+	float4 glowColour;
+	// Everything outside this part of the texture (min and max) and the rectangle from the
+	// vertex shader is treated as empty
+	float4 texRect;
+	float2 shadowOffset;
+	float2 texelSize;
+	float pxRange;
+	float outlineWidth;
+	float shadowBlur;
+	float glowWidth;
+	float multiChannel;
+	// 1 draws effects around the alpha of the texture and 2 around a distance field
+	int mode;
+	// 0 draws everything, 1 only the effects (also behind the fill) and 2 only the fill
+	int effectPass;
+};
+
+// The part of texRect that is in the rectangle from the vertex shader, which is set in main()
+static float4 clampRect;
+
+float4 sampleRect(float2 tc) {
+	if (any(tc < clampRect.xy) || any(tc > clampRect.zw)) {
+		return float4(0.0, 0.0, 0.0, 0.0);
+	}
+	return tex.SampleLevel(smp, tc, 0);
+}
+
+// The signed distance to the edge, in units of the field
+float fieldDistance(float2 tc) {
+	float4 s = sampleRect(tc);
+	float median = max(min(s.r, s.g), min(max(s.r, s.g), s.b));
+	return lerp(min(s.r, s.a), median, multiChannel) - 0.5;
+}
+
+// The alpha of the texture around tc, where the offsets are multiplied by radius
+float maxAround(float2 tc, float radius) {
+	float alpha = 0.0;
+	for (int i = 0; i < 12; i++) {
+		float angle = 0.5235988 * i;
+		alpha = max(alpha, sampleRect(tc + float2(cos(angle), sin(angle)) * radius * texelSize).a);
+	}
+	return alpha;
+}
+
+float meanAround(float2 tc, float radius) {
+	float alpha = 0.0;
+	for (int i = 0; i < 12; i++) {
+		float angle = 0.5235988 * i;
+		alpha += sampleRect(tc + float2(cos(angle), sin(angle)) * radius * texelSize).a;
+	}
+	return alpha / 12.0;
+}
+
+float4 main(float2 tc : TexCoord, float4 pos : SV_Position, float4 vertexRect : TexRect) : SV_Target {
+	clampRect = float4(max(texRect.xy, vertexRect.xy), min(texRect.zw, vertexRect.zw));
+	float4 fillColour;
+	float fill, outline, glow, shadow;
+	if (mode == 2) {
+		float d = fieldDistance(tc);
+		fill = saturate(d * pxRange + 0.5);
+		fillColour = colour * fill;
+		outline = saturate((d + outlineWidth) * pxRange + 0.5);
+		glow = glowWidth > 0.0 ? 1.0 - smoothstep(0.0, glowWidth, -(d + outlineWidth)) : 0.0;
+		float edge = shadowBlur + 0.5 / pxRange;
+		shadow = smoothstep(-edge, edge, fieldDistance(tc - shadowOffset) + outlineWidth);
+	} else {
+		// The textures have straight alpha
+		float4 s = sampleRect(tc);
+		fillColour = float4(s.rgb * s.a, s.a) * colour;
+		fill = s.a;
+		outline = fill;
+		if (outlineWidth > 0.0) {
+			outline = max(outline, max(maxAround(tc, outlineWidth), maxAround(tc, 0.5 * outlineWidth)));
+		}
+		glow = 0.0;
+		if (glowWidth > 0.0) {
+			float inner = meanAround(tc, outlineWidth + 0.5 * glowWidth);
+			float outer = meanAround(tc, outlineWidth + glowWidth);
+			glow = min(inner + outer, 1.0);
+		}
+		float2 shadowTc = tc - shadowOffset;
+		shadow = sampleRect(shadowTc).a;
+		if (shadowBlur > 0.0) {
+			shadow = 0.2 * shadow + 0.8 * meanAround(shadowTc, shadowBlur);
+		}
+		if (outlineWidth > 0.0) {
+			shadow = max(shadow, maxAround(shadowTc, outlineWidth));
+		}
+	}
+	if (effectPass == 1) {
+		fillColour = float4(0.0, 0.0, 0.0, 0.0);
+		fill = 0.0;
+	} else if (effectPass == 2) {
+		outline = 0.0;
+		glow = 0.0;
+		shadow = 0.0;
+	}
+	float4 c = fillColour + outlineColour * outline * (1.0 - fill);
+	c += glowColour * glow * (1.0 - c.a);
+	c += shadowColour * shadow * (1.0 - c.a);
+	// The blend state expects straight alpha
+	return float4(c.rgb / max(c.a, 0.00001), c.a);
+}
+"#;
@@ -0,0 +1,105 @@
+#!/usr/bin/env python3
+"""Writes test_font.ttf, a tiny TrueType font for the tests in src/ttf.rs.
+
+The glyphs are rectangles with round numbers, so the metrics and kerning that the
+tests expect can be worked out by hand. 1000 units per em, ascent 800, descent 200.
+
+  glyph  char   box (x0, y0, x1, y1)   advance
+  0      none   -                      500
+  1      ' '    -                      250
+  2      'A'    100, 0, 500, 700       600
+  3      'V'    50, 0, 550, 700        600
+  4      'o'    100, 0, 400, 400       500
+
+Kerning: A V -80, V A -60, V o -40.
+"""
+import os
+import struct
+
+GLYPHS = [
+	(None, 500),
+	(None, 250),
+	((100, 0, 500, 700), 600),
+	((50, 0, 550, 700), 600),
+	((100, 0, 400, 400), 500),
+]
+CHARS = [(0x20, 1), (0x41, 2), (0x56, 3), (0x6F, 4)]
+KERNING = [(2, 3, -80), (3, 2, -60), (3, 4, -40)]
+
+
+def glyph_data(box):
+	if box is None:
+		return b""
+	x0, y0, x1, y1 = box
+	points = [(x0, y0), (x0, y1), (x1, y1), (x1, y0)]
+	data = struct.pack(">hhhhh", 1, x0, y0, x1, y1)
+	data += struct.pack(">HH", len(points) - 1, 0)
+	data += bytes([1] * len(points))
+	last = 0
+	for x, _ in points:
+		data += struct.pack(">h", x - last)
+		last = x
+	last = 0
+	for _, y in points:
+		data += struct.pack(">h", y - last)
+		last = y
+	return data + b"\0" * (len(data) % 2)
+
+
+def cmap():
+	segments = CHARS + [(0xFFFF, None)]
+	count = len(segments)
+	ends = b"".join(struct.pack(">H", c) for c, _ in segments)
+	starts = b"".join(struct.pack(">H", c) for c, _ in segments)
+	# The last segment maps 0xFFFF to glyph 0, and the deltas wrap around at 16 bits
+	deltas = b"".join(struct.pack(">h", 1 if g is None else (g - c + 0x8000) % 0x10000 - 0x8000) for c, g in segments)
+	offsets = b"\0\0" * count
+	search = 2 ** (count.bit_length() - 1)
+	sub = struct.pack(">HHHH", count * 2, search * 2, search.bit_length() - 1, count * 2 - search * 2)
+	sub += ends + b"\0\0" + starts + deltas + offsets
+	sub = struct.pack(">HHH", 4, 6 + len(sub), 0) + sub
+	return struct.pack(">HHHHI", 0, 1, 3, 1, 12) + sub
+
+
+def kern():
+	pairs = sorted(KERNING)
+	n = len(pairs)
+	search = 2 ** (n.bit_length() - 1)
+	data = struct.pack(">HHHH", n, search * 6, search.bit_length() - 1, n * 6 - search * 6)
+	data += b"".join(struct.pack(">HHh", *pair) for pair in pairs)
+	return struct.pack(">HH", 0, 1) + struct.pack(">HHH", 0, 6 + len(data), 0x0001) + data
+
+
+def main():
+	glyf = b""
+	loca = []
+	for box, _ in GLYPHS:
+		loca.append(len(glyf) // 2)
+		glyf += glyph_data(box)
+	loca.append(len(glyf) // 2)
+	tables = {
+		b"head": struct.pack(">IIIIHHqqhhhhHHhhh", 0x00010000, 0x00010000, 0, 0x5F0F3CF5, 0, 1000, 0, 0, 0, 0, 550, 700, 0, 8, 2, 0, 0),
+		b"hhea": struct.pack(">IhhhHhhhhhhhhhhhH", 0x00010000, 800, -200, 0, 600, 0, 0, 550, 1, 0, 0, 0, 0, 0, 0, 0, len(GLYPHS)),
+		b"maxp": struct.pack(">IHHHHHHHHHHHHHH", 0x00010000, len(GLYPHS), 4, 1, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0),
+		b"hmtx": b"".join(struct.pack(">Hh", advance, box[0] if box else 0) for box, advance in GLYPHS),
+		b"cmap": cmap(),
+		b"loca": b"".join(struct.pack(">H", offset) for offset in loca),
+		b"glyf": glyf,
+		b"kern": kern(),
+	}
+	tags = sorted(tables)
+	search = 2 ** (len(tags).bit_length() - 1)
+	out = struct.pack(">IHHHH", 0x00010000, len(tags), search * 16, search.bit_length() - 1, len(tags) * 16 - search * 16)
+	offset = len(out) + 16 * len(tags)
+	body = b""
+	for tag in tags:
+		data = tables[tag]
+		checksum = sum(struct.unpack(">%dI" % ((len(data) + 3) // 4), data + b"\0" * (-len(data) % 4))) & 0xFFFFFFFF
+		out += struct.pack(">4sIII", tag, checksum, offset + len(body), len(data))
+		body += data + b"\0" * (-len(data) % 4)
+	path = os.path.join(os.path.dirname(os.path.abspath(__file__)), "test_font.ttf")
+	with open(path, "wb") as f:
+		f.write(out + body)
+
+
+main()